@@ -78,12 +78,13 @@ class TenTenOneApp extends StatefulWidget {
   State<TenTenOneApp> createState() => _TenTenOneState();
 }
 
-class _TenTenOneState extends State<TenTenOneApp> {
+class _TenTenOneState extends State<TenTenOneApp> with WidgetsBindingObserver {
   bool showOnboarding = false;
 
   @override
   void initState() {
     super.initState();
+    WidgetsBinding.instance.addObserver(this);
 
     TenTenOneSharedPreferences.instance.isFirstStartup().then((value) => setState(() {
           showOnboarding = value;
@@ -92,6 +93,31 @@ class _TenTenOneState extends State<TenTenOneApp> {
     init();
   }
 
+  @override
+  void dispose() {
+    WidgetsBinding.instance.removeObserver(this);
+    super.dispose();
+  }
+
+  @override
+  void didChangeAppLifecycleState(AppLifecycleState state) {
+    switch (state) {
+      case AppLifecycleState.paused:
+        // The OS may kill us in the background without further notice
+        api.persist().catchError((error) {
+          FLog.error(text: "Failed to persist node state", exception: error);
+        });
+        break;
+      case AppLifecycleState.detached:
+        api.shutdown().catchError((error) {
+          FLog.error(text: "Failed to shut down", exception: error);
+        });
+        break;
+      default:
+        break;
+    }
+  }
+
   @override
   Widget build(BuildContext context) {
     const mainColor = Colors.blue;
@@ -316,6 +342,8 @@ class _TenTenOneState extends State<TenTenOneApp> {
           walletChangeNotifier.update(event.field0);
         } else if (event is Event_ChannelState) {
           context.read<ChannelChangeNotifier>().update(event.field0);
+        } else if (event is Event_Channels) {
+          context.read<ChannelChangeNotifier>().updateChannels(event.field0);
         } else if (event is Event_Init) {
           startupChangeNotifier.set(event.field0);
         } else {
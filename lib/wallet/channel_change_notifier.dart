@@ -8,7 +8,9 @@ import 'package:ten_ten_one/wallet/action_card.dart';
 import 'package:ten_ten_one/wallet/open_channel.dart';
 
 class ChannelChangeNotifier extends ChangeNotifier {
+  /// The state across all channels
   ChannelState? state;
+  List<ChannelInfo> channels = [];
 
   bool isInitialising() => state == ChannelState.Establishing;
 
@@ -93,4 +95,9 @@ class ChannelChangeNotifier extends ChangeNotifier {
     this.state = state;
     super.notifyListeners();
   }
+
+  void updateChannels(List<ChannelInfo> channels) {
+    this.channels = channels;
+    super.notifyListeners();
+  }
 }
@@ -3,6 +3,7 @@ import 'package:flutter/gestures.dart';
 import 'package:flutter/material.dart';
 import 'package:flutter_rust_bridge/flutter_rust_bridge.dart';
 import 'package:go_router/go_router.dart';
+import 'package:ten_ten_one/bridge_generated/bridge_definitions.dart';
 import 'package:ten_ten_one/cfd_trading/validation_error.dart';
 import 'package:ten_ten_one/utilities/submit_button.dart';
 import 'package:url_launcher/url_launcher.dart';
@@ -90,12 +91,20 @@ class _DrainFaucetState extends State<DrainFaucet> {
                         child: SubmitButton(
                             onPressed: () async {
                               try {
-                                final txid = await api.callFaucet(address: address);
-                                FLog.debug(text: "Tx id: " + txid);
-                                ScaffoldMessenger.of(context).showSnackBar(const SnackBar(
-                                  content: Text("Success. Your funds will arrive shortly."),
-                                ));
-                                GoRouter.of(context).go('/');
+                                final result = await api.callFaucet(address: address);
+                                if (result is FaucetResult_Paid) {
+                                  FLog.debug(text: "Tx id: " + result.field0);
+                                  ScaffoldMessenger.of(context).showSnackBar(const SnackBar(
+                                    content: Text("Success. Your funds will arrive shortly."),
+                                  ));
+                                  GoRouter.of(context).go('/');
+                                } else if (result is FaucetResult_Refused) {
+                                  FLog.info(text: "Faucet refused to pay: " + result.field1);
+                                  ScaffoldMessenger.of(context).showSnackBar(SnackBar(
+                                    backgroundColor: Colors.red,
+                                    content: Text(_refusalMessage(result.field0, result.field1)),
+                                  ));
+                                }
                               } on FfiException catch (error) {
                                 FLog.error(
                                     text: "Failed to call faucet: Error: " + error.message,
@@ -114,3 +123,18 @@ class _DrainFaucetState extends State<DrainFaucet> {
     );
   }
 }
+
+String _refusalMessage(MakerError error, String message) {
+  switch (error) {
+    case MakerError.FaucetLimitExceeded:
+      return "You already used the faucet today, please try again tomorrow.";
+    case MakerError.FaucetUnavailable:
+      return "There is no faucet on this network.";
+    case MakerError.InsufficientFunds:
+      return "The faucet is empty, please use the public faucet instead.";
+    case MakerError.InvalidAddress:
+      return "The faucet can't pay to your address: " + message;
+    default:
+      return "Failed to call faucet: " + message;
+  }
+}
@@ -0,0 +1,97 @@
+//! The errors of the maker's API, as `application/problem+json` responses (RFC 7807).
+//!
+//! The `type` of every problem is a stable [`ErrorCode`], so clients can react to specific
+//! failures without parsing the human-readable `title` and `detail`.
+
+use serde::Deserialize;
+use serde::Serialize;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default, strum_macros::Display,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub enum ErrorCode {
+    InvalidAddress,
+    InvalidNodeId,
+    InsufficientFunds,
+    /// We don't have a quote yet.
+    QuoteUnavailable,
+    /// Our latest quote is too old to trade on.
+    QuoteStale,
+    ChannelNotFound,
+    /// We won't fund the channel, e.g. because the amount exceeds our limits.
+    ChannelFundingRefused,
+    TakerNotConnected,
+    /// The spread is out of range, only returned by the admin API.
+    InvalidSpread,
+    /// There is no faucet on mainnet.
+    FaucetUnavailable,
+    FaucetLimitExceeded,
+    PaymentFailed,
+    Unauthorized,
+    Forbidden,
+    Internal,
+    /// A code this client does not know yet, or a problem without a code.
+    #[default]
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Problem {
+    #[serde(rename = "type", default)]
+    pub code: ErrorCode,
+    pub status: Option<u16>,
+    pub title: Option<String>,
+    pub detail: Option<String>,
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match (&self.title, &self.detail) {
+            (Some(title), Some(detail)) => write!(f, "{title}: {detail}"),
+            (Some(reason), None) | (None, Some(reason)) => reason.fmt(f),
+            (None, None) => write!(f, "{}", self.code),
+        }
+    }
+}
+
+impl std::error::Error for Problem {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_code_from_type() {
+        let problem = serde_json::from_str::<Problem>(
+            r#"{"type":"faucet_limit_exceeded","status":429,"title":"Faucet limit exceeded"}"#,
+        )
+        .unwrap();
+
+        assert_eq!(problem.code, ErrorCode::FaucetLimitExceeded);
+        assert_eq!(problem.status, Some(429));
+    }
+
+    #[test]
+    fn unknown_or_missing_type_is_unknown() {
+        let unknown = serde_json::from_str::<Problem>(r#"{"type":"about:blank"}"#).unwrap();
+        let missing = serde_json::from_str::<Problem>(r#"{"status":500}"#).unwrap();
+
+        assert_eq!(unknown.code, ErrorCode::Unknown);
+        assert_eq!(missing.code, ErrorCode::Unknown);
+    }
+
+    #[test]
+    fn code_is_displayed_as_serialized() {
+        let serialized = serde_json::to_string(&ErrorCode::InsufficientFunds).unwrap();
+
+        assert_eq!(serialized, format!("\"{}\"", ErrorCode::InsufficientFunds));
+    }
+}
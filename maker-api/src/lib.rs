@@ -0,0 +1,232 @@
+//! Requests and responses of the maker's public HTTP API, shared by the maker and the taker.
+//!
+//! With the `openapi` feature, the types also describe themselves for the maker's OpenAPI
+//! document.
+
+pub mod error;
+
+use bitcoin::secp256k1::PublicKey;
+use bitcoin::Address;
+use bitcoin::Txid;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+/// The paths of the public routes, as they appear in the OpenAPI document.
+pub mod path {
+    pub const OFFER: &str = "/api/offer";
+    pub const OFFER_STREAM: &str = "/api/offer/stream";
+    pub const OPEN_CHANNEL: &str = "/api/channel/open";
+    pub const ALIVE: &str = "/api/alive";
+    pub const FAUCET: &str = "/api/faucet/{address}";
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Offer {
+    /// Bid of the smallest size tier.
+    #[serde(with = "rust_decimal::serde::float")]
+    #[cfg_attr(feature = "openapi", schema(value_type = f64))]
+    pub bid: Decimal,
+    /// Ask of the smallest size tier.
+    #[serde(with = "rust_decimal::serde::float")]
+    #[cfg_attr(feature = "openapi", schema(value_type = f64))]
+    pub ask: Decimal,
+    #[serde(with = "rust_decimal::serde::float")]
+    #[cfg_attr(feature = "openapi", schema(value_type = f64))]
+    pub index: Decimal,
+    /// Older makers don't quote size tiers.
+    #[serde(default)]
+    pub tiers: Vec<PriceTier>,
+}
+
+/// Prices for orders up to a certain size.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct PriceTier {
+    pub max_quantity: u64,
+    #[serde(with = "rust_decimal::serde::float")]
+    #[cfg_attr(feature = "openapi", schema(value_type = f64))]
+    pub bid: Decimal,
+    #[serde(with = "rust_decimal::serde::float")]
+    #[cfg_attr(feature = "openapi", schema(value_type = f64))]
+    pub ask: Decimal,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct OpenChannelRequest {
+    /// The taker address where the maker should send the funds to
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
+    pub address_to_fund: Address,
+
+    /// The amount that the taker expects for funding
+    ///
+    /// This represents the amount of the maker.
+    pub fund_amount: u64,
+
+    /// The node that is going to open the channel with the maker
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
+    pub taker_node_id: PublicKey,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct OpenChannelResponse {
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
+    pub funding_txid: Txid,
+}
+
+/// How to reach a Lightning node.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct PeerInfo {
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
+    pub pubkey: PublicKey,
+    #[cfg_attr(feature = "openapi", schema(value_type = String, example = "127.0.0.1:9045"))]
+    pub peer_addr: PeerAddr,
+}
+
+impl Display for PeerInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        format!("{}@{}", self.pubkey, self.peer_addr).fmt(f)
+    }
+}
+
+/// Where a Lightning node listens, as `<ip>:<port>` or `<onion>:<port>`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(try_from = "String", into = "String")]
+pub enum PeerAddr {
+    Ip(SocketAddr),
+    /// A Tor onion service, only reachable through a Tor proxy.
+    Onion {
+        host: String,
+        port: u16,
+    },
+}
+
+impl PeerAddr {
+    pub fn port(&self) -> u16 {
+        match self {
+            PeerAddr::Ip(addr) => addr.port(),
+            PeerAddr::Onion { port, .. } => *port,
+        }
+    }
+}
+
+impl From<SocketAddr> for PeerAddr {
+    fn from(addr: SocketAddr) -> Self {
+        PeerAddr::Ip(addr)
+    }
+}
+
+impl FromStr for PeerAddr {
+    type Err = ParsePeerAddrError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(addr) = s.parse::<SocketAddr>() {
+            return Ok(PeerAddr::Ip(addr));
+        }
+
+        let error = || ParsePeerAddrError(s.to_owned());
+        let (host, port) = s.rsplit_once(':').ok_or_else(error)?;
+        let port = port.parse().map_err(|_| error())?;
+
+        // Version 3 onion addresses are 56 base32 characters
+        let name = host.strip_suffix(".onion").ok_or_else(error)?;
+        let is_base32 = |c: char| c.is_ascii_lowercase() || ('2'..='7').contains(&c);
+        if name.len() != 56 || !name.chars().all(is_base32) {
+            return Err(error());
+        }
+
+        Ok(PeerAddr::Onion {
+            host: host.to_owned(),
+            port,
+        })
+    }
+}
+
+impl TryFrom<String> for PeerAddr {
+    type Error = ParsePeerAddrError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<PeerAddr> for String {
+    fn from(addr: PeerAddr) -> Self {
+        addr.to_string()
+    }
+}
+
+impl Display for PeerAddr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PeerAddr::Ip(addr) => addr.fmt(f),
+            PeerAddr::Onion { host, port } => write!(f, "{host}:{port}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsePeerAddrError(String);
+
+impl Display for ParsePeerAddrError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid address {}, expected <ip>:<port> or <onion>:<port>",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for ParsePeerAddrError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn offer_without_tiers_can_be_read() {
+        let offer =
+            serde_json::from_str::<Offer>(r#"{"bid":16000.5,"ask":16100.0,"index":16050.0}"#)
+                .unwrap();
+
+        assert_eq!(offer.bid, dec!(16000.5));
+        assert!(offer.tiers.is_empty());
+    }
+
+    #[test]
+    fn parses_peer_addresses() {
+        let onion = "vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd.onion:9735";
+
+        assert_eq!(
+            "127.0.0.1:9045".parse::<PeerAddr>().unwrap(),
+            PeerAddr::Ip("127.0.0.1:9045".parse().unwrap())
+        );
+        assert_eq!(onion.parse::<PeerAddr>().unwrap().to_string(), onion);
+        assert_eq!(onion.parse::<PeerAddr>().unwrap().port(), 9735);
+
+        assert!("example.com:9735".parse::<PeerAddr>().is_err());
+        assert!("tooshort.onion:9735".parse::<PeerAddr>().is_err());
+        assert!("127.0.0.1".parse::<PeerAddr>().is_err());
+    }
+
+    #[test]
+    fn peer_info_keeps_its_format() {
+        let json = r#"{"pubkey":"02cb6517193c466de0688b8b0386dbfb39d96c3844525c1315d44bd8e108c08bc1","peer_addr":"127.0.0.1:9045"}"#;
+
+        let peer_info = serde_json::from_str::<PeerInfo>(json).unwrap();
+
+        assert_eq!(peer_info.peer_addr.port(), 9045);
+        assert_eq!(serde_json::to_string(&peer_info).unwrap(), json);
+    }
+}
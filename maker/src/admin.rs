@@ -0,0 +1,419 @@
+//! Subcommands that operate a running maker through its admin API.
+
+use crate::cfd::MakerCfd;
+use crate::routes::ChannelDetail;
+use crate::routes::WalletDetails;
+use crate::spread::SpreadModel;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use bdk::bitcoin::Txid;
+use clap::Subcommand;
+use maker_api::error::Problem;
+use reqwest::Method;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Show the on-chain and Lightning balance.
+    Balance,
+    /// List our Lightning channels.
+    Channels,
+    /// Close the channel with a node.
+    CloseChannel {
+        /// The node ID of the counterparty.
+        node: String,
+        /// Force close the channel, e.g. if the counterparty is offline.
+        #[clap(long)]
+        force: bool,
+    },
+    /// Send bitcoin on-chain.
+    Send {
+        address: String,
+        /// Amount in sats.
+        amount: u64,
+    },
+    /// Show or change the spread model.
+    #[clap(subcommand)]
+    Spread(SpreadCommand),
+    /// Show our CFDs.
+    #[clap(subcommand)]
+    Cfds(CfdsCommand),
+    /// Export CFDs, funded channel opens, hedge fills, risk and spread as JSON.
+    Export {
+        /// Write the export to this file instead of stdout.
+        #[clap(long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum SpreadCommand {
+    Get,
+    /// Set the base spread.
+    Set {
+        /// Base spread multiplied by 1000, e.g. 15 for 1.5%.
+        base: i32,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum CfdsCommand {
+    List,
+}
+
+/// Talks to the admin API of a running maker.
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    token: Option<String>,
+}
+
+impl Client {
+    pub fn new(http_address: SocketAddr, token: Option<String>) -> Result<Self> {
+        let http = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()?;
+
+        Ok(Self {
+            http,
+            base_url: format!("http://{http_address}/api/admin"),
+            token,
+        })
+    }
+
+    /// Sends the request and parses the response as the type the route responds with, so that a
+    /// maker of another version fails loudly instead of leaving out what it answers differently.
+    async fn request<T: DeserializeOwned>(&self, method: Method, path: &str) -> Result<T> {
+        let body = self.send(method, path).await?;
+
+        serde_json::from_str(&body)
+            .with_context(|| format!("Unexpected response from maker to {path}: {body}"))
+    }
+
+    /// Sends the request and returns the response body.
+    async fn send(&self, method: Method, path: &str) -> Result<String> {
+        let url = format!("{}{path}", self.base_url);
+
+        let mut request = self.http.request(method, &url);
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach maker at {url}"))?;
+        let status = response.status();
+        let body = response.text().await?;
+
+        if !status.is_success() {
+            // Our routes fail with an RFC 7807 problem
+            let problem = serde_json::from_str::<Problem>(&body).ok();
+            let reason = problem
+                .as_ref()
+                .and_then(|problem| problem.detail.as_deref().or(problem.title.as_deref()))
+                .unwrap_or(&body);
+            let code = problem
+                .as_ref()
+                .map(|problem| problem.code)
+                .unwrap_or_default();
+            bail!("Maker responded with {status} ({code}): {reason}");
+        }
+
+        Ok(body)
+    }
+}
+
+/// Runs the command against the maker, printing tables or, if `json` is set, JSON.
+pub async fn run(command: Command, client: &Client, json: bool) -> Result<()> {
+    let (value, table) = match command {
+        Command::Balance => {
+            let details = client
+                .request::<WalletDetails>(Method::GET, "/wallet-details")
+                .await?;
+            let table = balance_table(&details);
+            (serde_json::to_value(details)?, table)
+        }
+        Command::Channels => {
+            let channels = client
+                .request::<Vec<ChannelDetail>>(Method::GET, "/channel/list")
+                .await?;
+            let table = channels_table(&channels);
+            (serde_json::to_value(channels)?, table)
+        }
+        Command::CloseChannel { node, force } => {
+            let path = format!("/channel/close/{node}?force={force}");
+            client.send(Method::POST, &path).await?;
+            let table = Table::new(["Closed channel with"]).row([node.clone()]);
+            (serde_json::json!({ "closed": node }), table)
+        }
+        Command::Send { address, amount } => {
+            let path = format!("/send/{address}/{amount}");
+            // Responds with the txid as plain text
+            let body = client.send(Method::POST, &path).await?;
+            let txid = body
+                .parse::<Txid>()
+                .with_context(|| format!("Unexpected response from maker to {path}: {body}"))?;
+            let table = Table::new(["Txid"]).row([txid]);
+            (serde_json::json!({ "txid": txid }), table)
+        }
+        Command::Spread(SpreadCommand::Get) => {
+            let model = client
+                .request::<SpreadModel>(Method::GET, "/spread")
+                .await?;
+            let table = spread_table(&model);
+            (serde_json::to_value(model)?, table)
+        }
+        Command::Spread(SpreadCommand::Set { base }) => {
+            client.send(Method::PUT, &format!("/spread/{base}")).await?;
+            let model = client
+                .request::<SpreadModel>(Method::GET, "/spread")
+                .await?;
+            let table = spread_table(&model);
+            (serde_json::to_value(model)?, table)
+        }
+        Command::Cfds(CfdsCommand::List) => {
+            let cfds = client
+                .request::<Vec<MakerCfd>>(Method::GET, "/cfds")
+                .await?;
+            let table = cfds_table(&cfds);
+            (serde_json::to_value(cfds)?, table)
+        }
+        Command::Export { output } => {
+            // Passed on as is, for accounting
+            let export = client.request::<Value>(Method::GET, "/export").await?;
+            let export = serde_json::to_string_pretty(&export)?;
+
+            match output {
+                Some(path) => {
+                    std::fs::write(&path, export)
+                        .with_context(|| format!("Failed to write {}", path.display()))?;
+                    eprintln!("Exported to {}", path.display());
+                }
+                None => println!("{export}"),
+            }
+
+            return Ok(());
+        }
+    };
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&value)?);
+    } else {
+        print!("{table}");
+    }
+
+    Ok(())
+}
+
+fn balance_table(details: &WalletDetails) -> Table {
+    let balance = &details.balance;
+
+    Table::new(["Balance", "Sats"])
+        .row([
+            "On-chain confirmed".to_owned(),
+            balance.on_chain.confirmed.to_string(),
+        ])
+        .row([
+            "On-chain trusted pending".to_owned(),
+            balance.on_chain.trusted_pending.to_string(),
+        ])
+        .row([
+            "On-chain untrusted pending".to_owned(),
+            balance.on_chain.untrusted_pending.to_string(),
+        ])
+        .row([
+            "Lightning available".to_owned(),
+            balance.off_chain.available.to_string(),
+        ])
+        .row([
+            "Lightning pending close".to_owned(),
+            balance.off_chain.pending_close.to_string(),
+        ])
+        .row(["Address".to_owned(), details.address.to_string()])
+        .row(["Node ID".to_owned(), details.node_id.to_string()])
+}
+
+fn channels_table(channels: &[ChannelDetail]) -> Table {
+    let mut table = Table::new([
+        "Channel ID",
+        "Counterparty",
+        "Capacity (sats)",
+        "Balance (msats)",
+        "Usable",
+    ]);
+
+    for channel in channels {
+        table = table.row([
+            channel.channel_id.clone(),
+            channel.counterparty.clone(),
+            channel.channel_value_satoshis.to_string(),
+            channel.balance_msat.to_string(),
+            channel.is_usable.to_string(),
+        ]);
+    }
+
+    table
+}
+
+fn spread_table(model: &SpreadModel) -> Table {
+    let mut table = Table::new(["Spread", "Value"])
+        .row(["Base".to_owned(), model.base.to_string()])
+        .row(["Per leverage".to_owned(), model.leverage.to_string()])
+        .row([
+            "Per 1000 contracts exposure".to_owned(),
+            model.exposure.to_string(),
+        ])
+        .row(["Volatility factor".to_owned(), model.volatility.to_string()])
+        .row(["Max".to_owned(), model.max.to_string()]);
+
+    for tier in &model.tiers {
+        table = table.row([
+            format!("Up to {} contracts", tier.max_quantity),
+            tier.spread.to_string(),
+        ]);
+    }
+
+    table
+}
+
+fn cfds_table(cfds: &[MakerCfd]) -> Table {
+    let mut table = Table::new([
+        "Custom output ID",
+        "Taker",
+        "Position",
+        "Quantity",
+        "Leverage",
+        "Open price",
+        "State",
+    ]);
+
+    for cfd in cfds {
+        table = table.row([
+            cfd.custom_output_id.clone(),
+            cfd.taker.to_string(),
+            format!("{:?}", cfd.position),
+            cfd.quantity.to_string(),
+            cfd.leverage.to_string(),
+            cfd.open_price.to_string(),
+            format!("{:?}", cfd.state),
+        ]);
+    }
+
+    table
+}
+
+/// A plain text table with aligned columns.
+struct Table {
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    fn new<const N: usize>(headers: [&str; N]) -> Self {
+        Self {
+            rows: vec![headers.iter().map(|header| header.to_string()).collect()],
+        }
+    }
+
+    fn row<const N: usize>(mut self, cells: [impl ToString; N]) -> Self {
+        self.rows
+            .push(cells.iter().map(|cell| cell.to_string()).collect());
+        self
+    }
+}
+
+impl std::fmt::Display for Table {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let columns = self.rows.iter().map(Vec::len).max().unwrap_or_default();
+        let widths = (0..columns)
+            .map(|column| {
+                self.rows
+                    .iter()
+                    .filter_map(|row| row.get(column))
+                    .map(|cell| cell.chars().count())
+                    .max()
+                    .unwrap_or_default()
+            })
+            .collect::<Vec<_>>();
+
+        for (i, row) in self.rows.iter().enumerate() {
+            let line = row
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{cell:width$}"))
+                .collect::<Vec<_>>()
+                .join("  ");
+            writeln!(f, "{}", line.trim_end())?;
+
+            if i == 0 {
+                let separator = widths
+                    .iter()
+                    .map(|width| "-".repeat(*width))
+                    .collect::<Vec<_>>()
+                    .join("  ");
+                writeln!(f, "{separator}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bdk::bitcoin::secp256k1::PublicKey;
+    use bdk::bitcoin::secp256k1::Secp256k1;
+    use bdk::bitcoin::secp256k1::SecretKey;
+    use ten_ten_one::cfd::models::CfdState;
+    use ten_ten_one::cfd::models::ContractSymbol;
+    use ten_ten_one::cfd::models::Order;
+    use ten_ten_one::cfd::models::Position;
+
+    fn taker() -> PublicKey {
+        let secret = SecretKey::from_slice(&[1; 32]).unwrap();
+        PublicKey::from_secret_key(&Secp256k1::new(), &secret)
+    }
+
+    #[test]
+    fn aligns_columns() {
+        let table = Table::new(["Name", "Sats"])
+            .row(["confirmed", "1000"])
+            .row(["pending", "5"]);
+
+        assert_eq!(
+            table.to_string(),
+            "Name       Sats\n---------  ----\nconfirmed  1000\npending    5\n"
+        );
+    }
+
+    #[test]
+    fn cfds_table_has_a_row_per_cfd() {
+        let order = Order {
+            leverage: 2,
+            quantity: 100,
+            contract_symbol: ContractSymbol::BtcUsd,
+            position: Position::Long,
+            open_price: 16000.0,
+        };
+        let cfds = [
+            MakerCfd::new("a".to_owned(), taker(), &order),
+            MakerCfd {
+                state: CfdState::Closed,
+                ..MakerCfd::new("b".to_owned(), taker(), &order)
+            },
+        ];
+
+        let table = cfds_table(&cfds);
+
+        assert_eq!(table.rows.len(), 3);
+        assert_eq!(table.rows[2][6], "Closed");
+    }
+}
@@ -0,0 +1,275 @@
+//! Bearer token authentication for the admin API.
+
+use crate::problem::problem;
+use anyhow::bail;
+use anyhow::Context;
+use http_api_problem::HttpApiProblem;
+use maker_api::error::ErrorCode;
+use rocket::http::Status;
+use rocket::request::FromRequest;
+use rocket::request::Outcome;
+use rocket::Request;
+use serde::Serialize;
+use serde::Serializer;
+use std::fmt;
+use std::marker::PhantomData;
+use std::str::FromStr;
+
+/// What a token allows its bearer to do on the admin API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum_macros::EnumString, strum_macros::Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum Permission {
+    /// Look at balances, channels, the spread model and our risk.
+    Read,
+    /// Send on-chain and Lightning payments.
+    Payments,
+    /// Close channels.
+    Channels,
+    /// Change the spread model.
+    Spread,
+}
+
+const ALL_PERMISSIONS: [Permission; 4] = [
+    Permission::Read,
+    Permission::Payments,
+    Permission::Channels,
+    Permission::Spread,
+];
+
+/// Stands in for the token wherever we print it.
+const REDACTED: &str = "<redacted>";
+
+/// A bearer token and the permissions it grants.
+///
+/// Parsed from `<token>` for all permissions or `<token>:<permission>,...`, e.g.
+/// `s3cr3t:read,spread`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct AdminToken {
+    token: String,
+    permissions: Vec<Permission>,
+}
+
+impl AdminToken {
+    fn matches(&self, token: &str) -> bool {
+        // Compare in constant time, so the response time does not leak the token
+        self.token.len() == token.len()
+            && self
+                .token
+                .bytes()
+                .zip(token.bytes())
+                .fold(0, |acc, (a, b)| acc | (a ^ b))
+                == 0
+    }
+
+    fn allows(&self, permission: Permission) -> bool {
+        self.permissions.contains(&permission)
+    }
+}
+
+impl FromStr for AdminToken {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (token, permissions) = match s.split_once(':') {
+            Some((token, permissions)) => {
+                let permissions = permissions
+                    .split(',')
+                    .map(|permission| {
+                        permission
+                            .trim()
+                            .parse()
+                            .with_context(|| format!("Unknown permission {permission}"))
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                (token, permissions)
+            }
+            None => (s, ALL_PERMISSIONS.to_vec()),
+        };
+
+        if token.is_empty() {
+            bail!("Admin token must not be empty");
+        }
+        // Otherwise feeding a printed config back in would let anyone in with the placeholder
+        if token == REDACTED {
+            bail!("Admin token must not be the placeholder of a printed config");
+        }
+
+        Ok(Self {
+            token: token.to_owned(),
+            permissions,
+        })
+    }
+}
+
+impl fmt::Debug for AdminToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AdminToken")
+            .field("token", &REDACTED)
+            .field("permissions", &self.permissions)
+            .finish()
+    }
+}
+
+impl Serialize for AdminToken {
+    /// Redacts the token, so printing the config does not leak it.
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.permissions == ALL_PERMISSIONS {
+            return serializer.serialize_str(REDACTED);
+        }
+
+        let permissions = self
+            .permissions
+            .iter()
+            .map(Permission::to_string)
+            .collect::<Vec<_>>()
+            .join(",");
+        serializer.serialize_str(&format!("{REDACTED}:{permissions}"))
+    }
+}
+
+/// The tokens that grant access to the admin API, managed by Rocket.
+#[derive(Debug, Clone, Default)]
+pub struct AdminTokens(pub Vec<AdminToken>);
+
+/// Marks the permission an admin route requires.
+pub trait RequiredPermission: Send + Sync {
+    const PERMISSION: Permission;
+}
+
+pub struct Read;
+pub struct Payments;
+pub struct Channels;
+pub struct Spread;
+
+impl RequiredPermission for Read {
+    const PERMISSION: Permission = Permission::Read;
+}
+
+impl RequiredPermission for Payments {
+    const PERMISSION: Permission = Permission::Payments;
+}
+
+impl RequiredPermission for Channels {
+    const PERMISSION: Permission = Permission::Channels;
+}
+
+impl RequiredPermission for Spread {
+    const PERMISSION: Permission = Permission::Spread;
+}
+
+/// Request guard for admin routes, only succeeds if the bearer token grants permission `P`.
+pub struct Admin<P>(PhantomData<P>);
+
+#[rocket::async_trait]
+impl<'r, P: RequiredPermission> FromRequest<'r> for Admin<P> {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let token = match request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Bearer "))
+        {
+            Some(token) => token,
+            None => return Outcome::Failure((Status::Unauthorized, ())),
+        };
+
+        let tokens = match request.rocket().state::<AdminTokens>() {
+            Some(tokens) => tokens,
+            None => return Outcome::Failure((Status::Unauthorized, ())),
+        };
+
+        match tokens.0.iter().find(|candidate| candidate.matches(token)) {
+            Some(admin) if admin.allows(P::PERMISSION) => Outcome::Success(Admin(PhantomData)),
+            Some(_) => {
+                tracing::warn!(permission = %P::PERMISSION, path = %request.uri(), "Admin token lacks permission");
+                Outcome::Failure((Status::Forbidden, ()))
+            }
+            None => Outcome::Failure((Status::Unauthorized, ())),
+        }
+    }
+}
+
+#[rocket::catch(401)]
+pub fn unauthorized() -> HttpApiProblem {
+    problem(ErrorCode::Unauthorized)
+        .title("Unauthorized")
+        .detail("Admin routes require a valid bearer token")
+}
+
+#[rocket::catch(403)]
+pub fn forbidden() -> HttpApiProblem {
+    problem(ErrorCode::Forbidden)
+        .title("Forbidden")
+        .detail("The bearer token does not grant access to this route")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_without_permissions_grants_all() {
+        let token = "s3cr3t".parse::<AdminToken>().unwrap();
+
+        assert!(token.matches("s3cr3t"));
+        assert!(ALL_PERMISSIONS
+            .iter()
+            .all(|permission| token.allows(*permission)));
+    }
+
+    #[test]
+    fn token_with_permissions() {
+        let token = "s3cr3t:read,spread".parse::<AdminToken>().unwrap();
+
+        assert!(token.allows(Permission::Read));
+        assert!(token.allows(Permission::Spread));
+        assert!(!token.allows(Permission::Payments));
+        assert!(!token.allows(Permission::Channels));
+    }
+
+    #[test]
+    fn rejects_unknown_permission() {
+        assert!("s3cr3t:everything".parse::<AdminToken>().is_err());
+    }
+
+    #[test]
+    fn rejects_empty_token() {
+        assert!("".parse::<AdminToken>().is_err());
+        assert!(":read".parse::<AdminToken>().is_err());
+    }
+
+    #[test]
+    fn rejects_redacted_placeholder() {
+        assert!("<redacted>".parse::<AdminToken>().is_err());
+        assert!("<redacted>:read".parse::<AdminToken>().is_err());
+    }
+
+    #[test]
+    fn only_matches_exact_token() {
+        let token = "s3cr3t".parse::<AdminToken>().unwrap();
+
+        assert!(!token.matches("s3cr3"));
+        assert!(!token.matches("s3cr3t!"));
+        assert!(!token.matches("S3CR3T"));
+    }
+
+    #[test]
+    fn debug_output_hides_token() {
+        let token = "s3cr3t".parse::<AdminToken>().unwrap();
+
+        assert!(!format!("{token:?}").contains("s3cr3t"));
+    }
+
+    #[test]
+    fn serialisation_hides_token() {
+        let full = "s3cr3t".parse::<AdminToken>().unwrap();
+        let restricted = "s3cr3t:read,spread".parse::<AdminToken>().unwrap();
+
+        assert_eq!(serde_json::to_string(&full).unwrap(), r#""<redacted>""#);
+        assert_eq!(
+            serde_json::to_string(&restricted).unwrap(),
+            r#""<redacted>:read,spread""#
+        );
+    }
+}
@@ -1,5 +1,7 @@
 use anyhow::Result;
 use futures::TryStreamExt;
+use rocket::serde::Deserialize;
+use rocket::serde::Serialize;
 use rust_decimal::Decimal;
 use time::OffsetDateTime;
 use tokio::sync::watch;
@@ -7,14 +9,24 @@ use tokio::task::JoinHandle;
 
 pub const QUOTE_INTERVAL_MINUTES: i64 = 1;
 
-pub fn subscribe() -> Result<(JoinHandle<()>, watch::Receiver<Option<Quote>>)> {
+/// The BitMEX environment we take quotes from.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Network {
+    Mainnet,
+    Testnet,
+}
+
+pub fn subscribe(network: Network) -> Result<(JoinHandle<()>, watch::Receiver<Option<Quote>>)> {
     let (quote_sender, quote_receiver) = watch::channel(None);
 
+    let network = match network {
+        Network::Mainnet => bitmex_stream::Network::Mainnet,
+        Network::Testnet => bitmex_stream::Network::Testnet,
+    };
+
     let handle = tokio::spawn(async move {
-        let mut stream = bitmex_stream::subscribe(
-            ["instrument:XBTUSD".to_string()],
-            bitmex_stream::Network::Testnet,
-        );
+        let mut stream = bitmex_stream::subscribe(["instrument:XBTUSD".to_string()], network);
 
         // We keep track of the latest quote because not every quote
         // update references every field. TODO: Manage each field as a
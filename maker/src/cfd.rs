@@ -0,0 +1,132 @@
+//! Our side of the CFDs we entered with takers.
+
+use anyhow::bail;
+use anyhow::Result;
+use bdk::bitcoin::secp256k1::PublicKey;
+use rocket::serde::Deserialize;
+use rocket::serde::Serialize;
+use sqlx::Row;
+use ten_ten_one::cfd::models::CfdState;
+use ten_ten_one::cfd::models::Order;
+use ten_ten_one::cfd::models::Position;
+use ten_ten_one::db;
+use time::OffsetDateTime;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MakerCfd {
+    pub custom_output_id: String,
+    pub taker: PublicKey,
+    /// Our position, i.e. the opposite of the taker's.
+    pub position: Position,
+    pub leverage: i64,
+    /// Number of contracts, each worth one USD.
+    pub quantity: u64,
+    pub open_price: f64,
+    pub state: CfdState,
+    pub created: i64,
+    pub updated: i64,
+}
+
+impl MakerCfd {
+    /// Takes the other side of the taker's order.
+    pub fn new(custom_output_id: String, taker: PublicKey, order: &Order) -> Self {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+
+        Self {
+            custom_output_id,
+            taker,
+            position: counter_position(order.position),
+            leverage: order.leverage,
+            quantity: order.quantity.unsigned_abs(),
+            open_price: order.open_price,
+            state: CfdState::Open,
+            created: now,
+            updated: now,
+        }
+    }
+}
+
+/// The position we take when a taker enters the given position.
+pub fn counter_position(position: Position) -> Position {
+    match position {
+        Position::Long => Position::Short,
+        Position::Short => Position::Long,
+    }
+}
+
+pub async fn insert(cfd: &MakerCfd) -> Result<()> {
+    let mut connection = db::acquire().await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO maker_cfd (custom_output_id, taker, position, leverage, quantity, open_price, state_id, created, updated)
+        VALUES ($1, $2, $3, $4, $5, $6, 1, $7, $8)
+        "#,
+    )
+    .bind(&cfd.custom_output_id)
+    .bind(cfd.taker.to_string())
+    .bind(cfd.position)
+    .bind(cfd.leverage)
+    .bind(cfd.quantity as i64)
+    .bind(cfd.open_price)
+    .bind(cfd.created)
+    .bind(cfd.updated)
+    .execute(&mut connection)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn mark_closed(custom_output_id: &str) -> Result<()> {
+    let mut connection = db::acquire().await?;
+    let updated = OffsetDateTime::now_utc().unix_timestamp();
+
+    let query_result = sqlx::query(
+        r#"
+        UPDATE maker_cfd
+        SET state_id = 2, updated = $1
+        WHERE custom_output_id = $2
+        "#,
+    )
+    .bind(updated)
+    .bind(custom_output_id)
+    .execute(&mut connection)
+    .await?;
+
+    if query_result.rows_affected() != 1 {
+        bail!("Failed to mark CFD as closed in DB. Custom output ID: {custom_output_id}");
+    }
+
+    Ok(())
+}
+
+pub async fn load_all() -> Result<Vec<MakerCfd>> {
+    let mut connection = db::acquire().await?;
+
+    let rows = sqlx::query(
+        r#"
+        SELECT custom_output_id, taker, position, leverage, quantity, open_price, cfd_state.state AS state, created, updated
+        FROM maker_cfd
+        INNER JOIN cfd_state ON maker_cfd.state_id = cfd_state.id
+        ORDER BY maker_cfd.id
+        "#,
+    )
+    .fetch_all(&mut connection)
+    .await?;
+
+    rows.into_iter()
+        .map(|row| {
+            Ok(MakerCfd {
+                custom_output_id: row.try_get("custom_output_id")?,
+                taker: row.try_get::<String, _>("taker")?.parse()?,
+                position: row.try_get("position")?,
+                leverage: row.try_get("leverage")?,
+                quantity: row.try_get::<i64, _>("quantity")?.unsigned_abs(),
+                open_price: row.try_get("open_price")?,
+                state: row.try_get("state")?,
+                created: row.try_get("created")?,
+                updated: row.try_get("updated")?,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,503 @@
+//! Funding the maker's side of channels that takers open with us.
+//!
+//! We send our part of the channel to the taker, who then opens the channel. To make sure the
+//! taker keeps its promise, we record every funded open and blacklist takers whose channel does
+//! not show up in time. As anyone can make up new node IDs, we also bound the funds in pending
+//! opens across all takers.
+
+use crate::metrics;
+use crate::metrics::PaymentKind;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use bdk::bitcoin::secp256k1::PublicKey;
+use bdk::bitcoin::Txid;
+use maker_api::OpenChannelRequest;
+use maker_api::OpenChannelResponse;
+use rocket::serde::Deserialize;
+use rocket::serde::Serialize;
+use sqlx::Row;
+use std::time::Duration;
+use ten_ten_one::db;
+use ten_ten_one::wallet;
+use time::OffsetDateTime;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+/// We add a few sats on top to cover the fees of the channel funding transaction.
+const FUNDING_FEE_SATS: u64 = 500;
+
+/// How long the funds of an expired open still count against our pending limit. Expiring an open
+/// does not get the funds back, so takers with fresh node IDs must not be able to drain us by
+/// letting their opens expire.
+const EXPIRED_FUNDING_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How often we check whether takers opened the channels we funded.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct Settings {
+    /// Minimum amount in sats we fund per channel.
+    pub min_fund_amount: u64,
+
+    /// Maximum amount in sats we fund per channel.
+    pub max_fund_amount: u64,
+
+    /// Minutes a taker has to open the channel we funded before we blacklist it.
+    pub open_timeout_minutes: u64,
+
+    /// Maximum amount in sats across all opens whose channel has not shown up yet, including those
+    /// that expired within the last day.
+    pub max_pending_amount: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            min_fund_amount: 20_000,
+            max_fund_amount: 1_000_000,
+            open_timeout_minutes: 60,
+            max_pending_amount: 5_000_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, sqlx::Type)]
+pub enum OpenState {
+    /// We funded the taker, but the channel has not shown up yet.
+    Pending,
+    Opened,
+    /// The taker did not open the channel in time.
+    Expired,
+}
+
+/// A channel open we funded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChannelOpen {
+    pub funding_txid: Txid,
+    pub taker: PublicKey,
+    pub amount: u64,
+    pub state: OpenState,
+    /// The channel the taker opened with our funds, in hex.
+    pub channel_id: Option<String>,
+    pub created: i64,
+}
+
+/// A channel as far as matching it with a funded open is concerned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Channel {
+    channel_id: String,
+    counterparty: PublicKey,
+    value_sats: u64,
+}
+
+pub struct ChannelFunder {
+    settings: Settings,
+    /// Serialises funding, so a taker can't get two opens funded concurrently.
+    lock: Mutex<()>,
+}
+
+impl ChannelFunder {
+    pub fn new(settings: Settings) -> Self {
+        Self {
+            settings,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Sends the maker's part of the channel to the taker, so the taker can open the channel.
+    pub async fn fund(&self, request: &OpenChannelRequest) -> Result<OpenChannelResponse> {
+        check_amount(&self.settings, request.fund_amount)?;
+
+        let _guard = self.lock.lock().await;
+
+        let opens = load_opens(None).await?;
+        let taker_opens = opens
+            .iter()
+            .filter(|open| open.taker == request.taker_node_id)
+            .cloned()
+            .collect::<Vec<_>>();
+        check_history(&taker_opens)?;
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        check_pending(&self.settings, &opens, request.fund_amount, now)?;
+
+        // Otherwise we could not tell the new channel apart from the existing one
+        let has_channel = wallet::get_channel_manager()
+            .list_channels()
+            .iter()
+            .any(|channel| channel.counterparty.node_id == request.taker_node_id);
+        if has_channel {
+            bail!("We already have a channel with {}", request.taker_node_id);
+        }
+
+        let funding_tx = wallet::build_transaction(
+            request.address_to_fund.clone(),
+            request.fund_amount + FUNDING_FEE_SATS,
+        )
+        .context("Failed to build funding transaction")?;
+        let funding_txid = funding_tx.txid();
+
+        // Recorded before we broadcast, so that every open we fund expires if the channel doesn't
+        // show up
+        let open = ChannelOpen {
+            funding_txid,
+            taker: request.taker_node_id,
+            amount: request.fund_amount,
+            state: OpenState::Pending,
+            channel_id: None,
+            created: now,
+        };
+        insert_open(&open, &request.address_to_fund.to_string())
+            .await
+            .context("Failed to record funded channel open")?;
+
+        // The open stays pending if broadcasting fails, as we can't tell whether the transaction
+        // reached the network regardless
+        let result = wallet::broadcast(&funding_tx);
+        metrics::record_payment(PaymentKind::ChannelFunding, &result);
+        result.context("Failed to transfer funds")?;
+
+        Ok(OpenChannelResponse { funding_txid })
+    }
+
+    /// Matches the opens we funded with the channels takers opened and expires the ones that
+    /// timed out.
+    pub fn watch_pending_opens(&self) -> JoinHandle<()> {
+        let timeout = time::Duration::minutes(self.settings.open_timeout_minutes as i64);
+
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = update_pending_opens(timeout).await {
+                    tracing::error!("Failed to update pending channel opens: {e:#}");
+                }
+
+                tokio::time::sleep(CHECK_INTERVAL).await;
+            }
+        })
+    }
+}
+
+fn check_amount(settings: &Settings, fund_amount: u64) -> Result<()> {
+    if fund_amount < settings.min_fund_amount {
+        bail!(
+            "We fund channels with at least {} sats",
+            settings.min_fund_amount
+        );
+    }
+
+    if fund_amount > settings.max_fund_amount {
+        bail!(
+            "We fund channels with at most {} sats",
+            settings.max_fund_amount
+        );
+    }
+
+    Ok(())
+}
+
+/// Checks the taker's previous opens, so that it can't collect funds without opening channels.
+fn check_history(opens: &[ChannelOpen]) -> Result<()> {
+    if opens.iter().any(|open| open.state == OpenState::Expired) {
+        bail!("Not funding channels for takers that did not open a channel they asked us to fund");
+    }
+
+    if opens.iter().any(|open| open.state == OpenState::Pending) {
+        bail!("Open the channel we already funded first");
+    }
+
+    Ok(())
+}
+
+/// Checks that the open keeps the funds in pending and recently expired opens across all takers
+/// within our limit.
+fn check_pending(
+    settings: &Settings,
+    opens: &[ChannelOpen],
+    fund_amount: u64,
+    now: i64,
+) -> Result<()> {
+    let window = EXPIRED_FUNDING_WINDOW.as_secs() as i64;
+    let pending_amount = opens
+        .iter()
+        .filter(|open| match open.state {
+            OpenState::Pending => true,
+            OpenState::Expired => open.created + window > now,
+            OpenState::Opened => false,
+        })
+        .map(|open| open.amount)
+        .sum::<u64>();
+
+    if pending_amount + fund_amount > settings.max_pending_amount {
+        bail!("We are funding too many channels at the moment, try again later");
+    }
+
+    Ok(())
+}
+
+async fn update_pending_opens(timeout: time::Duration) -> Result<()> {
+    let opens = load_opens(None).await?;
+
+    let mut claimed = opens
+        .iter()
+        .filter_map(|open| open.channel_id.clone())
+        .collect::<Vec<_>>();
+
+    let channels = wallet::get_channel_manager()
+        .list_channels()
+        .into_iter()
+        .map(|channel| Channel {
+            channel_id: hex::encode(channel.channel_id),
+            counterparty: channel.counterparty.node_id,
+            value_sats: channel.channel_value_satoshis,
+        })
+        .collect::<Vec<_>>();
+
+    let now = OffsetDateTime::now_utc();
+
+    for open in opens.iter().filter(|open| open.state == OpenState::Pending) {
+        if let Some(channel) = match_channel(open, &channels, &claimed) {
+            tracing::info!(funding_txid = %open.funding_txid, taker = %open.taker, channel_id = channel.channel_id, "Taker opened the channel we funded");
+            mark_opened(open.funding_txid, &channel.channel_id).await?;
+            claimed.push(channel.channel_id.clone());
+        } else if open.created + timeout.whole_seconds() <= now.unix_timestamp() {
+            tracing::warn!(funding_txid = %open.funding_txid, taker = %open.taker, "Taker did not open the channel we funded, blacklisting it");
+            mark_expired(open.funding_txid).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Finds a channel with the taker that our funding could have gone into and that no other open
+/// has been matched with yet.
+fn match_channel<'a>(
+    open: &ChannelOpen,
+    channels: &'a [Channel],
+    claimed: &[String],
+) -> Option<&'a Channel> {
+    channels.iter().find(|channel| {
+        channel.counterparty == open.taker
+            && channel.value_sats >= open.amount
+            && !claimed.contains(&channel.channel_id)
+    })
+}
+
+async fn insert_open(open: &ChannelOpen, address: &str) -> Result<()> {
+    let mut connection = db::acquire().await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO channel_open (funding_txid, taker, address, amount, state, created, updated)
+        VALUES ($1, $2, $3, $4, $5, $6, $6)
+        "#,
+    )
+    .bind(open.funding_txid.to_string())
+    .bind(open.taker.to_string())
+    .bind(address)
+    .bind(open.amount as i64)
+    .bind(open.state)
+    .bind(open.created)
+    .execute(&mut connection)
+    .await?;
+
+    Ok(())
+}
+
+/// Loads the opens we funded, only those of the given taker if set.
+pub async fn load_opens(taker: Option<PublicKey>) -> Result<Vec<ChannelOpen>> {
+    let mut connection = db::acquire().await?;
+
+    let rows = sqlx::query(
+        r#"
+        SELECT funding_txid, taker, amount, state, channel_id, created
+        FROM channel_open
+        WHERE $1 IS NULL OR taker = $1
+        ORDER BY created
+        "#,
+    )
+    .bind(taker.map(|taker| taker.to_string()))
+    .fetch_all(&mut connection)
+    .await?;
+
+    rows.into_iter()
+        .map(|row| {
+            Ok(ChannelOpen {
+                funding_txid: row.try_get::<String, _>("funding_txid")?.parse()?,
+                taker: row.try_get::<String, _>("taker")?.parse()?,
+                amount: row.try_get::<i64, _>("amount")? as u64,
+                state: row.try_get("state")?,
+                channel_id: row.try_get("channel_id")?,
+                created: row.try_get("created")?,
+            })
+        })
+        .collect()
+}
+
+async fn mark_opened(funding_txid: Txid, channel_id: &str) -> Result<()> {
+    let mut connection = db::acquire().await?;
+
+    sqlx::query(
+        r#"
+        UPDATE channel_open
+        SET state = $1, channel_id = $2, updated = $3
+        WHERE funding_txid = $4
+        "#,
+    )
+    .bind(OpenState::Opened)
+    .bind(channel_id)
+    .bind(OffsetDateTime::now_utc().unix_timestamp())
+    .bind(funding_txid.to_string())
+    .execute(&mut connection)
+    .await?;
+
+    Ok(())
+}
+
+async fn mark_expired(funding_txid: Txid) -> Result<()> {
+    let mut connection = db::acquire().await?;
+
+    sqlx::query(
+        r#"
+        UPDATE channel_open
+        SET state = $1, updated = $2
+        WHERE funding_txid = $3
+        "#,
+    )
+    .bind(OpenState::Expired)
+    .bind(OffsetDateTime::now_utc().unix_timestamp())
+    .bind(funding_txid.to_string())
+    .execute(&mut connection)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bdk::bitcoin::hashes::Hash;
+    use bdk::bitcoin::secp256k1::Secp256k1;
+    use bdk::bitcoin::secp256k1::SecretKey;
+
+    fn taker(byte: u8) -> PublicKey {
+        let secret = SecretKey::from_slice(&[byte; 32]).unwrap();
+        PublicKey::from_secret_key(&Secp256k1::new(), &secret)
+    }
+
+    fn open(taker: PublicKey, state: OpenState) -> ChannelOpen {
+        ChannelOpen {
+            funding_txid: Txid::all_zeros(),
+            taker,
+            amount: 100_000,
+            state,
+            channel_id: None,
+            created: 0,
+        }
+    }
+
+    fn channel(id: &str, counterparty: PublicKey, value_sats: u64) -> Channel {
+        Channel {
+            channel_id: id.to_owned(),
+            counterparty,
+            value_sats,
+        }
+    }
+
+    fn settings() -> Settings {
+        Settings {
+            min_fund_amount: 20_000,
+            max_fund_amount: 1_000_000,
+            open_timeout_minutes: 60,
+            max_pending_amount: 250_000,
+        }
+    }
+
+    #[test]
+    fn bounds_fund_amount() {
+        assert!(check_amount(&settings(), 19_999).is_err());
+        assert!(check_amount(&settings(), 20_000).is_ok());
+        assert!(check_amount(&settings(), 1_000_000).is_ok());
+        assert!(check_amount(&settings(), 1_000_001).is_err());
+    }
+
+    #[test]
+    fn funds_takers_that_opened_their_channels() {
+        assert!(check_history(&[]).is_ok());
+        assert!(check_history(&[open(taker(1), OpenState::Opened)]).is_ok());
+    }
+
+    #[test]
+    fn refuses_takers_with_pending_open() {
+        assert!(check_history(&[open(taker(1), OpenState::Pending)]).is_err());
+    }
+
+    #[test]
+    fn refuses_blacklisted_takers() {
+        let opens = [
+            open(taker(1), OpenState::Expired),
+            open(taker(1), OpenState::Opened),
+        ];
+
+        assert!(check_history(&opens).is_err());
+    }
+
+    #[test]
+    fn bounds_pending_amount_across_takers() {
+        let opens = [
+            open(taker(1), OpenState::Pending),
+            open(taker(2), OpenState::Pending),
+            open(taker(3), OpenState::Opened),
+            open(taker(4), OpenState::Expired),
+        ];
+
+        let now = EXPIRED_FUNDING_WINDOW.as_secs() as i64;
+
+        assert!(check_pending(&settings(), &opens, 50_000, now).is_ok());
+        assert!(
+            check_pending(&settings(), &opens, 50_001, now).is_err(),
+            "A new node ID must not get around the limit"
+        );
+    }
+
+    #[test]
+    fn counts_recently_expired_opens_against_pending_amount() {
+        let opens = [
+            open(taker(1), OpenState::Expired),
+            open(taker(2), OpenState::Expired),
+        ];
+        let window = EXPIRED_FUNDING_WINDOW.as_secs() as i64;
+
+        assert!(
+            check_pending(&settings(), &opens, 50_001, window - 1).is_err(),
+            "Letting opens expire must not free up the limit"
+        );
+        assert!(check_pending(&settings(), &opens, 50_001, window).is_ok());
+    }
+
+    #[test]
+    fn matches_channel_with_taker() {
+        let channels = [
+            channel("a", taker(2), 150_000),
+            channel("b", taker(1), 50_000),
+            channel("c", taker(1), 150_000),
+        ];
+
+        let matched = match_channel(&open(taker(1), OpenState::Pending), &channels, &[]);
+
+        assert_eq!(matched, Some(&channels[2]));
+    }
+
+    #[test]
+    fn does_not_match_claimed_channel() {
+        let channels = [channel("a", taker(1), 150_000)];
+
+        let matched = match_channel(
+            &open(taker(1), OpenState::Pending),
+            &channels,
+            &["a".to_owned()],
+        );
+
+        assert_eq!(matched, None);
+    }
+}
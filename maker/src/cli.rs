@@ -1,3 +1,5 @@
+use crate::admin;
+use crate::config::Config;
 use anyhow::Result;
 use clap::Parser;
 use std::env::current_dir;
@@ -6,7 +8,19 @@ use std::path::PathBuf;
 
 #[derive(Parser)]
 pub struct Opts {
-    /// The IP address to listen on for the HTTP API.
+    /// Operate a running maker through its admin API instead of starting one.
+    #[clap(subcommand)]
+    pub command: Option<admin::Command>,
+
+    /// Bearer token the subcommands authenticate with.
+    #[clap(long, env = "MAKER_ADMIN_TOKEN", hide_env_values = true, global = true)]
+    pub token: Option<String>,
+
+    /// Print the output of the subcommands as JSON instead of tables.
+    #[clap(long, global = true)]
+    pub json: bool,
+
+    /// The IP address to listen on for the HTTP API, or of the maker the subcommands talk to.
     #[clap(long, default_value = "127.0.0.1:8000")]
     pub http_address: SocketAddr,
 
@@ -14,6 +28,32 @@ pub struct Opts {
     #[clap(long, default_value = "127.0.0.1:9045")]
     pub lightning_p2p_address: SocketAddr,
 
+    /// Path to a TOML file with the maker's settings, uses defaults if omitted.
+    #[clap(long, env = "MAKER_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// The profile to use from the config file.
+    #[clap(long, env = "MAKER_PROFILE", default_value = "default")]
+    pub profile: String,
+
+    /// Print the effective settings as TOML and exit.
+    #[clap(long)]
+    pub print_config: bool,
+
+    /// Encrypts the seed with this password, required on mainnet.
+    #[clap(long, env = "MAKER_SEED_PASSWORD", hide_env_values = true)]
+    pub seed_password: Option<String>,
+
+    /// Derives the wallet from the seed with this BIP39 passphrase, it is never stored.
+    #[clap(long, env = "MAKER_SEED_PASSPHRASE", hide_env_values = true)]
+    pub seed_passphrase: Option<String>,
+
+    #[clap(long, env = "BITMEX_API_KEY", hide_env_values = true)]
+    pub bitmex_api_key: Option<String>,
+
+    #[clap(long, env = "BITMEX_API_SECRET", hide_env_values = true)]
+    pub bitmex_api_secret: Option<String>,
+
     /// Where to permanently store data, defaults to the current working directory.
     #[clap(long)]
     data_dir: Option<PathBuf>,
@@ -25,6 +65,11 @@ impl Opts {
         Opts::parse()
     }
 
+    /// Reads the settings from the config file and the environment.
+    pub fn config(&self) -> Result<Config> {
+        Config::read(self.config.as_deref(), &self.profile)
+    }
+
     pub fn data_dir(&self) -> Result<PathBuf> {
         let data_dir = match self.data_dir.clone() {
             None => current_dir()?.join("data"),
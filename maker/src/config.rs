@@ -0,0 +1,494 @@
+//! The maker's settings, read from a TOML file with profiles and overridden by environment
+//! variables.
+//!
+//! The file holds a table per profile, `[default]` applies to all of them:
+//!
+//! ```toml
+//! [default]
+//! sync_interval = 60
+//!
+//! [default.risk]
+//! max_leverage = 5
+//!
+//! [testnet]
+//! network = "testnet"
+//! admin_tokens = ["s3cr3t", "monitoring:read"]
+//! ```
+//!
+//! Environment variables prefixed with `MAKER_` override the settings of every profile, nested
+//! settings are separated by `__`, e.g. `MAKER_RISK__MAX_LEVERAGE=5`.
+
+use crate::auth::AdminToken;
+use crate::bitmex;
+use crate::channel;
+use crate::faucet;
+use crate::hedging::ExchangeKind;
+use crate::mainnet;
+use crate::risk;
+use crate::spread::SpreadModel;
+use anyhow::Context;
+use anyhow::Result;
+use bdk::bitcoin::Network;
+use rocket::figment::providers::Env;
+use rocket::figment::providers::Format;
+use rocket::figment::providers::Serialized;
+use rocket::figment::providers::Toml;
+use rocket::figment::Figment;
+use rocket::serde::Deserialize;
+use rocket::serde::Serialize;
+use serde::Deserializer;
+use std::path::Path;
+use std::time::Duration;
+use ten_ten_one::chain::Backend;
+use ten_ten_one::chain::Bitcoind;
+
+const ENV_PREFIX: &str = "MAKER_";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Config {
+    /// Defaults to the `NETWORK` environment variable or testnet.
+    pub network: Network,
+
+    /// Replaces the public Electrum server of the network, we still fail over to the public
+    /// Esplora servers.
+    pub electrum_url: Option<String>,
+
+    /// Our own bitcoind, used on its own if set.
+    pub bitcoind: Option<Bitcoind>,
+
+    /// Seconds between wallet syncs, defaults to a value suitable for the network.
+    pub sync_interval: Option<u64>,
+
+    /// Bearer tokens for the admin API, as `<token>` for full access or
+    /// `<token>:<permission>,...` with the permissions `read`, `payments`, `channels` and
+    /// `spread`.
+    ///
+    /// The admin API is unavailable without a token.
+    #[serde(deserialize_with = "deserialize_admin_tokens")]
+    pub admin_tokens: Vec<AdminToken>,
+
+    pub feed: Feed,
+
+    pub spread: SpreadModel,
+
+    pub risk: risk::Limits,
+
+    pub faucet: faucet::Settings,
+
+    pub channel_funding: channel::Settings,
+
+    pub hedging: Hedging,
+
+    /// Caps and opt-in for running on mainnet.
+    pub mainnet: mainnet::Settings,
+
+    /// The Electrum and Esplora servers and bitcoinds we get chain data from, in the order we try
+    /// them.
+    ///
+    /// Defaults to `bitcoind` or `electrum_url` followed by the public Esplora servers of the
+    /// network.
+    // Last, as TOML requires arrays of tables to follow the other settings
+    pub chain_backends: Vec<Backend>,
+}
+
+/// Where we get our quotes from.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct Feed {
+    pub source: FeedSource,
+    pub network: bitmex::Network,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedSource {
+    Bitmex,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct Hedging {
+    /// The exchange to hedge our net exposure on, we don't hedge if omitted.
+    pub exchange: Option<ExchangeKind>,
+
+    /// Unhedged net exposure in contracts at which we place a hedge.
+    pub threshold: u64,
+}
+
+impl Config {
+    /// Reads the settings of the profile from the file, if any, and the environment.
+    ///
+    /// Settings that default to a value depending on the network are filled in, so the config
+    /// shows the settings we actually use.
+    pub fn read(file: Option<&Path>, profile: &str) -> Result<Self> {
+        let toml = match file {
+            Some(file) => std::fs::read_to_string(file)
+                .with_context(|| format!("Failed to read config from {}", file.display()))?,
+            None => String::new(),
+        };
+
+        Self::load(&toml, profile, Env::prefixed(ENV_PREFIX).split("__"))
+    }
+
+    fn load(toml: &str, profile: &str, env: Env) -> Result<Self> {
+        let mut config: Config = Figment::from(Serialized::defaults(Config::default()))
+            .merge(Toml::string(toml).nested())
+            .merge(env.global())
+            .select(profile)
+            .extract()
+            .context("Invalid config")?;
+
+        let network = config.network;
+        if config.chain_backends.is_empty() {
+            config.chain_backends = default_chain_backends(&config);
+        }
+        config
+            .sync_interval
+            .get_or_insert_with(|| default_sync_interval(network));
+
+        if network == Network::Bitcoin {
+            mainnet::apply_caps(&mut config);
+        }
+
+        config.spread.validate().context("Invalid spread model")?;
+
+        Ok(config)
+    }
+
+    pub fn sync_interval(&self) -> Duration {
+        let seconds = self
+            .sync_interval
+            .unwrap_or_else(|| default_sync_interval(self.network));
+
+        Duration::from_secs(seconds)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            network: ten_ten_one::config::network(),
+            electrum_url: None,
+            bitcoind: None,
+            sync_interval: None,
+            admin_tokens: Vec::new(),
+            feed: Feed::default(),
+            spread: SpreadModel::default(),
+            risk: risk::Limits::default(),
+            faucet: faucet::Settings::default(),
+            channel_funding: channel::Settings::default(),
+            hedging: Hedging::default(),
+            mainnet: mainnet::Settings::default(),
+            chain_backends: Vec::new(),
+        }
+    }
+}
+
+impl Default for Feed {
+    fn default() -> Self {
+        Self {
+            source: FeedSource::Bitmex,
+            network: bitmex::Network::Testnet,
+        }
+    }
+}
+
+impl Default for Hedging {
+    fn default() -> Self {
+        Self {
+            exchange: None,
+            threshold: 1000,
+        }
+    }
+}
+
+fn default_chain_backends(config: &Config) -> Vec<Backend> {
+    if let Some(bitcoind) = config.bitcoind.clone() {
+        return vec![Backend::Bitcoind(bitcoind)];
+    }
+
+    let electrum_url = config
+        .electrum_url
+        .clone()
+        .unwrap_or_else(|| ten_ten_one::config::default_electrum_url(config.network));
+    ten_ten_one::config::chain_backends_with_electrum(config.network, electrum_url)
+}
+
+fn default_sync_interval(network: Network) -> u64 {
+    match network {
+        Network::Bitcoin => 5 * 60,
+        Network::Testnet => 2 * 60,
+        Network::Signet => 60,
+        Network::Regtest => 30,
+    }
+}
+
+/// Accepts a list of tokens as well as a single string of space-separated tokens, as they come
+/// from environment variables.
+fn deserialize_admin_tokens<'de, D>(deserializer: D) -> Result<Vec<AdminToken>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Tokens {
+        List(Vec<String>),
+        Separated(String),
+    }
+
+    let tokens = match Tokens::deserialize(deserializer)? {
+        Tokens::List(tokens) => tokens,
+        Tokens::Separated(tokens) => tokens.split_whitespace().map(str::to_owned).collect(),
+    };
+
+    tokens
+        .iter()
+        .map(|token| token.parse().map_err(serde::de::Error::custom))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    /// Environment variables no test sets, so the tests don't depend on the environment.
+    fn no_env() -> Env {
+        Env::prefixed("MAKER_CONFIG_TEST_UNSET_")
+    }
+
+    #[test]
+    fn uses_defaults_without_file() {
+        let config = Config::load("", "default", no_env()).unwrap();
+
+        assert_eq!(config.risk, risk::Limits::default());
+        assert_eq!(config.spread, SpreadModel::default());
+        assert_eq!(
+            config.sync_interval,
+            Some(default_sync_interval(config.network))
+        );
+        assert!(config.admin_tokens.is_empty());
+    }
+
+    #[test]
+    fn profile_overrides_default_table() {
+        let toml = r#"
+            [default]
+            sync_interval = 10
+
+            [default.risk]
+            max_leverage = 5
+
+            [testnet]
+            network = "testnet"
+            sync_interval = 20
+            admin_tokens = ["s3cr3t", "monitoring:read"]
+
+            [testnet.spread]
+            base = 0.02
+        "#;
+
+        let config = Config::load(toml, "testnet", no_env()).unwrap();
+
+        assert_eq!(config.network, Network::Testnet);
+        assert_eq!(config.sync_interval, Some(20));
+        assert_eq!(config.risk.max_leverage, 5);
+        assert_eq!(
+            config.risk.max_notional,
+            risk::Limits::default().max_notional
+        );
+        assert_eq!(config.spread.base, dec!(0.02));
+        assert_eq!(config.spread.tiers, SpreadModel::default().tiers);
+        assert_eq!(config.admin_tokens.len(), 2);
+        assert_eq!(
+            config.chain_backends,
+            ten_ten_one::config::default_chain_backends(Network::Testnet)
+        );
+    }
+
+    #[test]
+    fn signet_profile_uses_signet_defaults() {
+        let toml = r#"
+            [signet]
+            network = "signet"
+        "#;
+
+        let config = Config::load(toml, "signet", no_env()).unwrap();
+
+        assert_eq!(config.network, Network::Signet);
+        assert_eq!(
+            config.chain_backends,
+            ten_ten_one::config::default_chain_backends(Network::Signet)
+        );
+        assert_eq!(
+            config.sync_interval,
+            Some(default_sync_interval(Network::Signet))
+        );
+    }
+
+    #[test]
+    fn mainnet_profile_is_capped() {
+        let toml = r#"
+            [bitcoin]
+            network = "bitcoin"
+
+            [bitcoin.channel_funding]
+            max_fund_amount = 1000000
+
+            [bitcoin.mainnet]
+            max_channel_sats = 100000
+        "#;
+
+        let config = Config::load(toml, "bitcoin", no_env()).unwrap();
+
+        assert_eq!(config.channel_funding.max_fund_amount, 100_000);
+        assert_eq!(
+            config.risk.max_notional_per_cfd,
+            mainnet::Settings::default().max_cfd_contracts
+        );
+    }
+
+    #[test]
+    fn reads_bitcoind() {
+        let toml = r#"
+            [regtest]
+            network = "regtest"
+
+            [regtest.bitcoind]
+            url = "http://127.0.0.1:18443"
+            rpc_user = "admin1"
+            rpc_password = "123"
+        "#;
+
+        let config = Config::load(toml, "regtest", no_env()).unwrap();
+        let bitcoind = config.bitcoind.unwrap();
+
+        assert_eq!(bitcoind.url, "http://127.0.0.1:18443");
+        assert_eq!(bitcoind.rpc_user.as_deref(), Some("admin1"));
+        assert_eq!(bitcoind.cookie_file, None);
+        assert_eq!(config.chain_backends, vec![Backend::Bitcoind(bitcoind)]);
+    }
+
+    #[test]
+    fn configured_electrum_server_fails_over_to_esplora() {
+        let toml = r#"
+            [testnet]
+            network = "testnet"
+            electrum_url = "tcp://electrum.example.com:50001"
+        "#;
+
+        let config = Config::load(toml, "testnet", no_env()).unwrap();
+
+        assert_eq!(
+            config.chain_backends[0],
+            Backend::Electrum {
+                url: "tcp://electrum.example.com:50001".to_owned()
+            }
+        );
+        assert!(config.chain_backends[1..]
+            .iter()
+            .all(|backend| matches!(backend, Backend::Esplora { .. })));
+        assert!(config.chain_backends.len() > 1);
+    }
+
+    #[test]
+    fn reads_ordered_chain_backends() {
+        let toml = r#"
+            [regtest]
+            network = "regtest"
+
+            [[regtest.chain_backends]]
+            kind = "bitcoind"
+            url = "http://127.0.0.1:18443"
+            cookie_file = "/data/regtest/.cookie"
+
+            [[regtest.chain_backends]]
+            kind = "esplora"
+            url = "http://localhost:3000"
+        "#;
+
+        let config = Config::load(toml, "regtest", no_env()).unwrap();
+
+        assert_eq!(config.chain_backends.len(), 2);
+        assert_eq!(config.chain_backends[0].kind(), "bitcoind");
+        assert_eq!(
+            config.chain_backends[1],
+            Backend::Esplora {
+                url: "http://localhost:3000".to_owned()
+            }
+        );
+    }
+
+    #[test]
+    fn ignores_other_profiles() {
+        let toml = r#"
+            [regtest]
+            sync_interval = 5
+        "#;
+
+        let config = Config::load(toml, "default", no_env()).unwrap();
+
+        assert_ne!(config.sync_interval, Some(5));
+    }
+
+    #[test]
+    fn environment_overrides_file() {
+        std::env::set_var("MAKER_CONFIG_TEST_ENV_RISK__MAX_LEVERAGE", "3");
+        std::env::set_var(
+            "MAKER_CONFIG_TEST_ENV_ADMIN_TOKENS",
+            "s3cr3t monitoring:read",
+        );
+        let toml = r#"
+            [testnet.risk]
+            max_leverage = 5
+        "#;
+
+        let config = Config::load(
+            toml,
+            "testnet",
+            Env::prefixed("MAKER_CONFIG_TEST_ENV_").split("__"),
+        )
+        .unwrap();
+
+        assert_eq!(config.risk.max_leverage, 3);
+        assert_eq!(config.admin_tokens.len(), 2);
+    }
+
+    #[test]
+    fn rejects_invalid_spread_model() {
+        let toml = r#"
+            [default.spread]
+            tiers = []
+        "#;
+
+        assert!(Config::load(toml, "default", no_env()).is_err());
+    }
+
+    #[test]
+    fn printed_config_can_be_read_again() {
+        let config = Config::load("", "default", no_env()).unwrap();
+
+        let printed = toml::to_string_pretty(&config).unwrap();
+        let reread = toml::from_str::<Config>(&printed).unwrap();
+
+        assert_eq!(reread.risk, config.risk);
+        assert_eq!(reread.spread, config.spread);
+        assert_eq!(reread.sync_interval, config.sync_interval);
+        assert_eq!(reread.chain_backends, config.chain_backends);
+    }
+
+    #[test]
+    fn printed_admin_tokens_do_not_grant_access() {
+        let toml = r#"
+            [default]
+            admin_tokens = ["s3cr3t", "monitoring:read"]
+        "#;
+        let config = Config::load(toml, "default", no_env()).unwrap();
+
+        let printed = toml::to_string_pretty(&config).unwrap();
+
+        assert!(!printed.contains("s3cr3t"));
+        assert!(toml::from_str::<Config>(&printed).is_err());
+    }
+}
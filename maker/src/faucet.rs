@@ -0,0 +1,291 @@
+//! Hands out test coins, with limits so that nobody can drain our wallet.
+
+use crate::metrics;
+use crate::metrics::PaymentKind;
+use anyhow::bail;
+use anyhow::Result;
+use bdk::bitcoin::Address;
+use bdk::bitcoin::Network;
+use bdk::bitcoin::Txid;
+use rocket::serde::Deserialize;
+use rocket::serde::Serialize;
+use sqlx::Connection;
+use sqlx::Row;
+use sqlx::Sqlite;
+use sqlx::Transaction;
+use std::fmt;
+use std::net::IpAddr;
+use ten_ten_one::db;
+use ten_ten_one::wallet;
+use time::OffsetDateTime;
+use tokio::sync::Mutex;
+
+/// Limits apply to the payouts within this window.
+const WINDOW: time::Duration = time::Duration::days(1);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct Settings {
+    /// Sats the faucet sends per request.
+    pub amount: u64,
+
+    /// Sats the faucet sends in total per day.
+    pub daily_budget: u64,
+
+    /// Payouts per address and day.
+    pub payouts_per_address: u32,
+
+    /// Payouts per IP address and day.
+    pub payouts_per_ip: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            amount: 10_000,
+            daily_budget: 1_000_000,
+            payouts_per_address: 1,
+            payouts_per_ip: 3,
+        }
+    }
+}
+
+/// The request exceeds one of the faucet's limits.
+#[derive(Debug)]
+pub struct LimitExceeded(&'static str);
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+/// Payouts within the current window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Usage {
+    address_payouts: u32,
+    ip_payouts: u32,
+    spent: u64,
+}
+
+pub struct Faucet {
+    settings: Settings,
+    /// Serialises payouts, so concurrent requests can't exceed the limits.
+    lock: Mutex<()>,
+}
+
+impl Faucet {
+    pub fn new(settings: Settings) -> Self {
+        Self {
+            settings,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Sends the configured amount to the address, fails with [`LimitExceeded`] if that exceeds
+    /// one of our limits.
+    ///
+    /// Never pays out on mainnet.
+    pub async fn pay(&self, network: Network, address: Address, ip: IpAddr) -> Result<Txid> {
+        if network == Network::Bitcoin {
+            bail!("There is no faucet on mainnet");
+        }
+
+        let _guard = self.lock.lock().await;
+
+        let payout = reserve_payout(&self.settings, &address, ip).await?;
+
+        let result = wallet::send_to_address(address.clone(), self.settings.amount);
+        metrics::record_payment(PaymentKind::Faucet, &result);
+        let txid = match result {
+            Ok(txid) => txid,
+            Err(e) => {
+                // We did not pay out, so the request doesn't count against the limits
+                if let Err(e) = delete_payout(payout).await {
+                    tracing::error!(%address, "Failed to release faucet payout: {e:#}");
+                }
+                return Err(e);
+            }
+        };
+        tracing::info!(%address, %ip, amount = self.settings.amount, %txid, "Paid out from faucet");
+
+        // The reserved payout counts against the limits regardless
+        if let Err(e) = set_payout_txid(payout, txid).await {
+            tracing::error!(%address, %txid, "Failed to record faucet payout: {e:#}");
+        }
+
+        Ok(txid)
+    }
+}
+
+fn check(settings: &Settings, usage: Usage) -> Result<()> {
+    if usage.address_payouts >= settings.payouts_per_address {
+        bail!(LimitExceeded("This address has already been funded today"));
+    }
+
+    if usage.ip_payouts >= settings.payouts_per_ip {
+        bail!(LimitExceeded("Too many faucet requests today"));
+    }
+
+    if usage.spent + settings.amount > settings.daily_budget {
+        bail!(LimitExceeded("The faucet has run dry for today"));
+    }
+
+    Ok(())
+}
+
+/// Checks the limits and records the payout as pending in one transaction, so that it counts
+/// against the limits before we send it.
+///
+/// Returns the ID of the payout.
+async fn reserve_payout(settings: &Settings, address: &Address, ip: IpAddr) -> Result<i64> {
+    let mut connection = db::acquire().await?;
+    let mut transaction = connection.begin().await?;
+
+    let now = OffsetDateTime::now_utc();
+    let usage = load_usage(&mut transaction, address, ip, now - WINDOW).await?;
+    check(settings, usage)?;
+
+    let id = sqlx::query(
+        r#"
+        INSERT INTO faucet_payout (address, ip, amount, created)
+        VALUES ($1, $2, $3, $4)
+        "#,
+    )
+    .bind(address.to_string())
+    .bind(ip.to_string())
+    .bind(settings.amount as i64)
+    .bind(now.unix_timestamp())
+    .execute(&mut transaction)
+    .await?
+    .last_insert_rowid();
+
+    transaction.commit().await?;
+
+    Ok(id)
+}
+
+async fn load_usage(
+    transaction: &mut Transaction<'_, Sqlite>,
+    address: &Address,
+    ip: IpAddr,
+    since: OffsetDateTime,
+) -> Result<Usage> {
+    let row = sqlx::query(
+        r#"
+        SELECT
+            COUNT(CASE WHEN address = $1 THEN 1 END) AS address_payouts,
+            COUNT(CASE WHEN ip = $2 THEN 1 END) AS ip_payouts,
+            COALESCE(SUM(amount), 0) AS spent
+        FROM faucet_payout
+        WHERE created >= $3
+        "#,
+    )
+    .bind(address.to_string())
+    .bind(ip.to_string())
+    .bind(since.unix_timestamp())
+    .fetch_one(transaction)
+    .await?;
+
+    Ok(Usage {
+        address_payouts: row.try_get::<i64, _>("address_payouts")? as u32,
+        ip_payouts: row.try_get::<i64, _>("ip_payouts")? as u32,
+        spent: row.try_get::<i64, _>("spent")? as u64,
+    })
+}
+
+async fn set_payout_txid(id: i64, txid: Txid) -> Result<()> {
+    let mut connection = db::acquire().await?;
+
+    sqlx::query(
+        r#"
+        UPDATE faucet_payout
+        SET txid = $1
+        WHERE id = $2
+        "#,
+    )
+    .bind(txid.to_string())
+    .bind(id)
+    .execute(&mut connection)
+    .await?;
+
+    Ok(())
+}
+
+async fn delete_payout(id: i64) -> Result<()> {
+    let mut connection = db::acquire().await?;
+
+    sqlx::query(
+        r#"
+        DELETE FROM faucet_payout
+        WHERE id = $1
+        "#,
+    )
+    .bind(id)
+    .execute(&mut connection)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> Settings {
+        Settings {
+            amount: 10_000,
+            daily_budget: 100_000,
+            payouts_per_address: 1,
+            payouts_per_ip: 3,
+        }
+    }
+
+    fn usage(address_payouts: u32, ip_payouts: u32, spent: u64) -> Usage {
+        Usage {
+            address_payouts,
+            ip_payouts,
+            spent,
+        }
+    }
+
+    #[test]
+    fn pays_first_request() {
+        assert!(check(&settings(), usage(0, 0, 0)).is_ok());
+    }
+
+    #[test]
+    fn limits_payouts_per_address() {
+        let error = check(&settings(), usage(1, 1, 10_000)).unwrap_err();
+
+        assert!(error.is::<LimitExceeded>());
+    }
+
+    #[test]
+    fn limits_payouts_per_ip() {
+        assert!(check(&settings(), usage(0, 2, 20_000)).is_ok());
+        assert!(check(&settings(), usage(0, 3, 30_000)).is_err());
+    }
+
+    #[test]
+    fn stays_within_daily_budget() {
+        assert!(check(&settings(), usage(0, 0, 90_000)).is_ok());
+        assert!(check(&settings(), usage(0, 0, 90_001)).is_err());
+    }
+
+    #[tokio::test]
+    async fn refuses_to_run_on_mainnet() {
+        let faucet = Faucet::new(settings());
+        let address = "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq"
+            .parse()
+            .unwrap();
+
+        let result = faucet
+            .pay(Network::Bitcoin, address, IpAddr::from([127, 0, 0, 1]))
+            .await;
+
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,332 @@
+//! Reports the state of the components the maker depends on, for `/health` and `/ready`.
+
+use crate::bitmex::Quote;
+use anyhow::Result;
+use rocket::serde::Serialize;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+use ten_ten_one::chain;
+use ten_ten_one::db;
+use ten_ten_one::wallet;
+use time::OffsetDateTime;
+
+/// We consider the price feed stale if we did not receive a quote for this long.
+pub const MAX_QUOTE_AGE: time::Duration = time::Duration::minutes(2);
+
+/// How long we answer `/health` and `/ready` with the same report, so frequent probes don't ping
+/// the chain backend and lock the database each time.
+const REPORT_TTL: Duration = Duration::from_secs(5);
+
+/// We consider the wallet out of sync if this many sync intervals passed without a successful
+/// sync.
+const MAX_MISSED_SYNCS: u32 = 3;
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    /// Whether all components required for trading are healthy.
+    pub ready: bool,
+    pub components: Vec<Component>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Component {
+    pub name: &'static str,
+    pub healthy: bool,
+    /// Trading is unsafe while this component is unhealthy.
+    pub required_for_trading: bool,
+    pub detail: String,
+}
+
+impl Component {
+    fn healthy(name: &'static str, required_for_trading: bool, detail: String) -> Self {
+        Self {
+            name,
+            healthy: true,
+            required_for_trading,
+            detail,
+        }
+    }
+
+    fn unhealthy(name: &'static str, required_for_trading: bool, detail: String) -> Self {
+        Self {
+            name,
+            healthy: false,
+            required_for_trading,
+            detail,
+        }
+    }
+}
+
+impl Report {
+    fn new(components: Vec<Component>) -> Self {
+        let ready = components
+            .iter()
+            .all(|component| component.healthy || !component.required_for_trading);
+
+        Self { ready, components }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct SyncState {
+    last_success: Option<OffsetDateTime>,
+    last_error: Option<String>,
+}
+
+/// Tracks the outcome of our wallet syncs and checks the other components on demand.
+pub struct Monitor {
+    sync_interval: Duration,
+    sync: Mutex<SyncState>,
+    last_report: Mutex<Option<(Instant, Report)>>,
+}
+
+impl Monitor {
+    pub fn new(sync_interval: Duration) -> Self {
+        Self {
+            sync_interval,
+            sync: Mutex::new(SyncState::default()),
+            last_report: Mutex::new(None),
+        }
+    }
+
+    pub fn record_sync(&self, result: &Result<()>) {
+        let mut sync = self.sync.lock().expect("mutex not to be poisoned");
+
+        match result {
+            Ok(()) => {
+                sync.last_success = Some(OffsetDateTime::now_utc());
+                sync.last_error = None;
+            }
+            Err(e) => sync.last_error = Some(format!("{e:#}")),
+        }
+    }
+
+    /// Checks the components, unless we did so within [`REPORT_TTL`].
+    pub async fn check(&self, quote: Option<Quote>) -> Report {
+        let last_report = self
+            .last_report
+            .lock()
+            .expect("mutex not to be poisoned")
+            .clone();
+        if let Some((checked_at, report)) = last_report {
+            if checked_at.elapsed() < REPORT_TTL {
+                return report;
+            }
+        }
+
+        let report = self.check_components(quote).await;
+        *self.last_report.lock().expect("mutex not to be poisoned") =
+            Some((Instant::now(), report.clone()));
+
+        report
+    }
+
+    async fn check_components(&self, quote: Option<Quote>) -> Report {
+        let now = OffsetDateTime::now_utc();
+        let sync = self.sync.lock().expect("mutex not to be poisoned").clone();
+
+        Report::new(vec![
+            check_chain_backend().await,
+            check_failover(&wallet::chain_backend_health()),
+            check_sync(&sync, self.sync_interval, now),
+            check_lightning(),
+            check_price_feed(quote, now),
+            check_database().await,
+            check_peers(),
+        ])
+    }
+}
+
+/// Labelled with the kind of backend that answered, e.g. `electrum`.
+async fn check_chain_backend() -> Component {
+    let result = tokio::task::spawn_blocking(wallet::ping_chain_backend)
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|result| result);
+
+    match result {
+        Ok(backend) => Component::healthy(backend.kind(), true, format!("{backend} is reachable")),
+        Err(e) => Component::unhealthy("chain_backend", true, format!("{e:#}")),
+    }
+}
+
+/// Informational, as we fail over to the next backend: which of them failed on their last use.
+fn check_failover(backends: &[(chain::Backend, chain::Health)]) -> Component {
+    let failing = backends
+        .iter()
+        .filter(|(_, health)| health.consecutive_failures > 0)
+        .map(|(backend, health)| {
+            format!(
+                "{backend} failed {} times in a row: {}",
+                health.consecutive_failures,
+                health.last_error.as_deref().unwrap_or("unknown error")
+            )
+        })
+        .collect::<Vec<_>>();
+
+    if failing.is_empty() {
+        Component::healthy(
+            "chain_backends",
+            false,
+            format!("All {} backends work", backends.len()),
+        )
+    } else {
+        Component::unhealthy("chain_backends", false, failing.join(", "))
+    }
+}
+
+fn check_sync(sync: &SyncState, sync_interval: Duration, now: OffsetDateTime) -> Component {
+    let max_age = sync_interval * MAX_MISSED_SYNCS;
+
+    match sync.last_success {
+        Some(last_success) if now - last_success <= max_age => Component::healthy(
+            "wallet_sync",
+            true,
+            format!("Last synced at {last_success}"),
+        ),
+        Some(last_success) => Component::unhealthy(
+            "wallet_sync",
+            true,
+            format!(
+                "Last synced at {last_success}, last error: {}",
+                sync.last_error.as_deref().unwrap_or("none")
+            ),
+        ),
+        None => Component::unhealthy(
+            "wallet_sync",
+            true,
+            format!(
+                "Not synced yet, last error: {}",
+                sync.last_error.as_deref().unwrap_or("none")
+            ),
+        ),
+    }
+}
+
+fn check_lightning() -> Component {
+    if wallet::is_lightning_running() {
+        Component::healthy("lightning", true, "Background processor running".to_owned())
+    } else {
+        Component::unhealthy(
+            "lightning",
+            true,
+            "Background processor not running".to_owned(),
+        )
+    }
+}
+
+fn check_price_feed(quote: Option<Quote>, now: OffsetDateTime) -> Component {
+    match quote {
+        Some(quote) if now - quote.timestamp <= MAX_QUOTE_AGE => Component::healthy(
+            "price_feed",
+            true,
+            format!("Latest quote from {}", quote.timestamp),
+        ),
+        Some(quote) => Component::unhealthy(
+            "price_feed",
+            true,
+            format!("Latest quote from {} is stale", quote.timestamp),
+        ),
+        None => Component::unhealthy("price_feed", true, "No quote yet".to_owned()),
+    }
+}
+
+async fn check_database() -> Component {
+    match db::check_writable().await {
+        Ok(()) => Component::healthy("database", true, "Writable".to_owned()),
+        Err(e) => Component::unhealthy("database", true, format!("{e:#}")),
+    }
+}
+
+/// Informational only, we can trade as soon as a taker connects.
+fn check_peers() -> Component {
+    match wallet::get_peer_manager() {
+        Ok(peer_manager) => {
+            let peers = peer_manager.get_peer_node_ids().len();
+            Component::healthy("peers", false, format!("{peers} peers connected"))
+        }
+        Err(e) => Component::unhealthy("peers", false, format!("{e:#}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitmex::ContractSymbol;
+    use rust_decimal::Decimal;
+    use time::ext::NumericalDuration;
+
+    const SYNC_INTERVAL: Duration = Duration::from_secs(30);
+
+    fn quote(timestamp: OffsetDateTime) -> Quote {
+        Quote {
+            timestamp,
+            bid: Decimal::ONE,
+            ask: Decimal::ONE,
+            index: Decimal::ONE,
+            symbol: ContractSymbol::BtcUsd,
+        }
+    }
+
+    #[test]
+    fn wallet_sync_is_healthy_within_missed_syncs() {
+        let now = OffsetDateTime::now_utc();
+        let sync = SyncState {
+            last_success: Some(now - 60.seconds()),
+            last_error: Some("timeout".to_owned()),
+        };
+
+        assert!(check_sync(&sync, SYNC_INTERVAL, now).healthy);
+    }
+
+    #[test]
+    fn wallet_sync_is_unhealthy_after_missed_syncs() {
+        let now = OffsetDateTime::now_utc();
+        let sync = SyncState {
+            last_success: Some(now - 91.seconds()),
+            last_error: Some("timeout".to_owned()),
+        };
+
+        let component = check_sync(&sync, SYNC_INTERVAL, now);
+
+        assert!(!component.healthy);
+        assert!(component.detail.contains("timeout"));
+    }
+
+    #[test]
+    fn wallet_sync_is_unhealthy_before_first_sync() {
+        let now = OffsetDateTime::now_utc();
+
+        assert!(!check_sync(&SyncState::default(), SYNC_INTERVAL, now).healthy);
+    }
+
+    #[test]
+    fn price_feed_goes_stale() {
+        let now = OffsetDateTime::now_utc();
+
+        assert!(check_price_feed(Some(quote(now - 1.minutes())), now).healthy);
+        assert!(!check_price_feed(Some(quote(now - 3.minutes())), now).healthy);
+        assert!(!check_price_feed(None, now).healthy);
+    }
+
+    #[test]
+    fn not_ready_if_component_required_for_trading_is_unhealthy() {
+        let report = Report::new(vec![
+            Component::healthy("database", true, String::new()),
+            Component::unhealthy("price_feed", true, String::new()),
+        ]);
+
+        assert!(!report.ready);
+    }
+
+    #[test]
+    fn ready_if_only_informational_component_is_unhealthy() {
+        let report = Report::new(vec![
+            Component::healthy("database", true, String::new()),
+            Component::unhealthy("peers", false, String::new()),
+        ]);
+
+        assert!(report.ready);
+    }
+}
@@ -0,0 +1,296 @@
+use crate::hedging::Exchange;
+use crate::hedging::Execution;
+use crate::hedging::Fill;
+use crate::hedging::Side;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use async_trait::async_trait;
+use bdk::bitcoin::Network;
+use hmac::Hmac;
+use hmac::Mac;
+use reqwest::Method;
+use reqwest::RequestBuilder;
+use reqwest::Url;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use sha2::Sha256;
+use std::time::Duration;
+use time::OffsetDateTime;
+
+const ORDER_PATH: &str = "/api/v1/order";
+
+/// How long a signed request stays valid.
+const REQUEST_EXPIRY: time::Duration = time::Duration::minutes(1);
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// XBTUSD orders must be a multiple of this many contracts.
+const LOT_SIZE: u64 = 100;
+
+/// Places market orders on BitMEX's XBTUSD perpetual swap through the REST API.
+pub struct BitmexExchange {
+    client: reqwest::Client,
+    base_url: &'static str,
+    api_key: String,
+    api_secret: String,
+}
+
+impl BitmexExchange {
+    /// Trades on BitMEX testnet unless we are on mainnet.
+    pub fn new(network: Network, api_key: String, api_secret: String) -> Self {
+        let base_url = match network {
+            Network::Bitcoin => "https://www.bitmex.com",
+            Network::Testnet | Network::Signet | Network::Regtest => "https://testnet.bitmex.com",
+        };
+
+        Self {
+            client: reqwest::Client::new(),
+            base_url,
+            api_key,
+            api_secret,
+        }
+    }
+}
+
+#[async_trait]
+impl Exchange for BitmexExchange {
+    fn name(&self) -> &'static str {
+        "bitmex"
+    }
+
+    async fn place_market_order(
+        &self,
+        client_order_id: &str,
+        side: Side,
+        quantity: u64,
+    ) -> Result<Execution> {
+        // The remainder stays unhedged until it adds up to a full lot
+        let quantity = quantity - quantity % LOT_SIZE;
+        if quantity == 0 {
+            bail!("Order is smaller than the lot size of {LOT_SIZE} contracts");
+        }
+
+        let body = serde_json::json!({
+            "symbol": "XBTUSD",
+            "side": side.to_string(),
+            "orderQty": quantity,
+            "ordType": "Market",
+            "clOrdID": client_order_id,
+        })
+        .to_string();
+
+        let url = Url::parse(&format!("{}{ORDER_PATH}", self.base_url))?;
+        let response = self
+            .request(Method::POST, &url, body)?
+            .header("content-type", "application/json")
+            .send()
+            .await
+            .context("Failed to send order to BitMEX")?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            bail!("BitMEX rejected order with {status}: {text}");
+        }
+
+        let order = response
+            .json::<wire::Order>()
+            .await
+            .context("Failed to parse order from BitMEX")?;
+
+        order.into_execution(self.name())
+    }
+
+    async fn find_order(&self, client_order_id: &str) -> Result<Option<Execution>> {
+        let filter = serde_json::json!({ "clOrdID": client_order_id }).to_string();
+        let url = Url::parse_with_params(
+            &format!("{}{ORDER_PATH}", self.base_url),
+            &[("symbol", "XBTUSD"), ("filter", filter.as_str())],
+        )?;
+
+        let response = self
+            .request(Method::GET, &url, String::new())?
+            .send()
+            .await
+            .context("Failed to look up order on BitMEX")?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            bail!("BitMEX failed to look up order with {status}: {text}");
+        }
+
+        let orders = response
+            .json::<Vec<wire::Order>>()
+            .await
+            .context("Failed to parse orders from BitMEX")?;
+
+        match orders.into_iter().next() {
+            Some(order) => Ok(Some(order.into_execution(self.name())?)),
+            None => Ok(None),
+        }
+    }
+}
+
+impl BitmexExchange {
+    /// Builds a signed request to the URL.
+    fn request(&self, method: Method, url: &Url, body: String) -> Result<RequestBuilder> {
+        let path = match url.query() {
+            Some(query) => format!("{}?{query}", url.path()),
+            None => url.path().to_owned(),
+        };
+        let expires = (OffsetDateTime::now_utc() + REQUEST_EXPIRY).unix_timestamp();
+        let signature = sign(&self.api_secret, method.as_str(), &path, expires, &body)?;
+
+        Ok(self
+            .client
+            .request(method, url.clone())
+            .timeout(REQUEST_TIMEOUT)
+            .header("api-expires", expires.to_string())
+            .header("api-key", &self.api_key)
+            .header("api-signature", signature)
+            .body(body))
+    }
+}
+
+/// Signs a request as described in https://www.bitmex.com/app/apiKeysUsage.
+fn sign(secret: &str, verb: &str, path: &str, expires: i64, body: &str) -> Result<String> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).context("Invalid BitMEX API secret")?;
+    mac.update(format!("{verb}{path}{expires}{body}").as_bytes());
+
+    Ok(hex::encode(mac.finalize().into_bytes()))
+}
+
+mod wire {
+    use super::*;
+
+    #[derive(Debug, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Order {
+        #[serde(rename = "orderID")]
+        pub order_id: String,
+        pub side: String,
+        pub ord_status: String,
+        pub cum_qty: u64,
+        #[serde(default, with = "rust_decimal::serde::float_option")]
+        pub avg_px: Option<Decimal>,
+        #[serde(with = "time::serde::rfc3339")]
+        pub timestamp: OffsetDateTime,
+    }
+
+    impl Order {
+        /// What was filled so far counts, even if the rest of the order was canceled.
+        pub fn into_execution(self, exchange: &str) -> Result<Execution> {
+            let open = matches!(
+                self.ord_status.as_str(),
+                "PendingNew" | "New" | "PartiallyFilled"
+            );
+            if self.cum_qty == 0 {
+                return Ok(Execution { fill: None, open });
+            }
+
+            let side = match self.side.as_str() {
+                "Buy" => Side::Buy,
+                "Sell" => Side::Sell,
+                side => bail!("Unknown side {side} of BitMEX order {}", self.order_id),
+            };
+
+            let fill = Fill {
+                exchange: exchange.to_owned(),
+                side,
+                quantity: self.cum_qty,
+                price: self
+                    .avg_px
+                    .with_context(|| format!("BitMEX order {} has no price", self.order_id))?,
+                timestamp: self.timestamp,
+                order_id: self.order_id,
+            };
+
+            Ok(Execution {
+                fill: Some(fill),
+                open,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn signs_request_like_bitmex_docs() {
+        // Example from https://www.bitmex.com/app/apiKeysUsage
+        let signature = sign(
+            "chNOOS4KvNXR_Xq4k4c9qsfoKWvnDecLATCRlcBwyKDYnWgO",
+            "POST",
+            "/api/v1/order",
+            1518064238,
+            r#"{"symbol":"XBTM15","price":219.0,"clOrdID":"mm_bitmex_1a/oemUeQ4CAJZgP3fjHsA","orderQty":98}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            signature,
+            "1749cd2ccae4aa49048ae09f0b95110cee706e0944e6a14ad0b3a8cb45bd336b"
+        );
+    }
+
+    #[test]
+    fn parses_filled_order() {
+        let order = serde_json::from_str::<wire::Order>(
+            r#"{"orderID":"3f9c2b1a","side":"Sell","ordStatus":"Filled","cumQty":100,"avgPx":16005.5,"timestamp":"2022-12-13T09:00:00.000Z","symbol":"XBTUSD"}"#,
+        )
+        .unwrap();
+
+        let execution = order.into_execution("bitmex").unwrap();
+        let fill = execution.fill.unwrap();
+
+        assert!(!execution.open);
+        assert_eq!(fill.order_id, "3f9c2b1a");
+        assert_eq!(fill.side, Side::Sell);
+        assert_eq!(fill.quantity, 100);
+        assert_eq!(fill.price, dec!(16005.5));
+    }
+
+    #[test]
+    fn canceled_order_without_fills() {
+        let order = serde_json::from_str::<wire::Order>(
+            r#"{"orderID":"3f9c2b1a","side":"Sell","ordStatus":"Canceled","cumQty":0,"timestamp":"2022-12-13T09:00:00.000Z"}"#,
+        )
+        .unwrap();
+
+        let execution = order.into_execution("bitmex").unwrap();
+
+        assert_eq!(
+            execution,
+            Execution {
+                fill: None,
+                open: false
+            }
+        );
+    }
+
+    #[test]
+    fn partial_fills_count() {
+        let canceled = serde_json::from_str::<wire::Order>(
+            r#"{"orderID":"3f9c2b1a","side":"Buy","ordStatus":"Canceled","cumQty":300,"avgPx":16010,"timestamp":"2022-12-13T09:00:00.000Z"}"#,
+        )
+        .unwrap();
+        let partially_filled = serde_json::from_str::<wire::Order>(
+            r#"{"orderID":"3f9c2b1a","side":"Buy","ordStatus":"PartiallyFilled","cumQty":300,"avgPx":16010,"timestamp":"2022-12-13T09:00:00.000Z"}"#,
+        )
+        .unwrap();
+
+        let canceled = canceled.into_execution("bitmex").unwrap();
+        assert!(!canceled.open);
+        assert_eq!(canceled.fill.unwrap().quantity, 300);
+
+        let partially_filled = partially_filled.into_execution("bitmex").unwrap();
+        assert!(partially_filled.open);
+        assert_eq!(partially_filled.fill.unwrap().quantity, 300);
+    }
+}
@@ -0,0 +1,435 @@
+//! Offsets the net exposure from our CFDs by trading on an exchange.
+
+use anyhow::Result;
+use async_trait::async_trait;
+use rocket::serde::Deserialize;
+use rocket::serde::Serialize;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use sqlx::Row;
+use std::sync::Arc;
+use std::time::Duration;
+use ten_ten_one::db;
+use time::OffsetDateTime;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+pub mod bitmex;
+pub mod paper;
+
+/// How long we wait before trying again after failing to place a hedge.
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The exchanges we can hedge on.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExchangeKind {
+    /// Fills orders locally at the current quote, for testing.
+    Paper,
+    Bitmex,
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, strum_macros::Display, sqlx::Type,
+)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+/// An order that was filled on the exchange.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Fill {
+    pub exchange: String,
+    pub order_id: String,
+    pub side: Side,
+    /// Number of contracts, each worth one USD.
+    pub quantity: u64,
+    #[serde(with = "rust_decimal::serde::float")]
+    pub price: Decimal,
+    #[serde(with = "time::serde::timestamp")]
+    pub timestamp: OffsetDateTime,
+}
+
+/// What became of an order we placed on the exchange.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Execution {
+    /// What was filled so far, if anything.
+    pub fill: Option<Fill>,
+    /// Whether the order can still be filled further.
+    pub open: bool,
+}
+
+impl Fill {
+    /// The change of our position in contracts, positive if we bought.
+    fn signed_quantity(&self) -> i64 {
+        match self.side {
+            Side::Buy => self.quantity as i64,
+            Side::Sell => -(self.quantity as i64),
+        }
+    }
+}
+
+/// An exchange we can place hedging orders on.
+#[async_trait]
+pub trait Exchange: Send + Sync {
+    /// Identifies the exchange in our fill records.
+    fn name(&self) -> &'static str;
+
+    /// Places a market order for the given number of contracts.
+    ///
+    /// The client order ID identifies the order on the exchange, so we can look it up if we don't
+    /// hear back.
+    async fn place_market_order(
+        &self,
+        client_order_id: &str,
+        side: Side,
+        quantity: u64,
+    ) -> Result<Execution>;
+
+    /// Looks up the order we placed with the client order ID, `None` if it never reached the
+    /// exchange.
+    async fn find_order(&self, client_order_id: &str) -> Result<Option<Execution>>;
+}
+
+/// Keeps our position on the exchange opposite to our net exposure from CFDs.
+///
+/// We only hedge once the unhedged exposure reaches the threshold, so we don't trade on every
+/// small CFD.
+pub fn spawn(
+    exchange: Arc<dyn Exchange>,
+    mut exposure_receiver: watch::Receiver<i64>,
+    threshold: u64,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut hedged = match hedged_position(exchange.name()).await {
+            Ok(hedged) => hedged,
+            Err(e) => {
+                tracing::error!("Failed to load hedge position, not hedging: {e:#}");
+                return;
+            }
+        };
+        tracing::info!(exchange = exchange.name(), hedged, "Hedging net exposure");
+
+        // The order whose outcome we don't know yet, by client order ID
+        let mut pending = None;
+
+        loop {
+            let exposure = *exposure_receiver.borrow_and_update();
+
+            let retry = match hedge(
+                exchange.as_ref(),
+                &mut pending,
+                &mut hedged,
+                exposure,
+                threshold,
+            )
+            .await
+            {
+                Ok(()) => pending.is_some(),
+                Err(e) => {
+                    tracing::error!(exposure, hedged, "Failed to hedge net exposure: {e:#}");
+                    true
+                }
+            };
+
+            if retry {
+                tokio::select! {
+                    _ = tokio::time::sleep(RETRY_INTERVAL) => {}
+                    changed = exposure_receiver.changed() => {
+                        if changed.is_err() {
+                            break;
+                        }
+                    }
+                }
+            } else if exposure_receiver.changed().await.is_err() {
+                break;
+            }
+        }
+
+        tracing::error!("Stopped hedging");
+    })
+}
+
+/// The order that moves our hedge opposite to our exposure, if the unhedged exposure reaches the
+/// threshold.
+fn rebalance(exposure: i64, hedged: i64, threshold: u64) -> Option<(Side, u64)> {
+    let delta = -exposure - hedged;
+
+    if delta == 0 || delta.unsigned_abs() < threshold {
+        return None;
+    }
+
+    let side = if delta > 0 { Side::Buy } else { Side::Sell };
+    Some((side, delta.unsigned_abs()))
+}
+
+/// Places the order that moves our hedge opposite to our exposure.
+///
+/// An order we placed before but whose outcome we don't know, e.g. because the request timed out,
+/// is looked up first. Placing another one in the meantime could hedge the same exposure twice.
+async fn hedge(
+    exchange: &dyn Exchange,
+    pending: &mut Option<String>,
+    hedged: &mut i64,
+    exposure: i64,
+    threshold: u64,
+) -> Result<()> {
+    if let Some(client_order_id) = pending.clone() {
+        match exchange.find_order(&client_order_id).await? {
+            Some(execution) => record(pending, hedged, execution).await,
+            None => {
+                tracing::info!(client_order_id, "Hedge never reached the exchange");
+                *pending = None;
+            }
+        }
+
+        if pending.is_some() {
+            return Ok(());
+        }
+    }
+
+    let (side, quantity) = match rebalance(exposure, *hedged, threshold) {
+        Some(order) => order,
+        None => return Ok(()),
+    };
+
+    let client_order_id = client_order_id(OffsetDateTime::now_utc());
+    tracing::info!(
+        exchange = exchange.name(),
+        client_order_id,
+        %side,
+        quantity,
+        "Placing hedge"
+    );
+
+    *pending = Some(client_order_id.clone());
+    let execution = exchange
+        .place_market_order(&client_order_id, side, quantity)
+        .await?;
+    record(pending, hedged, execution).await;
+    tracing::info!(exposure, hedged, "Hedged net exposure");
+
+    Ok(())
+}
+
+/// Counts what was filled once the order can't be filled any further.
+///
+/// Partially filled orders count as well, otherwise we would hedge their fills again.
+async fn record(pending: &mut Option<String>, hedged: &mut i64, execution: Execution) {
+    if execution.open {
+        tracing::debug!(?execution, "Hedge is not done yet");
+        return;
+    }
+    *pending = None;
+
+    let fill = match execution.fill {
+        Some(fill) => fill,
+        None => {
+            tracing::warn!("Hedge was not filled");
+            return;
+        }
+    };
+
+    *hedged += fill.signed_quantity();
+    tracing::info!(hedged, ?fill, "Hedge was filled");
+
+    if let Err(e) = insert_fill(&fill).await {
+        // The order went through regardless, hence we still count it
+        tracing::error!(?fill, "Failed to persist fill: {e:#}");
+    }
+}
+
+/// Identifies our order on the exchange, the same ID is used to look it up later.
+fn client_order_id(timestamp: OffsetDateTime) -> String {
+    format!("10101-hedge-{}", timestamp.unix_timestamp_nanos())
+}
+
+async fn insert_fill(fill: &Fill) -> Result<()> {
+    let mut connection = db::acquire().await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO hedge_fill (exchange, order_id, side, quantity, price, created)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+    )
+    .bind(&fill.exchange)
+    .bind(&fill.order_id)
+    .bind(fill.side)
+    .bind(fill.quantity as i64)
+    .bind(fill.price.to_f64())
+    .bind(fill.timestamp.unix_timestamp())
+    .execute(&mut connection)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn load_fills() -> Result<Vec<Fill>> {
+    let mut connection = db::acquire().await?;
+
+    let rows = sqlx::query(
+        r#"
+        SELECT exchange, order_id, side, quantity, price, created
+        FROM hedge_fill
+        ORDER BY id
+        "#,
+    )
+    .fetch_all(&mut connection)
+    .await?;
+
+    rows.into_iter()
+        .map(|row| {
+            Ok(Fill {
+                exchange: row.try_get("exchange")?,
+                order_id: row.try_get("order_id")?,
+                side: row.try_get("side")?,
+                quantity: row.try_get::<i64, _>("quantity")? as u64,
+                price: Decimal::try_from(row.try_get::<f64, _>("price")?)?,
+                timestamp: OffsetDateTime::from_unix_timestamp(row.try_get("created")?)?,
+            })
+        })
+        .collect()
+}
+
+/// Our position on the exchange according to our recorded fills, positive if we are long.
+async fn hedged_position(exchange: &str) -> Result<i64> {
+    let mut connection = db::acquire().await?;
+
+    let row = sqlx::query(
+        r#"
+        SELECT COALESCE(SUM(CASE side WHEN 'Buy' THEN quantity ELSE -quantity END), 0) AS position
+        FROM hedge_fill
+        WHERE exchange = $1
+        "#,
+    )
+    .bind(exchange)
+    .fetch_one(&mut connection)
+    .await?;
+
+    Ok(row.try_get("position")?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use rust_decimal_macros::dec;
+    use std::sync::Mutex;
+
+    /// Fails to tell us about the first order, which was partially filled.
+    #[derive(Default)]
+    struct TimingOutExchange {
+        placed: Mutex<Vec<(String, Side, u64)>>,
+    }
+
+    #[async_trait]
+    impl Exchange for TimingOutExchange {
+        fn name(&self) -> &'static str {
+            "test"
+        }
+
+        async fn place_market_order(
+            &self,
+            client_order_id: &str,
+            side: Side,
+            quantity: u64,
+        ) -> Result<Execution> {
+            let mut placed = self.placed.lock().unwrap();
+            placed.push((client_order_id.to_owned(), side, quantity));
+
+            if placed.len() == 1 {
+                return Err(anyhow!("Request timed out"));
+            }
+            Ok(execution(client_order_id, side, quantity, false))
+        }
+
+        async fn find_order(&self, client_order_id: &str) -> Result<Option<Execution>> {
+            let placed = self.placed.lock().unwrap();
+            let (_, side, _) = placed
+                .iter()
+                .find(|(id, _, _)| id == client_order_id)
+                .expect("to only look up orders we placed");
+
+            Ok(Some(execution(client_order_id, *side, 300, false)))
+        }
+    }
+
+    fn execution(order_id: &str, side: Side, quantity: u64, open: bool) -> Execution {
+        Execution {
+            fill: Some(Fill {
+                exchange: "test".to_owned(),
+                order_id: order_id.to_owned(),
+                side,
+                quantity,
+                price: dec!(16000),
+                timestamp: OffsetDateTime::now_utc(),
+            }),
+            open,
+        }
+    }
+
+    #[tokio::test]
+    async fn counts_partial_fill_of_order_we_did_not_hear_back_about() {
+        let exchange = TimingOutExchange::default();
+        let mut pending = None;
+        let mut hedged = 0;
+
+        assert!(hedge(&exchange, &mut pending, &mut hedged, 1000, 100)
+            .await
+            .is_err());
+        assert!(pending.is_some());
+        assert_eq!(hedged, 0);
+
+        hedge(&exchange, &mut pending, &mut hedged, 1000, 100)
+            .await
+            .unwrap();
+
+        let placed = exchange.placed.lock().unwrap();
+        assert_eq!(placed[1].1, Side::Sell);
+        assert_eq!(placed[1].2, 700, "Only the unfilled rest is hedged again");
+        assert_ne!(placed[0].0, placed[1].0);
+        assert_eq!(hedged, -1000);
+        assert!(pending.is_none());
+    }
+
+    #[tokio::test]
+    async fn waits_for_open_order() {
+        let mut pending = Some("a".to_owned());
+        let mut hedged = 0;
+
+        record(
+            &mut pending,
+            &mut hedged,
+            execution("a", Side::Sell, 300, true),
+        )
+        .await;
+
+        assert_eq!(pending.as_deref(), Some("a"));
+        assert_eq!(hedged, 0);
+    }
+
+    #[test]
+    fn does_not_hedge_below_threshold() {
+        assert_eq!(rebalance(0, 0, 100), None);
+        assert_eq!(rebalance(99, 0, 100), None);
+        assert_eq!(rebalance(-150, 100, 100), None);
+    }
+
+    #[test]
+    fn sells_when_we_are_long() {
+        assert_eq!(rebalance(100, 0, 100), Some((Side::Sell, 100)));
+        assert_eq!(rebalance(500, -200, 100), Some((Side::Sell, 300)));
+    }
+
+    #[test]
+    fn buys_when_we_are_short() {
+        assert_eq!(rebalance(-1000, 0, 100), Some((Side::Buy, 1000)));
+    }
+
+    #[test]
+    fn unwinds_hedge_when_exposure_goes_away() {
+        assert_eq!(rebalance(0, -1000, 100), Some((Side::Buy, 1000)));
+    }
+}
@@ -0,0 +1,109 @@
+use crate::bitmex::Quote;
+use crate::hedging::Exchange;
+use crate::hedging::Execution;
+use crate::hedging::Fill;
+use crate::hedging::Side;
+use anyhow::Context;
+use anyhow::Result;
+use async_trait::async_trait;
+use time::OffsetDateTime;
+use tokio::sync::watch;
+
+/// Fills every order immediately at the current quote, without trading anywhere.
+pub struct PaperExchange {
+    quote_receiver: watch::Receiver<Option<Quote>>,
+}
+
+impl PaperExchange {
+    pub fn new(quote_receiver: watch::Receiver<Option<Quote>>) -> Self {
+        Self { quote_receiver }
+    }
+}
+
+#[async_trait]
+impl Exchange for PaperExchange {
+    fn name(&self) -> &'static str {
+        "paper"
+    }
+
+    async fn place_market_order(
+        &self,
+        client_order_id: &str,
+        side: Side,
+        quantity: u64,
+    ) -> Result<Execution> {
+        let quote = (*self.quote_receiver.borrow()).context("No quotes found")?;
+
+        let price = match side {
+            Side::Buy => quote.ask(),
+            Side::Sell => quote.bid(),
+        };
+
+        let fill = Fill {
+            exchange: self.name().to_owned(),
+            order_id: client_order_id.to_owned(),
+            side,
+            quantity,
+            price,
+            timestamp: OffsetDateTime::now_utc(),
+        };
+
+        Ok(Execution {
+            fill: Some(fill),
+            open: false,
+        })
+    }
+
+    /// Orders are filled as they are placed, an order we don't know the outcome of never was.
+    async fn find_order(&self, _: &str) -> Result<Option<Execution>> {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitmex::ContractSymbol;
+    use rust_decimal_macros::dec;
+
+    fn exchange() -> PaperExchange {
+        let (_, quote_receiver) = watch::channel(Some(Quote {
+            timestamp: OffsetDateTime::now_utc(),
+            bid: dec!(16000),
+            ask: dec!(16010),
+            index: dec!(16005),
+            symbol: ContractSymbol::BtcUsd,
+        }));
+
+        PaperExchange::new(quote_receiver)
+    }
+
+    #[tokio::test]
+    async fn fills_at_the_current_quote() {
+        let exchange = exchange();
+
+        let buy = exchange
+            .place_market_order("a", Side::Buy, 100)
+            .await
+            .unwrap();
+        let sell = exchange
+            .place_market_order("b", Side::Sell, 100)
+            .await
+            .unwrap();
+
+        assert!(!buy.open);
+        assert_eq!(buy.fill.unwrap().price, dec!(16010));
+        assert_eq!(sell.fill.unwrap().price, dec!(16000));
+    }
+
+    #[tokio::test]
+    async fn fails_without_quote() {
+        let (_, quote_receiver) = watch::channel(None);
+        let exchange = PaperExchange::new(quote_receiver);
+
+        assert!(exchange
+            .place_market_order("a", Side::Buy, 100)
+            .await
+            .is_err());
+    }
+}
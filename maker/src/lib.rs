@@ -1,4 +1,21 @@
+pub mod admin;
+pub mod auth;
 pub mod bitmex;
+pub mod cfd;
+pub mod channel;
 pub mod cli;
+pub mod config;
+pub mod faucet;
+pub mod health;
+pub mod hedging;
 pub mod logger;
+pub mod mainnet;
+pub mod metrics;
+pub mod offer;
+pub mod openapi;
+pub mod p2p;
+pub mod problem;
+pub mod risk;
 pub mod routes;
+pub mod spread;
+pub mod volatility;
@@ -1,11 +1,32 @@
+use anyhow::Context;
 use anyhow::Result;
 use bdk::bitcoin::Network;
+use maker::admin;
+use maker::auth;
+use maker::auth::AdminTokens;
 use maker::bitmex;
+use maker::cfd;
+use maker::channel::ChannelFunder;
 use maker::cli::Opts;
+use maker::config::FeedSource;
+use maker::faucet::Faucet;
+use maker::health::Monitor;
+use maker::hedging;
+use maker::hedging::bitmex::BitmexExchange;
+use maker::hedging::paper::PaperExchange;
+use maker::hedging::Exchange;
+use maker::hedging::ExchangeKind;
 use maker::logger;
+use maker::mainnet;
+use maker::metrics;
+use maker::metrics::HttpMetrics;
+use maker::p2p;
+use maker::risk::RiskManager;
 use maker::routes;
-use maker::routes::SpreadPrice;
-use std::time::Duration;
+use maker::volatility;
+use maker_api::PeerAddr;
+use maker_api::PeerInfo;
+use std::sync::Arc;
 use std::time::Instant;
 use ten_ten_one::db;
 use ten_ten_one::wallet;
@@ -16,14 +37,39 @@ use tracing::metadata::LevelFilter;
 async fn main() -> Result<()> {
     let opts = Opts::read();
 
+    if let Some(command) = opts.command {
+        let client = admin::Client::new(opts.http_address, opts.token)?;
+        return admin::run(command, &client, opts.json).await;
+    }
+
+    let config = opts.config()?;
+    if opts.print_config {
+        print!("{}", toml::to_string_pretty(&config)?);
+        return Ok(());
+    }
+
     let path = opts.data_dir()?;
     let lightning_p2p_address = opts.lightning_p2p_address;
     let http_address = opts.http_address;
+    let network = config.network;
+    let sync_interval = config.sync_interval();
+
+    if network == Network::Bitcoin {
+        mainnet::check(&config, opts.seed_password.as_deref())?;
+    }
+
+    ten_ten_one::config::set_network(network)?;
+    ten_ten_one::config::set_chain_backends(config.chain_backends.clone())?;
+    if let Some(seed_password) = opts.seed_password.clone() {
+        ten_ten_one::config::set_seed_password(seed_password)?;
+    }
+    if let Some(seed_passphrase) = opts.seed_passphrase.clone() {
+        ten_ten_one::config::set_seed_passphrase(seed_passphrase);
+    }
 
     logger::init_tracing(LevelFilter::DEBUG, false)?;
     wallet::init_wallet(path.as_path())?;
 
-    let network = ten_ten_one::config::network();
     db::init_db(&path.join(network.to_string()).join("maker.sqlite"))
         .await
         .expect("maker db to initialise");
@@ -31,8 +77,18 @@ async fn main() -> Result<()> {
     let connection = db::acquire().await.unwrap();
     tracing::info!(?connection);
 
+    tracing::info!(limits = ?config.risk, "Using risk limits");
+    let risk = Arc::new(RiskManager::new(config.risk, cfd::load_all().await?));
+    wallet::set_custom_output_filter(risk.clone());
+
+    let monitor = Arc::new(Monitor::new(sync_interval));
+
+    let channel_funder = Arc::new(ChannelFunder::new(config.channel_funding));
+    channel_funder.watch_pending_opens();
+
+    let sync_monitor = monitor.clone();
     tokio::spawn(async move {
-        let (_tcp_handle, _background_processor) = wallet::run_ldk_server(lightning_p2p_address)
+        wallet::run_ldk_server(lightning_p2p_address)
             .await
             .expect("lightning node to run");
 
@@ -48,63 +104,133 @@ async fn main() -> Result<()> {
         loop {
             let started = Instant::now();
 
-            if let Err(e) = wallet::sync() {
+            let sync_result = wallet::sync();
+            if let Err(e) = &sync_result {
                 tracing::error!("Wallet sync failed: {e:#}");
             }
+            sync_monitor.record_sync(&sync_result);
 
             let wallet_result = wallet::get_balance();
             let duration = started.elapsed();
+            metrics::record_sync(duration, sync_result.is_ok());
             let sync_time_in_seconds = duration.as_secs();
             match wallet_result {
-                Ok(balance) => tracing::info!(?balance, sync_time_in_seconds, "Current balance"),
+                Ok(balance) => {
+                    metrics::record_balance(&balance);
+                    tracing::info!(?balance, sync_time_in_seconds, "Current balance")
+                }
                 Err(e) => {
                     tracing::error!(sync_time_in_seconds, "Could not retrieve balance: {e:#}")
                 }
             }
-            let sync_time = match network {
-                Network::Bitcoin => 5 * 60,
-                Network::Testnet => 2 * 60,
-                Network::Signet => 60,
-                Network::Regtest => 30,
-            };
-            tokio::time::sleep(Duration::from_secs(sync_time)).await;
+            tokio::time::sleep(sync_interval).await;
         }
     });
 
-    let (_, quote_receiver) = bitmex::subscribe()?;
-
-    let (spread_sender, spread_receiver) = watch::channel(SpreadPrice::new(15));
-
+    let (_, quote_receiver) = match config.feed.source {
+        FeedSource::Bitmex => bitmex::subscribe(config.feed.network)?,
+    };
+
+    let (_, volatility_receiver) = volatility::track(quote_receiver.clone());
+
+    if let Some(kind) = config.hedging.exchange {
+        let exchange: Arc<dyn Exchange> = match kind {
+            ExchangeKind::Paper => Arc::new(PaperExchange::new(quote_receiver.clone())),
+            ExchangeKind::Bitmex => Arc::new(BitmexExchange::new(
+                network,
+                opts.bitmex_api_key
+                    .clone()
+                    .context("Hedging on BitMEX requires an API key")?,
+                opts.bitmex_api_secret
+                    .clone()
+                    .context("Hedging on BitMEX requires an API secret")?,
+            )),
+        };
+        hedging::spawn(exchange, risk.subscribe(), config.hedging.threshold);
+    }
+
+    tracing::info!(spread_model = ?config.spread, "Using spread model");
+    let (spread_sender, spread_receiver) = watch::channel(config.spread.clone());
+
+    p2p::broadcast_offers(
+        quote_receiver.clone(),
+        spread_receiver.clone(),
+        volatility_receiver.clone(),
+        risk.clone(),
+    );
+    p2p::handle_requests(
+        quote_receiver.clone(),
+        spread_receiver.clone(),
+        volatility_receiver.clone(),
+        risk.clone(),
+        channel_funder.clone(),
+    );
+
+    // We don't run behind a proxy, hence clients must not choose the IP we see with `X-Real-IP`
     let figment = rocket::Config::figment()
         .merge(("address", http_address.ip()))
-        .merge(("port", http_address.port()));
+        .merge(("port", http_address.port()))
+        .merge(("ip_header", false));
+
+    if config.admin_tokens.is_empty() {
+        tracing::warn!("No admin token configured, the admin API is unavailable");
+    }
+
+    let mut admin_routes = rocket::routes![
+        routes::post_close_channel,
+        routes::post_pay_invoice,
+        routes::post_send_to_address,
+        routes::get_new_invoice,
+        routes::get_wallet_details,
+        routes::get_channel_details,
+        routes::get_node_info,
+        routes::get_spread,
+        routes::get_risk,
+        routes::get_cfds,
+        routes::get_export,
+    ];
+
+    // Setting the spread is meant for testing and demos
+    if network != Network::Bitcoin {
+        admin_routes.extend(rocket::routes![routes::put_spread]);
+    }
 
     let mission_success = rocket::custom(figment)
+        .mount("/api", routes::public(network))
+        .mount("/api/admin", admin_routes)
         .mount(
-            "/api",
-            rocket::routes![
-                routes::get_offer,
-                routes::post_close_channel,
-                routes::post_open_channel,
-                routes::post_pay_invoice,
-                routes::post_send_to_address,
-                routes::get_new_invoice,
-                routes::get_wallet_details,
-                routes::get_channel_details,
-                routes::get_node_info,
-                routes::get_spread,
-                routes::put_spread,
-                routes::alive,
-                routes::get_faucet,
-            ],
+            "/",
+            rocket::routes![routes::get_metrics, routes::get_health, routes::get_ready],
+        )
+        .register(
+            "/api/admin",
+            rocket::catchers![auth::unauthorized, auth::forbidden],
         )
+        .register(
+            "/metrics",
+            rocket::catchers![auth::unauthorized, auth::forbidden],
+        )
+        .attach(HttpMetrics)
+        .manage(AdminTokens(config.admin_tokens.clone()))
+        .manage(Faucet::new(config.faucet))
+        .manage(channel_funder)
         .manage(quote_receiver)
         .manage(spread_sender)
         .manage(spread_receiver)
+        .manage(volatility_receiver)
+        .manage(risk)
+        .manage(monitor)
+        .manage(PeerInfo {
+            pubkey: wallet::node_id(),
+            peer_addr: PeerAddr::Ip(lightning_p2p_address),
+        })
         .launch()
         .await?;
 
     tracing::trace!(?mission_success, "Rocket has landed");
 
+    // Rocket stops on SIGTERM and Ctrl-C, after which we stop the node cleanly
+    wallet::shutdown().await?;
+
     Ok(())
 }
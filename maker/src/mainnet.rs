@@ -0,0 +1,132 @@
+//! Guardrails for running the maker on mainnet.
+//!
+//! Running on mainnet requires opting in, an encrypted seed and quotes from the mainnet feed.
+//! The amounts we put at risk are capped, and the faucet and demo routes are unavailable.
+
+use crate::bitmex;
+use crate::config::Config;
+use anyhow::bail;
+use anyhow::Result;
+use rocket::serde::Deserialize;
+use rocket::serde::Serialize;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct Settings {
+    /// We refuse to start on mainnet unless this is set.
+    pub enabled: bool,
+
+    /// Maximum amount in sats we fund per channel on mainnet.
+    pub max_channel_sats: u64,
+
+    /// Maximum notional in contracts of a single CFD on mainnet.
+    pub max_cfd_contracts: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_channel_sats: 200_000,
+            max_cfd_contracts: 100,
+        }
+    }
+}
+
+/// Lowers the limits of the config to the mainnet caps, limits below the caps are kept.
+pub fn apply_caps(config: &mut Config) {
+    let caps = config.mainnet;
+
+    let channel_funding = &mut config.channel_funding;
+    channel_funding.max_fund_amount = channel_funding.max_fund_amount.min(caps.max_channel_sats);
+    channel_funding.min_fund_amount = channel_funding
+        .min_fund_amount
+        .min(channel_funding.max_fund_amount);
+
+    let risk = &mut config.risk;
+    risk.max_notional_per_cfd = risk.max_notional_per_cfd.min(caps.max_cfd_contracts);
+}
+
+/// Fails with everything that is missing to run on mainnet, so we don't find out at runtime.
+pub fn check(config: &Config, seed_password: Option<&str>) -> Result<()> {
+    let mut missing = Vec::new();
+
+    if !config.mainnet.enabled {
+        missing.push("opt in with `mainnet.enabled = true`");
+    }
+    // An empty password doesn't protect the seed, e.g. from `MAKER_SEED_PASSWORD=` in a unit file
+    if seed_password.map_or(true, |password| password.trim().is_empty()) {
+        missing
+            .push("encrypt the seed with a non-empty `--seed-password` or `MAKER_SEED_PASSWORD`");
+    }
+    if config.feed.network != bitmex::Network::Mainnet {
+        missing.push("take quotes from the mainnet feed with `feed.network = \"mainnet\"`");
+    }
+
+    if !missing.is_empty() {
+        bail!("Refusing to run on mainnet, {}", missing.join(", "));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bdk::bitcoin::Network;
+
+    fn mainnet_config() -> Config {
+        let mut config = Config {
+            network: Network::Bitcoin,
+            ..Config::default()
+        };
+        config.mainnet.enabled = true;
+        config.feed.network = bitmex::Network::Mainnet;
+
+        config
+    }
+
+    #[test]
+    fn accepts_complete_mainnet_config() {
+        assert!(check(&mainnet_config(), Some("s3cr3t")).is_ok());
+    }
+
+    #[test]
+    fn lists_everything_missing_for_mainnet() {
+        let mut config = mainnet_config();
+        config.mainnet.enabled = false;
+        config.feed.network = bitmex::Network::Testnet;
+
+        let error = check(&config, None).unwrap_err().to_string();
+
+        assert!(error.contains("mainnet.enabled"));
+        assert!(error.contains("MAKER_SEED_PASSWORD"));
+        assert!(error.contains("feed.network"));
+    }
+
+    #[test]
+    fn rejects_empty_seed_password() {
+        for password in ["", "  "] {
+            let error = check(&mainnet_config(), Some(password))
+                .unwrap_err()
+                .to_string();
+
+            assert!(error.contains("MAKER_SEED_PASSWORD"));
+        }
+    }
+
+    #[test]
+    fn caps_limits_on_mainnet() {
+        let mut config = mainnet_config();
+        config.channel_funding.max_fund_amount = 1_000_000;
+        config.risk.max_notional_per_cfd = 50;
+
+        apply_caps(&mut config);
+
+        assert_eq!(
+            config.channel_funding.max_fund_amount,
+            Settings::default().max_channel_sats
+        );
+        assert_eq!(config.risk.max_notional_per_cfd, 50);
+    }
+}
@@ -0,0 +1,357 @@
+//! Prometheus metrics, served on `/metrics`.
+//!
+//! Metric names follow `maker_<subsystem>_<name>_<unit>`:
+//!
+//! - the subsystem is one of `wallet`, `channel`, `cfd`, `quote`, `payments` and `http`,
+//! - amounts are in base units, i.e. `sats`, `contracts` and `seconds`,
+//! - counters end in `_total`,
+//! - labels only take values from a fixed set, so the number of series stays bounded.
+
+use crate::bitmex::Quote;
+use crate::risk::Exposure;
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use prometheus::Encoder;
+use prometheus::Gauge;
+use prometheus::HistogramOpts;
+use prometheus::HistogramVec;
+use prometheus::IntCounter;
+use prometheus::IntCounterVec;
+use prometheus::IntGauge;
+use prometheus::IntGaugeVec;
+use prometheus::Opts;
+use prometheus::Registry;
+use prometheus::TextEncoder;
+use rocket::fairing::Fairing;
+use rocket::fairing::Info;
+use rocket::fairing::Kind;
+use rocket::Data;
+use rocket::Request;
+use rocket::Response;
+use std::time::Duration;
+use std::time::Instant;
+use ten_ten_one::wallet;
+use ten_ten_one::wallet::Balance;
+use time::OffsetDateTime;
+
+/// Our metrics, registered with a registry.
+///
+/// The maker uses the ones in the default registry, tests their own.
+struct Metrics {
+    wallet_balance: IntGaugeVec,
+    wallet_sync_duration: Gauge,
+    wallet_sync_failures: IntCounter,
+    channels: IntGaugeVec,
+    channel_capacity: IntGaugeVec,
+    cfds_open: IntGauge,
+    cfd_notional: IntGaugeVec,
+    cfd_net_exposure: IntGauge,
+    quote_age: Gauge,
+    payments: IntCounterVec,
+    http_request_duration: HistogramVec,
+}
+
+static METRICS: Lazy<Metrics> =
+    Lazy::new(|| Metrics::new(prometheus::default_registry()).expect("valid metrics"));
+
+impl Metrics {
+    fn new(registry: &Registry) -> Result<Self> {
+        let metrics = Self {
+            wallet_balance: IntGaugeVec::new(
+                Opts::new(
+                    "maker_wallet_balance_sats",
+                    "Balance of the wallet by layer and state.",
+                ),
+                &["layer", "state"],
+            )?,
+            wallet_sync_duration: Gauge::new(
+                "maker_wallet_sync_duration_seconds",
+                "Duration of the last wallet sync.",
+            )?,
+            wallet_sync_failures: IntCounter::new(
+                "maker_wallet_sync_failures_total",
+                "Wallet syncs that failed.",
+            )?,
+            channels: IntGaugeVec::new(
+                Opts::new(
+                    "maker_channel_count",
+                    "Lightning channels by whether they are usable.",
+                ),
+                &["usable"],
+            )?,
+            channel_capacity: IntGaugeVec::new(
+                Opts::new(
+                    "maker_channel_capacity_sats",
+                    "Summed capacity of our Lightning channels by direction.",
+                ),
+                &["direction"],
+            )?,
+            cfds_open: IntGauge::new("maker_cfd_open_count", "CFDs that are open.")?,
+            cfd_notional: IntGaugeVec::new(
+                Opts::new(
+                    "maker_cfd_notional_contracts",
+                    "Notional of our open CFDs by our position.",
+                ),
+                &["position"],
+            )?,
+            cfd_net_exposure: IntGauge::new(
+                "maker_cfd_net_exposure_contracts",
+                "Net exposure from our open CFDs, positive if we are long.",
+            )?,
+            quote_age: Gauge::new(
+                "maker_quote_age_seconds",
+                "Time since we received the latest quote, zero before the first quote.",
+            )?,
+            payments: IntCounterVec::new(
+                Opts::new(
+                    "maker_payments_total",
+                    "Payments we made by kind and whether we managed to send them.",
+                ),
+                &["kind", "result"],
+            )?,
+            http_request_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "maker_http_request_duration_seconds",
+                    "Time until we responded to HTTP requests, by route.",
+                ),
+                &["method", "route", "status"],
+            )?,
+        };
+
+        registry.register(Box::new(metrics.wallet_balance.clone()))?;
+        registry.register(Box::new(metrics.wallet_sync_duration.clone()))?;
+        registry.register(Box::new(metrics.wallet_sync_failures.clone()))?;
+        registry.register(Box::new(metrics.channels.clone()))?;
+        registry.register(Box::new(metrics.channel_capacity.clone()))?;
+        registry.register(Box::new(metrics.cfds_open.clone()))?;
+        registry.register(Box::new(metrics.cfd_notional.clone()))?;
+        registry.register(Box::new(metrics.cfd_net_exposure.clone()))?;
+        registry.register(Box::new(metrics.quote_age.clone()))?;
+        registry.register(Box::new(metrics.payments.clone()))?;
+        registry.register(Box::new(metrics.http_request_duration.clone()))?;
+
+        Ok(metrics)
+    }
+
+    fn record_sync(&self, duration: Duration, success: bool) {
+        self.wallet_sync_duration.set(duration.as_secs_f64());
+        if !success {
+            self.wallet_sync_failures.inc();
+        }
+    }
+
+    fn record_payment<T>(&self, kind: PaymentKind, result: &Result<T>) {
+        let result = if result.is_ok() { "success" } else { "failure" };
+
+        self.payments
+            .with_label_values(&[&kind.to_string(), result])
+            .inc();
+    }
+
+    fn record_exposure(&self, exposure: &Exposure) {
+        self.cfds_open.set(exposure.open_positions as i64);
+        self.cfd_notional
+            .with_label_values(&["long"])
+            .set(exposure.long as i64);
+        self.cfd_notional
+            .with_label_values(&["short"])
+            .set(exposure.short as i64);
+        self.cfd_net_exposure.set(exposure.net);
+    }
+}
+
+/// The payments we count in `maker_payments_total`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum_macros::Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum PaymentKind {
+    Lightning,
+    OnChain,
+    Faucet,
+    ChannelFunding,
+}
+
+pub fn record_balance(balance: &Balance) {
+    let on_chain = &balance.on_chain;
+    let off_chain = &balance.off_chain;
+
+    for (layer, state, sats) in [
+        ("on_chain", "confirmed", on_chain.confirmed),
+        ("on_chain", "trusted_pending", on_chain.trusted_pending),
+        ("on_chain", "untrusted_pending", on_chain.untrusted_pending),
+        ("off_chain", "available", off_chain.available),
+        ("off_chain", "pending_close", off_chain.pending_close),
+    ] {
+        METRICS
+            .wallet_balance
+            .with_label_values(&[layer, state])
+            .set(sats as i64);
+    }
+}
+
+pub fn record_sync(duration: Duration, success: bool) {
+    METRICS.record_sync(duration, success);
+}
+
+/// Counts a payment, a Lightning payment succeeds once it has been sent off.
+pub fn record_payment<T>(kind: PaymentKind, result: &Result<T>) {
+    METRICS.record_payment(kind, result);
+}
+
+/// Renders all metrics in the Prometheus text format.
+///
+/// Metrics that are cheap to compute are updated on every scrape.
+pub fn render(exposure: &Exposure, quote: Option<Quote>) -> Result<String> {
+    record_channels();
+    METRICS.record_exposure(exposure);
+    record_quote(quote, OffsetDateTime::now_utc());
+
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&prometheus::gather(), &mut buffer)?;
+
+    Ok(String::from_utf8(buffer)?)
+}
+
+fn record_channels() {
+    let channels = wallet::get_channel_manager().list_channels();
+
+    let usable = channels.iter().filter(|channel| channel.is_usable).count();
+    METRICS
+        .channels
+        .with_label_values(&["true"])
+        .set(usable as i64);
+    METRICS
+        .channels
+        .with_label_values(&["false"])
+        .set((channels.len() - usable) as i64);
+
+    let outbound_msat = channels
+        .iter()
+        .map(|channel| channel.outbound_capacity_msat)
+        .sum::<u64>();
+    let inbound_msat = channels
+        .iter()
+        .map(|channel| channel.inbound_capacity_msat)
+        .sum::<u64>();
+    METRICS
+        .channel_capacity
+        .with_label_values(&["outbound"])
+        .set((outbound_msat / 1000) as i64);
+    METRICS
+        .channel_capacity
+        .with_label_values(&["inbound"])
+        .set((inbound_msat / 1000) as i64);
+}
+
+fn record_quote(quote: Option<Quote>, now: OffsetDateTime) {
+    if let Some(quote) = quote {
+        METRICS
+            .quote_age
+            .set((now - quote.timestamp).as_seconds_f64().max(0.0));
+    }
+}
+
+/// Records how long it takes us to respond to HTTP requests.
+pub struct HttpMetrics;
+
+/// When we received the request.
+struct RequestStart(Instant);
+
+#[rocket::async_trait]
+impl Fairing for HttpMetrics {
+    fn info(&self) -> Info {
+        Info {
+            name: "HTTP metrics",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        request.local_cache(|| RequestStart(Instant::now()));
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let RequestStart(started) = request.local_cache(|| RequestStart(Instant::now()));
+
+        // The route's URI keeps its placeholders, e.g. `/api/faucet/<address>`
+        let route = request
+            .route()
+            .map(|route| route.uri.to_string())
+            .unwrap_or_else(|| "unmatched".to_owned());
+
+        METRICS
+            .http_request_duration
+            .with_label_values(&[
+                request.method().as_str(),
+                &route,
+                &response.status().code.to_string(),
+            ])
+            .observe(started.elapsed().as_secs_f64());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::risk::Limits;
+
+    fn exposure(long: u64, short: u64) -> Exposure {
+        Exposure {
+            long,
+            short,
+            net: long as i64 - short as i64,
+            open_positions: 2,
+            pending_orders: 0,
+            takers: Vec::new(),
+            limits: Limits::default(),
+        }
+    }
+
+    #[test]
+    fn records_exposure() {
+        let metrics = Metrics::new(&Registry::new()).unwrap();
+
+        metrics.record_exposure(&exposure(300, 100));
+
+        assert_eq!(metrics.cfds_open.get(), 2);
+        assert_eq!(metrics.cfd_notional.with_label_values(&["long"]).get(), 300);
+        assert_eq!(
+            metrics.cfd_notional.with_label_values(&["short"]).get(),
+            100
+        );
+        assert_eq!(metrics.cfd_net_exposure.get(), 200);
+    }
+
+    #[test]
+    fn counts_payments_by_result() {
+        let metrics = Metrics::new(&Registry::new()).unwrap();
+
+        metrics.record_payment::<()>(PaymentKind::Faucet, &Err(anyhow::anyhow!("no funds")));
+
+        assert_eq!(
+            metrics
+                .payments
+                .with_label_values(&["faucet", "failure"])
+                .get(),
+            1
+        );
+    }
+
+    #[test]
+    fn metric_names_follow_scheme() {
+        let registry = Registry::new();
+        let metrics = Metrics::new(&registry).unwrap();
+        metrics.record_exposure(&exposure(0, 0));
+        metrics.record_sync(Duration::from_secs(1), true);
+
+        for family in registry.gather() {
+            let name = family.get_name();
+            assert!(name.starts_with("maker_"), "{name} lacks the maker prefix");
+
+            let subsystem = name.split('_').nth(1).unwrap();
+            assert!(
+                ["wallet", "channel", "cfd", "quote", "payments", "http"].contains(&subsystem),
+                "{name} has unknown subsystem"
+            );
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use crate::bitmex::Quote;
+use crate::spread::Spread;
+use crate::spread::SpreadModel;
+use anyhow::Result;
+use maker_api::Offer;
+use maker_api::PriceTier;
+use rust_decimal::Decimal;
+
+/// Quotes every size tier of the spread model at the given leverage.
+pub fn new(
+    quote: Quote,
+    model: &SpreadModel,
+    leverage: Decimal,
+    exposure: Decimal,
+    volatility: Decimal,
+) -> Result<Offer> {
+    let tiers = model
+        .tiers
+        .iter()
+        .map(|tier| {
+            let spread = model.spread(tier.max_quantity, leverage, exposure, volatility)?;
+            let (bid, ask) = apply(quote, spread);
+
+            Ok(PriceTier {
+                max_quantity: tier.max_quantity,
+                bid,
+                ask,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let (bid, ask) = tiers
+        .first()
+        .map(|tier| (tier.bid, tier.ask))
+        .unwrap_or((quote.bid, quote.ask));
+
+    Ok(Offer {
+        bid,
+        ask,
+        index: quote.index,
+        tiers,
+    })
+}
+
+/// Applies the spread to the quote, returning bid and ask.
+pub fn apply(quote: Quote, spread: Spread) -> (Decimal, Decimal) {
+    (
+        quote.bid * (Decimal::ONE - spread.bid),
+        quote.ask * (Decimal::ONE + spread.ask),
+    )
+}
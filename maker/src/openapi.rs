@@ -0,0 +1,154 @@
+//! The OpenAPI document of our public API, served on `/api/openapi.json`.
+//!
+//! The request and response types are shared with the taker through the `maker-api` crate.
+
+use crate::routes;
+use maker_api::error::ErrorCode;
+use maker_api::error::Problem;
+use maker_api::Offer;
+use maker_api::OpenChannelRequest;
+use maker_api::OpenChannelResponse;
+use maker_api::PeerInfo;
+use maker_api::PriceTier;
+use utoipa::OpenApi;
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        routes::get_offer,
+        routes::get_offer_stream,
+        routes::post_open_channel,
+        routes::alive,
+        routes::get_faucet,
+    ),
+    components(schemas(
+        Offer,
+        PriceTier,
+        OpenChannelRequest,
+        OpenChannelResponse,
+        PeerInfo,
+        Problem,
+        ErrorCode
+    ))
+)]
+pub struct ApiDoc;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitmex::Quote;
+    use crate::channel;
+    use crate::channel::ChannelFunder;
+    use crate::faucet;
+    use crate::faucet::Faucet;
+    use crate::risk::Limits;
+    use crate::risk::RiskManager;
+    use crate::spread::SpreadModel;
+    use bdk::bitcoin::secp256k1::PublicKey;
+    use bdk::bitcoin::secp256k1::Secp256k1;
+    use bdk::bitcoin::secp256k1::SecretKey;
+    use bdk::bitcoin::Network;
+    use maker_api::path;
+    use maker_api::PeerAddr;
+    use rocket::http::ContentType;
+    use rocket::http::Status;
+    use rocket::local::asynchronous::Client;
+    use rust_decimal::Decimal;
+    use std::sync::Arc;
+    use tokio::sync::watch;
+
+    const UNMATCHED: &str = "No route matched";
+
+    /// Tells requests no route matched from our handlers responding with 404.
+    #[rocket::catch(404)]
+    fn unmatched() -> &'static str {
+        UNMATCHED
+    }
+
+    #[test]
+    fn documents_the_paths_the_taker_uses() {
+        let document = serde_json::to_value(ApiDoc::openapi()).unwrap();
+
+        for path in [
+            path::OFFER,
+            path::OFFER_STREAM,
+            path::OPEN_CHANNEL,
+            path::ALIVE,
+            path::FAUCET,
+        ] {
+            assert!(
+                document["paths"].get(path).is_some(),
+                "{path} is not documented"
+            );
+        }
+    }
+
+    #[rocket::async_test]
+    async fn serves_the_documented_paths_the_taker_uses() {
+        let (_, quote_receiver) = watch::channel(None::<Quote>);
+        let (_, spread_receiver) = watch::channel(SpreadModel::default());
+        let (_, volatility_receiver) = watch::channel(Decimal::ZERO);
+        let rocket = rocket::build()
+            .mount("/api", routes::public(Network::Testnet))
+            .register("/", rocket::catchers![unmatched])
+            .manage(Faucet::new(faucet::Settings::default()))
+            .manage(Arc::new(ChannelFunder::new(channel::Settings::default())))
+            .manage(quote_receiver)
+            .manage(spread_receiver)
+            .manage(volatility_receiver)
+            .manage(Arc::new(RiskManager::new(Limits::default(), Vec::new())))
+            .manage(PeerInfo {
+                pubkey: PublicKey::from_secret_key(
+                    &Secp256k1::new(),
+                    &SecretKey::from_slice(&[1; 32]).unwrap(),
+                ),
+                peer_addr: PeerAddr::Ip("127.0.0.1:9045".parse().unwrap()),
+            });
+        let client = Client::untracked(rocket).await.unwrap();
+        let document = serde_json::to_value(ApiDoc::openapi()).unwrap();
+
+        for path in [
+            path::OFFER,
+            path::OFFER_STREAM,
+            path::OPEN_CHANNEL,
+            path::ALIVE,
+            path::FAUCET,
+        ] {
+            let operations = document["paths"][path]
+                .as_object()
+                .unwrap_or_else(|| panic!("{path} is not documented"));
+            // Parameters and bodies our handlers reject before they touch the wallet
+            let uri = path.replace("{address}", "not-an-address");
+
+            for method in operations.keys() {
+                let request = match method.as_str() {
+                    "get" => client.get(uri.clone()),
+                    "post" => client
+                        .post(uri.clone())
+                        .header(ContentType::JSON)
+                        .body("{}"),
+                    method => panic!("{method} {path} is not covered"),
+                };
+                let response = request
+                    .remote("127.0.0.1:8000".parse().unwrap())
+                    .dispatch()
+                    .await;
+
+                // Only reading the body of a 404, the offer stream never ends
+                let unmatched = response.status() == Status::NotFound
+                    && response.into_string().await.as_deref() == Some(UNMATCHED);
+                assert!(!unmatched, "{method} {path} is documented, but not served");
+            }
+        }
+    }
+
+    #[test]
+    fn documents_the_offer() {
+        let document = serde_json::to_value(ApiDoc::openapi()).unwrap();
+
+        let offer = &document["components"]["schemas"]["Offer"]["properties"];
+        for property in ["bid", "ask", "index", "tiers"] {
+            assert!(offer.get(property).is_some(), "Offer misses {property}");
+        }
+    }
+}
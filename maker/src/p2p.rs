@@ -0,0 +1,367 @@
+//! Trading with takers over our custom Lightning messages.
+
+use crate::bitmex::Quote;
+use crate::channel::ChannelFunder;
+use crate::health::MAX_QUOTE_AGE;
+use crate::offer;
+use crate::problem;
+use crate::risk::RiskManager;
+use crate::spread::SpreadModel;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use maker_api::error::ErrorCode;
+use maker_api::error::Problem;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use std::sync::Arc;
+use std::time::Duration;
+use ten_ten_one::cfd::models::Order;
+use ten_ten_one::cfd::models::Position;
+use ten_ten_one::message::Message;
+use ten_ten_one::message::RequestId;
+use ten_ten_one::wallet;
+use tokio::sync::broadcast;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+/// Minimum time between two offer broadcasts, so we don't flood our peers on a busy market.
+const OFFER_BROADCAST_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Broadcasts our offer to all connected peers whenever the quote, the spread model or the
+/// volatility changes.
+///
+/// Takers receive the prices of the smallest size tier without leverage. We don't broadcast while
+/// the quote is stale, e.g. when the spread model changes after the price feed stopped.
+pub fn broadcast_offers(
+    mut quote_receiver: watch::Receiver<Option<Quote>>,
+    mut spread_receiver: watch::Receiver<SpreadModel>,
+    mut volatility_receiver: watch::Receiver<Decimal>,
+    risk: Arc<RiskManager>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let quote = *quote_receiver.borrow_and_update();
+            let model = spread_receiver.borrow_and_update().clone();
+            let volatility = *volatility_receiver.borrow_and_update();
+
+            match quote {
+                Some(quote) if quote.is_older_than(MAX_QUOTE_AGE) => {
+                    tracing::warn!(
+                        timestamp = %quote.timestamp,
+                        "Not broadcasting offer for stale quote"
+                    );
+                }
+                Some(quote) => {
+                    let exposure = risk.net_exposure();
+                    match offer::new(quote, &model, Decimal::ONE, exposure, volatility) {
+                        Ok(offer) => broadcast(Message::Offer(offer.into())),
+                        Err(e) => tracing::error!("Failed to create offer: {e:#}"),
+                    }
+                }
+                None => {}
+            }
+
+            tokio::time::sleep(OFFER_BROADCAST_INTERVAL).await;
+
+            tokio::select! {
+                Ok(()) = quote_receiver.changed() => {},
+                Ok(()) = spread_receiver.changed() => {},
+                Ok(()) = volatility_receiver.changed() => {},
+                else => {
+                    tracing::error!("Stopped broadcasting offers");
+                    return;
+                },
+            }
+        }
+    })
+}
+
+fn broadcast(message: Message) {
+    let peers = match wallet::get_peer_manager() {
+        Ok(peer_manager) => peer_manager.get_peer_node_ids(),
+        Err(e) => {
+            tracing::error!("Failed to get connected peers: {e:#}");
+            return;
+        }
+    };
+
+    for peer in peers {
+        wallet::send_custom_message(peer, message.clone());
+    }
+}
+
+/// Answers the requests takers send us over the Lightning connection.
+pub fn handle_requests(
+    quote_receiver: watch::Receiver<Option<Quote>>,
+    spread_receiver: watch::Receiver<SpreadModel>,
+    volatility_receiver: watch::Receiver<Decimal>,
+    risk: Arc<RiskManager>,
+    channel_funder: Arc<ChannelFunder>,
+) -> JoinHandle<()> {
+    let mut requests = wallet::subscribe_to_custom_messages();
+
+    tokio::spawn(async move {
+        loop {
+            let (taker, request) = match requests.recv().await {
+                Ok(request) => request,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    tracing::warn!(skipped, "Dropped requests from takers");
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => {
+                    tracing::error!("Stopped handling requests from takers");
+                    return;
+                }
+            };
+
+            let response = match request {
+                Message::OrderRequest(id, order) => {
+                    let filled = order_price(
+                        &order,
+                        &quote_receiver,
+                        &spread_receiver,
+                        &volatility_receiver,
+                        &risk,
+                    )
+                    .and_then(|price| fill(id.is_some(), order, price))
+                    .and_then(|filled| risk.reserve(taker, filled).map(|()| filled));
+                    match filled {
+                        Ok(filled) => {
+                            tracing::info!(%taker, ?order, ?filled, "Accepting order");
+                            // Takers of older versions fill at the price they sent
+                            let price = id.map(|_| filled.open_price);
+                            Message::OrderAccepted(id, price)
+                        }
+                        Err(e) => {
+                            tracing::info!(%taker, ?order, "Rejecting order: {e:#}");
+                            reject(id, ErrorCode::Unknown, &e)
+                        }
+                    }
+                }
+                Message::OpenChannelRequest(id, request) if request.taker_node_id != taker => {
+                    tracing::warn!(%taker, ?request, "Refusing to fund channel for another node");
+                    Message::Reject(
+                        id,
+                        rejection(
+                            ErrorCode::ChannelFundingRefused,
+                            "Can only fund channels opened by the requester".to_owned(),
+                        ),
+                    )
+                }
+                Message::OpenChannelRequest(id, request) => {
+                    match channel_funder.fund(&request).await {
+                        Ok(response) => {
+                            tracing::info!(%taker, ?request, ?response, "Funded channel open");
+                            Message::OpenChannelResponse(id, response)
+                        }
+                        Err(e) => {
+                            tracing::error!(%taker, ?request, "Failed to fund channel open: {e:#}");
+                            reject(id, ErrorCode::ChannelFundingRefused, &e)
+                        }
+                    }
+                }
+                // We close the CFD once the taker removes its custom output from our channel,
+                // not on the taker's word
+                Message::CfdSettled(custom_output_id) => {
+                    tracing::debug!(%taker, custom_output_id, "Taker reports settled CFD");
+                    continue;
+                }
+                message => {
+                    tracing::warn!(%taker, ?message, "Ignoring unexpected message");
+                    continue;
+                }
+            };
+
+            wallet::send_custom_message(taker, response);
+        }
+    })
+}
+
+/// Our current bid and ask for an order of this size and leverage.
+fn order_price(
+    order: &Order,
+    quote_receiver: &watch::Receiver<Option<Quote>>,
+    spread_receiver: &watch::Receiver<SpreadModel>,
+    volatility_receiver: &watch::Receiver<Decimal>,
+    risk: &RiskManager,
+) -> Result<(Decimal, Decimal)> {
+    let quote = match *quote_receiver.borrow() {
+        Some(quote) if quote.is_older_than(MAX_QUOTE_AGE) => {
+            let detail = format!("Our latest quote is from {}", quote.timestamp);
+            bail!(rejection(ErrorCode::QuoteStale, detail));
+        }
+        Some(quote) => quote,
+        None => bail!(rejection(
+            ErrorCode::QuoteUnavailable,
+            "No quotes found".to_owned()
+        )),
+    };
+    let volatility = *volatility_receiver.borrow();
+
+    let quantity = u64::try_from(order.quantity).context("Invalid order quantity")?;
+    let leverage = Decimal::from(order.leverage);
+
+    let spread =
+        spread_receiver
+            .borrow()
+            .spread(quantity, leverage, risk.net_exposure(), volatility)?;
+
+    Ok(offer::apply(quote, spread))
+}
+
+/// Rejects the request with the problem behind the error, or with the code if there is none.
+fn reject(id: Option<RequestId>, code: ErrorCode, e: &anyhow::Error) -> Message {
+    let problem = e
+        .downcast_ref::<Problem>()
+        .cloned()
+        .unwrap_or_else(|| rejection(code, format!("{e:#}")));
+
+    Message::Reject(id, problem)
+}
+
+/// A rejection with the status the code has on our HTTP API.
+fn rejection(code: ErrorCode, detail: String) -> Problem {
+    Problem {
+        code,
+        status: Some(problem::status(code).as_u16()),
+        title: None,
+        detail: Some(detail),
+    }
+}
+
+/// The order as we fill it: at our current bid or ask if the taker sent its price as a limit, at
+/// the taker's price otherwise.
+fn fill(price_is_limit: bool, order: Order, (bid, ask): (Decimal, Decimal)) -> Result<Order> {
+    check_order(&order, (bid, ask))?;
+    if !price_is_limit {
+        return Ok(order);
+    }
+
+    let price = match order.position {
+        Position::Long => ask,
+        Position::Short => bid,
+    };
+    let open_price = price.to_f64().context("Price out of range")?;
+
+    Ok(Order {
+        open_price,
+        ..order
+    })
+}
+
+/// Checks that the taker wants to trade at our current bid or ask, or at a price worse for the
+/// taker, e.g. because our offer moved in its favour while it was submitting the order.
+fn check_order(order: &Order, (bid, ask): (Decimal, Decimal)) -> Result<()> {
+    let price = Decimal::try_from(order.open_price).context("Invalid order price")?;
+
+    match order.position {
+        // The taker buys from us at our ask
+        Position::Long => {
+            if price < ask {
+                bail!("Price {price} is below our ask of {ask}");
+            }
+        }
+        // The taker sells to us at our bid
+        Position::Short => {
+            if price > bid {
+                bail!("Price {price} is above our bid of {bid}");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitmex;
+    use crate::risk::Limits;
+    use rust_decimal_macros::dec;
+    use ten_ten_one::cfd::models::ContractSymbol;
+    use time::OffsetDateTime;
+
+    fn order(position: Position, open_price: f64) -> Order {
+        Order {
+            leverage: 2,
+            quantity: 100,
+            contract_symbol: ContractSymbol::BtcUsd,
+            position,
+            open_price,
+        }
+    }
+
+    fn offer() -> (Decimal, Decimal) {
+        (dec!(16000), dec!(16100))
+    }
+
+    #[test]
+    fn accepts_orders_at_our_offer() {
+        assert!(check_order(&order(Position::Long, 16100.0), offer()).is_ok());
+        assert!(check_order(&order(Position::Short, 16000.0), offer()).is_ok());
+    }
+
+    #[test]
+    fn accepts_orders_worse_for_the_taker() {
+        assert!(check_order(&order(Position::Long, 16150.0), offer()).is_ok());
+        assert!(check_order(&order(Position::Short, 15950.0), offer()).is_ok());
+    }
+
+    #[test]
+    fn rejects_orders_just_inside_our_offer() {
+        assert!(check_order(&order(Position::Long, 16099.5), offer()).is_err());
+        assert!(check_order(&order(Position::Short, 16000.5), offer()).is_err());
+    }
+
+    #[test]
+    fn fills_limit_orders_at_our_offer() {
+        let long = fill(true, order(Position::Long, 16150.0), offer()).unwrap();
+        let short = fill(true, order(Position::Short, 15950.0), offer()).unwrap();
+
+        assert_eq!(long.open_price, 16100.0);
+        assert_eq!(short.open_price, 16000.0);
+    }
+
+    #[test]
+    fn fills_orders_of_older_takers_at_their_price() {
+        let long = fill(false, order(Position::Long, 16150.0), offer()).unwrap();
+
+        assert_eq!(long.open_price, 16150.0);
+    }
+
+    #[test]
+    fn rejects_orders_far_inside_our_offer() {
+        assert!(check_order(&order(Position::Long, 15000.0), offer()).is_err());
+        assert!(check_order(&order(Position::Short, 17000.0), offer()).is_err());
+    }
+
+    #[test]
+    fn rejects_orders_on_stale_quotes() {
+        let quote = Quote {
+            timestamp: OffsetDateTime::now_utc() - MAX_QUOTE_AGE - time::Duration::seconds(1),
+            bid: dec!(16000),
+            ask: dec!(16100),
+            index: dec!(16050),
+            symbol: bitmex::ContractSymbol::BtcUsd,
+        };
+        let (_quote_sender, quote_receiver) = watch::channel(Some(quote));
+        let (_spread_sender, spread_receiver) = watch::channel(SpreadModel::default());
+        let (_volatility_sender, volatility_receiver) = watch::channel(Decimal::ZERO);
+        let risk = RiskManager::new(Limits::default(), Vec::new());
+
+        let e = order_price(
+            &order(Position::Long, 16100.0),
+            &quote_receiver,
+            &spread_receiver,
+            &volatility_receiver,
+            &risk,
+        )
+        .unwrap_err();
+
+        match reject(None, ErrorCode::Unknown, &e) {
+            Message::Reject(_, problem) => assert_eq!(problem.code, ErrorCode::QuoteStale),
+            message => panic!("Unexpected message {message:?}"),
+        }
+    }
+}
@@ -0,0 +1,71 @@
+//! Our error catalogue: every error response carries a stable [`ErrorCode`] as its `type`, and
+//! every code has a fixed status.
+
+use http_api_problem::HttpApiProblem;
+use http_api_problem::StatusCode;
+use maker_api::error::ErrorCode;
+
+/// Creates a problem with the code, add a `title` and `detail` for humans.
+pub fn problem(code: ErrorCode) -> HttpApiProblem {
+    HttpApiProblem::new(status(code)).type_url(code.to_string())
+}
+
+pub fn status(code: ErrorCode) -> StatusCode {
+    match code {
+        ErrorCode::InvalidAddress
+        | ErrorCode::InvalidNodeId
+        | ErrorCode::ChannelFundingRefused
+        | ErrorCode::TakerNotConnected
+        | ErrorCode::InvalidSpread => StatusCode::BAD_REQUEST,
+        ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+        ErrorCode::Forbidden => StatusCode::FORBIDDEN,
+        ErrorCode::QuoteUnavailable | ErrorCode::ChannelNotFound | ErrorCode::FaucetUnavailable => {
+            StatusCode::NOT_FOUND
+        }
+        ErrorCode::FaucetLimitExceeded => StatusCode::TOO_MANY_REQUESTS,
+        ErrorCode::InsufficientFunds | ErrorCode::QuoteStale => StatusCode::SERVICE_UNAVAILABLE,
+        ErrorCode::PaymentFailed | ErrorCode::Internal | ErrorCode::Unknown => {
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Whether we failed to pay because our wallet does not hold enough coins.
+pub fn is_insufficient_funds(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        matches!(
+            cause.downcast_ref::<bdk::Error>(),
+            Some(bdk::Error::InsufficientFunds { .. })
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn problem_carries_code_as_type() {
+        let problem = problem(ErrorCode::FaucetLimitExceeded).title("Faucet limit exceeded");
+
+        let json = serde_json::to_value(&problem).unwrap();
+        let problem = serde_json::from_value::<maker_api::error::Problem>(json).unwrap();
+
+        assert_eq!(problem.code, ErrorCode::FaucetLimitExceeded);
+        assert_eq!(problem.status, Some(429));
+    }
+
+    #[test]
+    fn detects_insufficient_funds_behind_context() {
+        let e = Err::<(), _>(bdk::Error::InsufficientFunds {
+            needed: 2,
+            available: 1,
+        })
+        .context("Failed to transfer funds")
+        .unwrap_err();
+
+        assert!(is_insufficient_funds(&e));
+        assert!(!is_insufficient_funds(&anyhow::anyhow!("Failed")));
+    }
+}
@@ -0,0 +1,611 @@
+//! Keeps track of the directional risk we carry and refuses orders that would exceed our limits.
+
+use crate::cfd;
+use crate::cfd::MakerCfd;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use bdk::bitcoin::secp256k1::PublicKey;
+use rocket::serde::Deserialize;
+use rocket::serde::Serialize;
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use ten_ten_one::cfd::models::CfdState;
+use ten_ten_one::cfd::models::Order;
+use ten_ten_one::cfd::models::Position;
+use ten_ten_one::lightning::CustomOutputFilter;
+use ten_ten_one::lightning::ProposedCustomOutput;
+use time::OffsetDateTime;
+use tokio::sync::watch;
+
+/// How long the taker has to add the custom output after we accepted its order.
+const RESERVATION_TIMEOUT: time::Duration = time::Duration::minutes(1);
+
+/// Limits on the risk we are willing to take, notional amounts are in contracts.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct Limits {
+    /// Maximum notional of a single CFD.
+    pub max_notional_per_cfd: u64,
+
+    /// Maximum notional of all open CFDs with a single taker.
+    pub max_notional_per_taker: u64,
+
+    /// Maximum net exposure across all open CFDs, long or short.
+    pub max_notional: u64,
+
+    /// Maximum leverage we accept on an order.
+    pub max_leverage: i64,
+
+    /// Maximum number of open CFDs with a single taker.
+    pub max_positions_per_taker: usize,
+
+    /// Maximum number of open CFDs across all takers.
+    pub max_positions: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_notional_per_cfd: 10_000,
+            max_notional_per_taker: 10_000,
+            max_notional: 100_000,
+            max_leverage: 10,
+            max_positions_per_taker: 5,
+            max_positions: 100,
+        }
+    }
+}
+
+/// Our current exposure, as reported by the API.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Exposure {
+    /// Contracts we are long.
+    pub long: u64,
+    /// Contracts we are short.
+    pub short: u64,
+    /// Net exposure in contracts, positive if we are long.
+    pub net: i64,
+    pub open_positions: usize,
+    /// Orders we accepted but whose custom output has not been added yet.
+    pub pending_orders: usize,
+    pub takers: Vec<TakerExposure>,
+    pub limits: Limits,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct TakerExposure {
+    pub taker: PublicKey,
+    /// Notional of all open CFDs with this taker.
+    pub notional: u64,
+    pub open_positions: usize,
+}
+
+pub struct RiskManager {
+    limits: Limits,
+    book: Mutex<Book>,
+    net_exposure: watch::Sender<i64>,
+}
+
+struct Book {
+    /// Open CFDs by custom output ID.
+    open: HashMap<String, MakerCfd>,
+    /// Accepted orders, oldest first.
+    reserved: VecDeque<Reservation>,
+}
+
+struct Reservation {
+    taker: PublicKey,
+    order: Order,
+    /// The margins of taker and maker in msats the custom output has to lock up.
+    margins: (u64, u64),
+    expires: OffsetDateTime,
+}
+
+impl Reservation {
+    fn matches(&self, custom_output: &ProposedCustomOutput) -> bool {
+        let (margin_taker, margin_maker) = self.margins;
+
+        self.taker == custom_output.counterparty
+            && custom_output.remote_amount_msat == margin_taker
+            && custom_output.local_amount_msat == margin_maker
+    }
+}
+
+impl RiskManager {
+    /// Creates a risk manager tracking the given CFDs, closed ones are ignored.
+    pub fn new(limits: Limits, cfds: Vec<MakerCfd>) -> Self {
+        let open = cfds
+            .into_iter()
+            .filter(|cfd| cfd.state == CfdState::Open)
+            .map(|cfd| (cfd.custom_output_id.clone(), cfd))
+            .collect();
+
+        let book = Book {
+            open,
+            reserved: VecDeque::new(),
+        };
+        let (net_exposure, _) = watch::channel(book.net());
+
+        Self {
+            limits,
+            book: Mutex::new(book),
+            net_exposure,
+        }
+    }
+
+    /// Checks the order against our limits and reserves room for it until the taker adds the
+    /// custom output.
+    pub fn reserve(&self, taker: PublicKey, order: Order) -> Result<()> {
+        self.reserve_at(taker, order, OffsetDateTime::now_utc())
+    }
+
+    fn reserve_at(&self, taker: PublicKey, order: Order, now: OffsetDateTime) -> Result<()> {
+        let mut book = self.book.lock().expect("mutex not to be poisoned");
+        book.remove_expired(now);
+        book.check(&self.limits, taker, &order)?;
+
+        book.reserved.push_back(Reservation {
+            taker,
+            order,
+            margins: ten_ten_one::cfd::margins(&order),
+            expires: now + RESERVATION_TIMEOUT,
+        });
+
+        Ok(())
+    }
+
+    /// Turns the reservation the custom output was added for into an open CFD.
+    ///
+    /// The custom output has to come from the taker we accepted the order from and lock up the
+    /// margins of that order, otherwise it is rejected.
+    pub fn open(&self, custom_output: &ProposedCustomOutput) -> Result<MakerCfd> {
+        let mut book = self.book.lock().expect("mutex not to be poisoned");
+        book.remove_expired(OffsetDateTime::now_utc());
+
+        let custom_output_id = &custom_output.id;
+        if book.open.contains_key(custom_output_id) {
+            bail!("CFD {custom_output_id} is already open");
+        }
+
+        let index = book
+            .reserved
+            .iter()
+            .position(|reservation| reservation.matches(custom_output))
+            .with_context(|| {
+                format!(
+                    "Custom output does not match any order we accepted from {}",
+                    custom_output.counterparty
+                )
+            })?;
+        let reservation = book.reserved.remove(index).expect("index to be valid");
+
+        let cfd = MakerCfd::new(
+            custom_output_id.clone(),
+            reservation.taker,
+            &reservation.order,
+        );
+        book.open.insert(custom_output_id.clone(), cfd.clone());
+        self.net_exposure.send_replace(book.net());
+
+        Ok(cfd)
+    }
+
+    /// Stops tracking a CFD once its custom output left our channel with the taker.
+    pub fn close(&self, taker: PublicKey, custom_output_id: &str) -> Result<MakerCfd> {
+        let mut book = self.book.lock().expect("mutex not to be poisoned");
+
+        match book.open.get(custom_output_id) {
+            Some(cfd) if cfd.taker == taker => {}
+            Some(_) => bail!("CFD {custom_output_id} belongs to another taker"),
+            None => bail!("Unknown CFD {custom_output_id}"),
+        }
+
+        let cfd = book.open.remove(custom_output_id).expect("CFD to be open");
+        self.net_exposure.send_replace(book.net());
+
+        Ok(cfd)
+    }
+
+    /// Our net exposure in contracts, positive if we are long.
+    pub fn net_exposure(&self) -> Decimal {
+        let book = self.book.lock().expect("mutex not to be poisoned");
+        Decimal::from(book.net())
+    }
+
+    /// Notifies about changes of our net exposure in contracts, positive if we are long.
+    pub fn subscribe(&self) -> watch::Receiver<i64> {
+        self.net_exposure.subscribe()
+    }
+
+    pub fn exposure(&self) -> Exposure {
+        let mut book = self.book.lock().expect("mutex not to be poisoned");
+        book.remove_expired(OffsetDateTime::now_utc());
+
+        let (long, short) = book.long_short();
+        let mut takers = BTreeMap::<PublicKey, TakerExposure>::new();
+        for cfd in book.open.values() {
+            let taker = takers.entry(cfd.taker).or_insert(TakerExposure {
+                taker: cfd.taker,
+                notional: 0,
+                open_positions: 0,
+            });
+            taker.notional += cfd.quantity;
+            taker.open_positions += 1;
+        }
+
+        Exposure {
+            long,
+            short,
+            net: book.net(),
+            open_positions: book.open.len(),
+            pending_orders: book.reserved.len(),
+            takers: takers.into_values().collect(),
+            limits: self.limits,
+        }
+    }
+}
+
+impl Book {
+    fn remove_expired(&mut self, now: OffsetDateTime) {
+        self.reserved.retain(|reservation| {
+            let expired = reservation.expires <= now;
+            if expired {
+                tracing::debug!(taker = %reservation.taker, order = ?reservation.order, "Reservation expired");
+            }
+            !expired
+        });
+    }
+
+    fn long_short(&self) -> (u64, u64) {
+        self.open
+            .values()
+            .fold((0, 0), |(long, short), cfd| match cfd.position {
+                Position::Long => (long + cfd.quantity, short),
+                Position::Short => (long, short + cfd.quantity),
+            })
+    }
+
+    fn net(&self) -> i64 {
+        let (long, short) = self.long_short();
+        long as i64 - short as i64
+    }
+
+    /// Checks whether the order fits our limits, counting accepted orders as open.
+    fn check(&self, limits: &Limits, taker: PublicKey, order: &Order) -> Result<()> {
+        let quantity = u64::try_from(order.quantity).context("Invalid order quantity")?;
+
+        if order.leverage > limits.max_leverage {
+            bail!(
+                "Leverage {} exceeds the maximum of {}",
+                order.leverage,
+                limits.max_leverage
+            );
+        }
+
+        if quantity > limits.max_notional_per_cfd {
+            bail!(
+                "CFDs of {quantity} contracts exceed the maximum of {}",
+                limits.max_notional_per_cfd
+            );
+        }
+
+        // Our side of the trades, i.e. the opposite of the takers'
+        let positions = self
+            .open
+            .values()
+            .map(|cfd| (cfd.taker, cfd.position, cfd.quantity))
+            .chain(self.reserved.iter().map(|reservation| {
+                (
+                    reservation.taker,
+                    cfd::counter_position(reservation.order.position),
+                    reservation.order.quantity.unsigned_abs(),
+                )
+            }))
+            .chain(std::iter::once((
+                taker,
+                cfd::counter_position(order.position),
+                quantity,
+            )))
+            .collect::<Vec<_>>();
+
+        if positions.len() > limits.max_positions {
+            bail!("We can't take on more than {} CFDs", limits.max_positions);
+        }
+
+        let taker_positions = positions
+            .iter()
+            .filter(|(other, _, _)| *other == taker)
+            .collect::<Vec<_>>();
+
+        if taker_positions.len() > limits.max_positions_per_taker {
+            bail!(
+                "Only {} open CFDs allowed per taker",
+                limits.max_positions_per_taker
+            );
+        }
+
+        let taker_notional = taker_positions
+            .iter()
+            .map(|(_, _, quantity)| quantity)
+            .sum::<u64>();
+        if taker_notional > limits.max_notional_per_taker {
+            bail!(
+                "Open CFDs of {taker_notional} contracts would exceed the maximum of {} per taker",
+                limits.max_notional_per_taker
+            );
+        }
+
+        let net = positions
+            .iter()
+            .map(|(_, position, quantity)| match position {
+                Position::Long => *quantity as i64,
+                Position::Short => -(*quantity as i64),
+            })
+            .sum::<i64>();
+        if net.unsigned_abs() > limits.max_notional {
+            bail!(
+                "Net exposure of {net} contracts would exceed our maximum of {}",
+                limits.max_notional
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Only continues adding custom outputs for orders we accepted and records the resulting CFDs,
+/// until their custom outputs are removed from the channel.
+impl CustomOutputFilter for RiskManager {
+    fn accept(&self, custom_output: &ProposedCustomOutput) -> Result<()> {
+        let cfd = self.open(custom_output)?;
+        tracing::info!(?cfd, "Opened CFD");
+
+        tokio::spawn(async move {
+            if let Err(e) = cfd::insert(&cfd).await {
+                tracing::error!(?cfd, "Failed to persist CFD: {e:#}");
+            }
+        });
+
+        Ok(())
+    }
+
+    fn removed(&self, counterparty: PublicKey, custom_output_id: &str) {
+        let cfd = match self.close(counterparty, custom_output_id) {
+            Ok(cfd) => cfd,
+            Err(e) => {
+                tracing::warn!(%counterparty, "Ignoring removed custom output: {e:#}");
+                return;
+            }
+        };
+        tracing::info!(?cfd, "Closed CFD");
+
+        tokio::spawn(async move {
+            if let Err(e) = cfd::mark_closed(&cfd.custom_output_id).await {
+                tracing::error!(?cfd, "Failed to persist closed CFD: {e:#}");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bdk::bitcoin::secp256k1::Secp256k1;
+    use bdk::bitcoin::secp256k1::SecretKey;
+    use ten_ten_one::cfd::models::ContractSymbol;
+
+    fn taker(byte: u8) -> PublicKey {
+        let secret = SecretKey::from_slice(&[byte; 32]).unwrap();
+        PublicKey::from_secret_key(&Secp256k1::new(), &secret)
+    }
+
+    fn order(position: Position, quantity: i64, leverage: i64) -> Order {
+        Order {
+            leverage,
+            quantity,
+            contract_symbol: ContractSymbol::BtcUsd,
+            position,
+            open_price: 16000.0,
+        }
+    }
+
+    fn limits() -> Limits {
+        Limits {
+            max_notional_per_cfd: 1000,
+            max_notional_per_taker: 1000,
+            max_notional: 1500,
+            max_leverage: 5,
+            max_positions_per_taker: 2,
+            max_positions: 3,
+        }
+    }
+
+    fn custom_output(taker: PublicKey, order: &Order, id: &str) -> ProposedCustomOutput {
+        let (margin_taker, margin_maker) = ten_ten_one::cfd::margins(order);
+
+        ProposedCustomOutput {
+            id: id.to_owned(),
+            counterparty: taker,
+            local_amount_msat: margin_maker,
+            remote_amount_msat: margin_taker,
+        }
+    }
+
+    fn open(risk: &RiskManager, taker: PublicKey, order: Order, id: &str) {
+        risk.reserve(taker, order).unwrap();
+        risk.open(&custom_output(taker, &order, id)).unwrap();
+    }
+
+    #[test]
+    fn taking_the_other_side_of_the_taker() {
+        let risk = RiskManager::new(limits(), Vec::new());
+
+        open(&risk, taker(1), order(Position::Long, 300, 2), "a");
+        open(&risk, taker(2), order(Position::Short, 100, 2), "b");
+
+        let exposure = risk.exposure();
+        assert_eq!(exposure.long, 100);
+        assert_eq!(exposure.short, 300);
+        assert_eq!(exposure.net, -200);
+        assert_eq!(risk.net_exposure(), Decimal::from(-200));
+    }
+
+    #[test]
+    fn rejects_excessive_leverage() {
+        let risk = RiskManager::new(limits(), Vec::new());
+
+        assert!(risk
+            .reserve(taker(1), order(Position::Long, 100, 10))
+            .is_err());
+    }
+
+    #[test]
+    fn limits_notional_per_cfd() {
+        let limits = Limits {
+            max_notional_per_cfd: 100,
+            ..limits()
+        };
+        let risk = RiskManager::new(limits, Vec::new());
+
+        assert!(risk
+            .reserve(taker(1), order(Position::Long, 101, 1))
+            .is_err());
+        assert!(risk
+            .reserve(taker(1), order(Position::Long, 100, 1))
+            .is_ok());
+    }
+
+    #[test]
+    fn limits_notional_per_taker_including_reservations() {
+        let risk = RiskManager::new(limits(), Vec::new());
+
+        risk.reserve(taker(1), order(Position::Long, 600, 1))
+            .unwrap();
+
+        assert!(risk
+            .reserve(taker(1), order(Position::Short, 600, 1))
+            .is_err());
+        assert!(risk
+            .reserve(taker(2), order(Position::Short, 600, 1))
+            .is_ok());
+    }
+
+    #[test]
+    fn limits_net_exposure_across_takers() {
+        let risk = RiskManager::new(limits(), Vec::new());
+
+        open(&risk, taker(1), order(Position::Long, 1000, 1), "a");
+
+        assert!(risk
+            .reserve(taker(2), order(Position::Long, 600, 1))
+            .is_err());
+        // Reducing our exposure is fine
+        assert!(risk
+            .reserve(taker(2), order(Position::Short, 600, 1))
+            .is_ok());
+    }
+
+    #[test]
+    fn limits_open_positions() {
+        let risk = RiskManager::new(limits(), Vec::new());
+
+        open(&risk, taker(1), order(Position::Long, 10, 1), "a");
+        open(&risk, taker(1), order(Position::Long, 10, 1), "b");
+        assert!(risk
+            .reserve(taker(1), order(Position::Long, 10, 1))
+            .is_err());
+
+        open(&risk, taker(2), order(Position::Long, 10, 1), "c");
+        assert!(risk
+            .reserve(taker(3), order(Position::Long, 10, 1))
+            .is_err());
+    }
+
+    #[test]
+    fn expired_reservations_are_released() {
+        let risk = RiskManager::new(limits(), Vec::new());
+        let now = OffsetDateTime::now_utc();
+
+        risk.reserve_at(taker(1), order(Position::Long, 1000, 1), now)
+            .unwrap();
+        assert!(risk
+            .reserve_at(taker(1), order(Position::Long, 10, 1), now)
+            .is_err());
+
+        let later = now + RESERVATION_TIMEOUT;
+        assert!(risk
+            .reserve_at(taker(1), order(Position::Long, 10, 1), later)
+            .is_ok());
+    }
+
+    #[test]
+    fn custom_output_needs_an_accepted_order() {
+        let risk = RiskManager::new(limits(), Vec::new());
+
+        let order = order(Position::Long, 100, 1);
+
+        assert!(risk.open(&custom_output(taker(1), &order, "a")).is_err());
+    }
+
+    #[test]
+    fn custom_output_has_to_match_the_takers_order() {
+        let risk = RiskManager::new(limits(), Vec::new());
+        let accepted = order(Position::Long, 100, 2);
+        risk.reserve(taker(1), accepted).unwrap();
+
+        assert!(
+            risk.open(&custom_output(taker(2), &accepted, "a")).is_err(),
+            "Another taker must not use the reservation"
+        );
+        assert!(
+            risk.open(&custom_output(
+                taker(1),
+                &order(Position::Long, 200, 2),
+                "a"
+            ))
+            .is_err(),
+            "The custom output has to lock up the margins of the order"
+        );
+
+        let cfd = risk.open(&custom_output(taker(1), &accepted, "a")).unwrap();
+        assert_eq!(cfd.taker, taker(1));
+        assert_eq!(cfd.quantity, 100);
+        assert_eq!(risk.exposure().pending_orders, 0);
+    }
+
+    #[test]
+    fn custom_output_id_is_only_used_once() {
+        let risk = RiskManager::new(limits(), Vec::new());
+        let order = order(Position::Long, 100, 1);
+        open(&risk, taker(1), order, "a");
+        risk.reserve(taker(1), order).unwrap();
+
+        assert!(risk.open(&custom_output(taker(1), &order, "a")).is_err());
+    }
+
+    #[test]
+    fn publishes_net_exposure() {
+        let risk = RiskManager::new(limits(), Vec::new());
+        let exposure = risk.subscribe();
+
+        open(&risk, taker(1), order(Position::Long, 100, 1), "a");
+        assert_eq!(*exposure.borrow(), -100);
+
+        risk.close(taker(1), "a").unwrap();
+        assert_eq!(*exposure.borrow(), 0);
+    }
+
+    #[test]
+    fn only_the_taker_can_close_its_cfd() {
+        let risk = RiskManager::new(limits(), Vec::new());
+        open(&risk, taker(1), order(Position::Long, 100, 1), "a");
+
+        assert!(risk.close(taker(2), "a").is_err());
+        assert!(risk.close(taker(1), "a").is_ok());
+        assert_eq!(risk.exposure().open_positions, 0);
+    }
+}
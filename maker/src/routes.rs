@@ -1,20 +1,54 @@
+use crate::auth;
+use crate::auth::Admin;
 use crate::bitmex::Quote;
+use crate::cfd;
+use crate::cfd::MakerCfd;
+use crate::channel;
+use crate::channel::ChannelFunder;
+use crate::channel::ChannelOpen;
+use crate::faucet;
+use crate::faucet::Faucet;
+use crate::health::Monitor;
+use crate::health::Report;
+use crate::health::MAX_QUOTE_AGE;
+use crate::hedging;
+use crate::hedging::Fill;
+use crate::metrics;
+use crate::metrics::PaymentKind;
+use crate::offer;
+use crate::openapi::ApiDoc;
+use crate::problem::is_insufficient_funds;
+use crate::problem::problem;
+use crate::risk::Exposure;
+use crate::risk::RiskManager;
+use crate::spread::SpreadModel;
 use anyhow::Result;
 use bdk::bitcoin::hashes::hex::ToHex;
 use bdk::bitcoin::secp256k1::PublicKey;
 use bdk::bitcoin::Address;
+use bdk::bitcoin::Network;
 use bdk::bitcoin::Txid;
 use http_api_problem::HttpApiProblem;
-use http_api_problem::StatusCode;
+use maker_api::error::ErrorCode;
+use maker_api::error::Problem;
+use maker_api::Offer;
+use maker_api::OpenChannelRequest;
+use maker_api::OpenChannelResponse;
+use maker_api::PeerInfo;
+use rocket::http::Status;
+use rocket::response::stream::Event;
+use rocket::response::stream::EventStream;
 use rocket::serde::json::Json;
 use rocket::serde::Deserialize;
 use rocket::serde::Serialize;
+use rocket::Route;
+use rocket::Shutdown;
 use rocket::State;
 use rust_decimal::Decimal;
+use std::net::SocketAddr;
 use std::str::FromStr;
-use ten_ten_one::config::maker_peer_info;
+use std::sync::Arc;
 use ten_ten_one::lightning::NodeInfo;
-use ten_ten_one::lightning::PeerInfo;
 use ten_ten_one::wallet;
 use ten_ten_one::wallet::close_channel;
 use ten_ten_one::wallet::create_invoice;
@@ -24,106 +58,291 @@ use ten_ten_one::wallet::get_channel_manager;
 use ten_ten_one::wallet::send_lightning_payment;
 use ten_ten_one::wallet::send_to_address;
 use ten_ten_one::wallet::Balance;
-use ten_ten_one::wallet::OpenChannelRequest;
-use ten_ten_one::wallet::OpenChannelResponse;
 use tokio::sync::watch;
+use utoipa::OpenApi;
+
+/// The routes of our public API, mounted under `/api`.
+///
+/// The faucet is meant for testing and demos, so we don't serve it on mainnet.
+pub fn public(network: Network) -> Vec<Route> {
+    let mut routes = rocket::routes![
+        get_offer,
+        get_offer_stream,
+        post_open_channel,
+        alive,
+        get_openapi
+    ];
+    if network != Network::Bitcoin {
+        routes.extend(rocket::routes![get_faucet]);
+    }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Offer {
-    #[serde(with = "rust_decimal::serde::float")]
-    bid: Decimal,
-    #[serde(with = "rust_decimal::serde::float")]
-    ask: Decimal,
-    #[serde(with = "rust_decimal::serde::float")]
-    index: Decimal,
+    routes
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/faucet/{address}",
+    params(("address" = String, Path, description = "Address to send test coins to")),
+    responses(
+        (status = 200, description = "Transaction paying the address", body = String),
+        (status = 400, description = "Invalid address", body = Problem),
+        (status = 404, description = "No faucet on mainnet", body = Problem),
+        (status = 429, description = "Faucet limit exceeded", body = Problem),
+        (status = 503, description = "Faucet is empty", body = Problem),
+    )
+)]
 #[rocket::get("/faucet/<address>")]
-pub async fn get_faucet(address: String) -> Result<Json<Txid>, HttpApiProblem> {
+pub async fn get_faucet(
+    address: String,
+    // Unlike `IpAddr`, this ignores the `X-Real-IP` header, which clients can set at will
+    remote: SocketAddr,
+    faucet: &State<Faucet>,
+) -> Result<Json<Txid>, HttpApiProblem> {
+    let network = ten_ten_one::config::network();
+    if network == Network::Bitcoin {
+        return Err(problem(ErrorCode::FaucetUnavailable)
+            .title("No faucet on mainnet")
+            .detail("The faucet only pays out test coins"));
+    }
+
     let address = Address::from_str(address.as_str()).map_err(|e| {
-        HttpApiProblem::new(StatusCode::BAD_REQUEST)
+        problem(ErrorCode::InvalidAddress)
             .title("Invalid address")
             .detail(format!("Provided address {address} was not valid: {e:#}"))
     })?;
 
-    let txid = send_to_address(address, 10_000).map_err(|e| {
-        HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
-            .title("Failed to fund address")
-            .detail(format!("{e:#}"))
+    let ip = remote.ip();
+    let txid = faucet.pay(network, address, ip).await.map_err(|e| {
+        if e.is::<faucet::LimitExceeded>() {
+            problem(ErrorCode::FaucetLimitExceeded)
+                .title("Faucet limit exceeded")
+                .detail(format!("{e:#}"))
+        } else if is_insufficient_funds(&e) {
+            problem(ErrorCode::InsufficientFunds)
+                .title("Faucet is empty")
+                .detail(format!("{e:#}"))
+        } else {
+            problem(ErrorCode::PaymentFailed)
+                .title("Failed to fund address")
+                .detail(format!("{e:#}"))
+        }
     })?;
 
     Ok(Json(txid))
 }
 
-#[rocket::get("/offer")]
+#[utoipa::path(
+    get,
+    path = "/api/offer",
+    params(("leverage" = Option<u32>, Query, description = "Leverage to quote, defaults to 1")),
+    responses(
+        (status = 200, description = "Our current offer", body = Offer),
+        (status = 404, description = "No quote yet", body = Problem),
+        (status = 503, description = "Our latest quote is stale", body = Problem),
+    )
+)]
+#[rocket::get("/offer?<leverage>")]
 pub async fn get_offer(
+    leverage: Option<u32>,
     rx_quote_receiver: &State<watch::Receiver<Option<Quote>>>,
-    spread_receiver: &State<watch::Receiver<SpreadPrice>>,
+    spread_receiver: &State<watch::Receiver<SpreadModel>>,
+    volatility_receiver: &State<watch::Receiver<Decimal>>,
+    risk: &State<Arc<RiskManager>>,
 ) -> Result<Json<Offer>, HttpApiProblem> {
     let rx_quote_receiver = rx_quote_receiver.inner().clone();
     let quote = *rx_quote_receiver.borrow();
 
-    let spread = spread_receiver.inner().clone().borrow().load();
-    let spread = Decimal::try_from(spread).map_err(|e| {
-        HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
-            .title("Failed to parse spread")
-            .detail(format!("Failed to parse spread from state: {e:#}"))
-    })?;
+    let model = spread_receiver.inner().borrow().clone();
+    let volatility = *volatility_receiver.inner().borrow();
+    let leverage = Decimal::from(leverage.unwrap_or(1));
 
     match quote {
-        Some(quote) => Ok(Json(Offer {
-            bid: (quote.bid * (Decimal::ONE - spread)),
-            ask: (quote.ask * (Decimal::ONE + spread)),
-            index: quote.index,
-        })),
-        None => Err("No quotes found"),
-    }
-    .map_err(|e| {
-        HttpApiProblem::new(StatusCode::NOT_FOUND)
+        Some(quote) if quote.is_older_than(MAX_QUOTE_AGE) => Err(problem(ErrorCode::QuoteStale)
+            .title("Quote is stale")
+            .detail(format!("Our latest quote is from {}", quote.timestamp))),
+        Some(quote) => {
+            let exposure = risk.net_exposure();
+            let offer = offer::new(quote, &model, leverage, exposure, volatility).map_err(|e| {
+                problem(ErrorCode::Internal)
+                    .title("Failed to create offer")
+                    .detail(format!("{e:#}"))
+            })?;
+            Ok(Json(offer))
+        }
+        None => Err(problem(ErrorCode::QuoteUnavailable)
             .title("No quotes found")
-            .detail(e.to_string())
-    })
+            .detail("No quotes found")),
+    }
 }
 
-/// Spread applied
-#[derive(Clone, Copy)]
-pub struct SpreadPrice(f32);
-
-impl SpreadPrice {
-    /// For ease of PUT request, we expect spread multiplied by 1000
-    pub fn new(spread: i32) -> SpreadPrice {
-        SpreadPrice(spread as f32 / 1000.0)
-    }
-    pub fn load(&self) -> f32 {
-        self.0
+/// Streams a new offer as server-sent event whenever the quote, the spread model or the volatility
+/// changes.
+///
+/// Nothing is sent while the quote is stale.
+#[utoipa::path(
+    get,
+    path = "/api/offer/stream",
+    params(("leverage" = Option<u32>, Query, description = "Leverage to quote, defaults to 1")),
+    responses(
+        (
+            status = 200,
+            description = "Server-sent events carrying an offer each",
+            body = Offer,
+            content_type = "text/event-stream"
+        ),
+    )
+)]
+#[rocket::get("/offer/stream?<leverage>")]
+pub async fn get_offer_stream(
+    leverage: Option<u32>,
+    rx_quote_receiver: &State<watch::Receiver<Option<Quote>>>,
+    spread_receiver: &State<watch::Receiver<SpreadModel>>,
+    volatility_receiver: &State<watch::Receiver<Decimal>>,
+    risk: &State<Arc<RiskManager>>,
+    mut shutdown: Shutdown,
+) -> EventStream![] {
+    let mut rx_quote_receiver = rx_quote_receiver.inner().clone();
+    let mut spread_receiver = spread_receiver.inner().clone();
+    let mut volatility_receiver = volatility_receiver.inner().clone();
+    let leverage = Decimal::from(leverage.unwrap_or(1));
+    let risk = risk.inner().clone();
+
+    EventStream! {
+        loop {
+            let quote = *rx_quote_receiver.borrow_and_update();
+            let model = spread_receiver.borrow_and_update().clone();
+            let volatility = *volatility_receiver.borrow_and_update();
+
+            match quote {
+                Some(quote) if quote.is_older_than(MAX_QUOTE_AGE) => {
+                    tracing::warn!(
+                        timestamp = %quote.timestamp,
+                        "Not streaming offer for stale quote"
+                    );
+                }
+                Some(quote) => {
+                    match offer::new(quote, &model, leverage, risk.net_exposure(), volatility) {
+                        Ok(offer) => yield Event::json(&offer),
+                        Err(e) => tracing::error!("Failed to create offer: {e:#}"),
+                    }
+                }
+                None => {}
+            }
+
+            tokio::select! {
+                Ok(()) = rx_quote_receiver.changed() => {},
+                Ok(()) = spread_receiver.changed() => {},
+                Ok(()) = volatility_receiver.changed() => {},
+                _ = &mut shutdown => break,
+                else => break,
+            }
+        }
     }
 }
 
 // TODO: changing the spread via an api has been added for demo purposes, remove when not needed
 // anymore
+/// Sets the base spread of the spread model.
+///
+/// For ease of PUT request, we expect the spread multiplied by 1000.
 #[rocket::put("/spread/<spread>")]
 pub async fn put_spread(
-    spread_sender: &State<watch::Sender<SpreadPrice>>,
+    _admin: Admin<auth::Spread>,
+    spread_sender: &State<watch::Sender<SpreadModel>>,
     spread: i32,
 ) -> Result<(), HttpApiProblem> {
-    spread_sender
-        .inner()
-        .send(SpreadPrice::new(spread))
-        .map_err(|_| {
-            HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR).title("cannot set the spread")
-        })?;
+    let base = Decimal::new(spread.into(), 3);
+
+    let model = SpreadModel {
+        base,
+        ..spread_sender.inner().borrow().clone()
+    };
+    model.validate().map_err(|e| {
+        problem(ErrorCode::InvalidSpread)
+            .title("Invalid spread")
+            .detail(format!("{e:#}"))
+    })?;
+    spread_sender.inner().send_replace(model);
+
     Ok(())
 }
 
 #[rocket::get("/spread")]
 pub async fn get_spread(
-    spread_receiver: &State<watch::Receiver<SpreadPrice>>,
-) -> Result<Json<f32>, HttpApiProblem> {
-    let spread = spread_receiver.inner().clone().borrow().load();
-    Ok(Json(spread))
+    _admin: Admin<auth::Read>,
+    spread_receiver: &State<watch::Receiver<SpreadModel>>,
+) -> Json<SpreadModel> {
+    Json(spread_receiver.inner().borrow().clone())
+}
+
+#[rocket::get("/risk")]
+pub async fn get_risk(_admin: Admin<auth::Read>, risk: &State<Arc<RiskManager>>) -> Json<Exposure> {
+    Json(risk.exposure())
+}
+
+/// Serves our metrics for Prometheus to scrape.
+#[rocket::get("/metrics")]
+pub async fn get_metrics(
+    _admin: Admin<auth::Read>,
+    risk: &State<Arc<RiskManager>>,
+    quote_receiver: &State<watch::Receiver<Option<Quote>>>,
+) -> Result<String, HttpApiProblem> {
+    let quote = *quote_receiver.borrow();
+
+    metrics::render(&risk.exposure(), quote).map_err(|e| {
+        problem(ErrorCode::Internal)
+            .title("Failed to render metrics")
+            .detail(format!("{e:#}"))
+    })
 }
 
+#[rocket::get("/cfds")]
+pub async fn get_cfds(_admin: Admin<auth::Read>) -> Result<Json<Vec<MakerCfd>>, HttpApiProblem> {
+    let cfds = cfd::load_all().await.map_err(|e| {
+        problem(ErrorCode::Internal)
+            .title("Failed to load CFDs")
+            .detail(format!("{e:#}"))
+    })?;
+
+    Ok(Json(cfds))
+}
+
+/// Everything we keep track of, e.g. for accounting.
 #[derive(Serialize)]
+pub struct Export {
+    pub cfds: Vec<MakerCfd>,
+    pub channel_opens: Vec<ChannelOpen>,
+    pub hedge_fills: Vec<Fill>,
+    pub risk: Exposure,
+    pub spread: SpreadModel,
+}
+
+#[rocket::get("/export")]
+pub async fn get_export(
+    _admin: Admin<auth::Read>,
+    risk: &State<Arc<RiskManager>>,
+    spread_receiver: &State<watch::Receiver<SpreadModel>>,
+) -> Result<Json<Export>, HttpApiProblem> {
+    let export = async {
+        anyhow::Ok(Export {
+            cfds: cfd::load_all().await?,
+            channel_opens: channel::load_opens(None).await?,
+            hedge_fills: hedging::load_fills().await?,
+            risk: risk.exposure(),
+            spread: spread_receiver.borrow().clone(),
+        })
+    }
+    .await
+    .map_err(|e| {
+        problem(ErrorCode::Internal)
+            .title("Failed to export")
+            .detail(format!("{e:#}"))
+    })?;
+
+    Ok(Json(export))
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct WalletDetails {
     pub address: Address,
     pub balance: Balance,
@@ -132,15 +351,17 @@ pub struct WalletDetails {
 
 #[allow(clippy::result_large_err)]
 #[rocket::get("/wallet-details")]
-pub fn get_wallet_details() -> Result<Json<WalletDetails>, HttpApiProblem> {
+pub fn get_wallet_details(
+    _admin: Admin<auth::Read>,
+) -> Result<Json<WalletDetails>, HttpApiProblem> {
     let balance = get_balance().map_err(|e| {
-        HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+        problem(ErrorCode::Internal)
             .title("Failed get new balance")
             .detail(format!("Internal wallet error: {e:#}"))
     })?;
 
     let address = get_address().map_err(|e| {
-        HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+        problem(ErrorCode::Internal)
             .title("Failed get new address")
             .detail(format!("Internal wallet error: {e:#}"))
     })?;
@@ -154,26 +375,77 @@ pub fn get_wallet_details() -> Result<Json<WalletDetails>, HttpApiProblem> {
     }))
 }
 
+/// Reports the state of all components, succeeds as long as we are up.
+#[rocket::get("/health")]
+pub async fn get_health(
+    monitor: &State<Arc<Monitor>>,
+    quote_receiver: &State<watch::Receiver<Option<Quote>>>,
+) -> Json<Report> {
+    let quote = *quote_receiver.borrow();
+
+    Json(monitor.check(quote).await)
+}
+
+/// Like `/health`, but fails if trading would be unsafe.
+#[rocket::get("/ready")]
+pub async fn get_ready(
+    monitor: &State<Arc<Monitor>>,
+    quote_receiver: &State<watch::Receiver<Option<Quote>>>,
+) -> (Status, Json<Report>) {
+    let quote = *quote_receiver.borrow();
+    let report = monitor.check(quote).await;
+
+    let status = if report.ready {
+        Status::Ok
+    } else {
+        Status::ServiceUnavailable
+    };
+
+    (status, Json(report))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/alive",
+    responses((status = 200, description = "How to connect to our Lightning node", body = PeerInfo))
+)]
 #[rocket::get("/alive")]
-pub async fn alive() -> Result<Json<PeerInfo>, HttpApiProblem> {
-    Ok(Json(maker_peer_info()))
+pub fn alive(peer_info: &State<PeerInfo>) -> Json<PeerInfo> {
+    Json(peer_info.inner().clone())
+}
+
+/// Describes our public API, the types are shared with the taker.
+#[rocket::get("/openapi.json")]
+pub fn get_openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
 }
 
 #[rocket::post("/channel/close/<remote_node_id>?<force>")]
 pub async fn post_close_channel(
+    _admin: Admin<auth::Channels>,
     remote_node_id: String,
     force: Option<bool>,
 ) -> Result<(), HttpApiProblem> {
     let force = force.unwrap_or_default();
 
-    let remote_node_id = remote_node_id.parse().map_err(|e| {
-        HttpApiProblem::new(StatusCode::BAD_REQUEST)
+    let remote_node_id: PublicKey = remote_node_id.parse().map_err(|e| {
+        problem(ErrorCode::InvalidNodeId)
             .title("Failed to force-close channel")
             .detail(format!("Could not parse remote node ID: {e:#}"))
     })?;
 
+    let has_channel = get_channel_manager()
+        .list_channels()
+        .iter()
+        .any(|channel| channel.counterparty.node_id == remote_node_id);
+    if !has_channel {
+        return Err(problem(ErrorCode::ChannelNotFound)
+            .title("Failed to close channel")
+            .detail(format!("We have no channel with {remote_node_id}")));
+    }
+
     close_channel(remote_node_id, force).await.map_err(|e| {
-        HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+        problem(ErrorCode::Internal)
             .title("Failed to close channel")
             .detail(format!("{e:#}"))
     })?;
@@ -181,30 +453,73 @@ pub async fn post_close_channel(
     Ok(())
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/channel/open",
+    request_body = OpenChannelRequest,
+    responses(
+        (status = 200, description = "We funded the taker's address", body = OpenChannelResponse),
+        (
+            status = 400,
+            description = "Taker not connected or we refuse to fund the channel",
+            body = Problem
+        ),
+        (status = 503, description = "We don't have enough funds", body = Problem),
+    )
+)]
 #[rocket::post("/channel/open", data = "<request>", format = "json")]
 pub async fn post_open_channel(
     request: Json<OpenChannelRequest>,
+    channel_funder: &State<Arc<ChannelFunder>>,
 ) -> Result<Json<OpenChannelResponse>, HttpApiProblem> {
-    let funding_txid = send_to_address(request.address_to_fund.clone(), request.fund_amount + 500)
-        .map_err(|e| {
-            HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
-                .title("Failed to open channel with maker")
-                .detail(format!("Failed to transfer funds: {e:#}"))
-        })?;
+    // We can't authenticate the taker over HTTP, but we can at least insist on it being connected
+    let connected = wallet::get_peer_manager()
+        .map(|peer_manager| peer_manager.get_peer_node_ids())
+        .unwrap_or_default()
+        .contains(&request.taker_node_id);
+    if !connected {
+        return Err(problem(ErrorCode::TakerNotConnected)
+            .title("Taker not connected")
+            .detail("Connect to the maker before asking it to fund a channel"));
+    }
+
+    let response = channel_funder.fund(&request).await.map_err(|e| {
+        let code = if is_insufficient_funds(&e) {
+            ErrorCode::InsufficientFunds
+        } else {
+            ErrorCode::ChannelFundingRefused
+        };
 
-    Ok(Json(OpenChannelResponse { funding_txid }))
+        problem(code)
+            .title("Failed to open channel with maker")
+            .detail(format!("{e:#}"))
+    })?;
+
+    Ok(Json(response))
 }
 
 #[rocket::post("/send/<address>/<amount>")]
-pub async fn post_send_to_address(address: String, amount: u64) -> Result<String, HttpApiProblem> {
+pub async fn post_send_to_address(
+    _admin: Admin<auth::Payments>,
+    address: String,
+    amount: u64,
+) -> Result<String, HttpApiProblem> {
     let address = address.parse().map_err(|_| {
-        HttpApiProblem::new(StatusCode::BAD_REQUEST)
+        problem(ErrorCode::InvalidAddress)
             .title("Failed to send bitcoin to address")
             .detail("Invalid address")
     })?;
 
-    let txid = send_to_address(address, amount).map_err(|e| {
-        HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+    let result = send_to_address(address, amount);
+    metrics::record_payment(PaymentKind::OnChain, &result);
+    let txid = result.map_err(|e| {
+        let code = if is_insufficient_funds(&e) {
+            ErrorCode::InsufficientFunds
+        } else {
+            ErrorCode::PaymentFailed
+        };
+
+        problem(code)
             .title("Failed to send bitcoin to address")
             .detail(format!("{e:#}"))
     })?;
@@ -213,27 +528,32 @@ pub async fn post_send_to_address(address: String, amount: u64) -> Result<String
 }
 
 #[rocket::post("/invoice/send/<invoice>")]
-pub async fn post_pay_invoice(invoice: String) -> Result<(), HttpApiProblem> {
-    send_lightning_payment(&invoice).await.map_err(|e| {
-        HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+pub async fn post_pay_invoice(
+    _admin: Admin<auth::Payments>,
+    invoice: String,
+) -> Result<(), HttpApiProblem> {
+    let result = send_lightning_payment(&invoice).await;
+    metrics::record_payment(PaymentKind::Lightning, &result);
+    result.map_err(|e| {
+        problem(ErrorCode::PaymentFailed)
             .title("Failed to pay lightning invoice")
             .detail(format!("{e:#}"))
     })
 }
 
 #[rocket::get("/invoice/create")]
-pub async fn get_new_invoice() -> Result<String, HttpApiProblem> {
+pub async fn get_new_invoice(_admin: Admin<auth::Read>) -> Result<String, HttpApiProblem> {
     // FIXME: Hard-code the parameters for testing
     create_invoice(10000, 6000, "maker's invoice".to_string())
         .await
         .map_err(|e| {
-            HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+            problem(ErrorCode::Internal)
                 .title("Failed to create lightning invoice")
                 .detail(format!("{e:#}"))
         })
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub struct ChannelDetail {
     pub channel_id: String,
     pub counterparty: String,
@@ -256,7 +576,7 @@ pub struct ChannelDetail {
     pub inbound_htlc_maximum_msat: Option<u64>,
     pub config: Option<ChannelConfig>,
 }
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub struct ChannelConfig {
     pub forwarding_fee_proportional_millionths: u32,
     pub forwarding_fee_base_msat: u32,
@@ -266,7 +586,7 @@ pub struct ChannelConfig {
 }
 
 #[rocket::get("/channel/list")]
-pub async fn get_channel_details() -> Json<Vec<ChannelDetail>> {
+pub async fn get_channel_details(_admin: Admin<auth::Read>) -> Json<Vec<ChannelDetail>> {
     let list = get_channel_manager()
         .list_channels()
         .iter()
@@ -306,6 +626,6 @@ pub async fn get_channel_details() -> Json<Vec<ChannelDetail>> {
 }
 
 #[rocket::get("/node/info")]
-pub async fn get_node_info() -> Json<NodeInfo> {
+pub async fn get_node_info(_admin: Admin<auth::Read>) -> Json<NodeInfo> {
     Json(wallet::get_node_info())
 }
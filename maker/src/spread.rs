@@ -0,0 +1,305 @@
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use rocket::serde::Deserialize;
+use rocket::serde::Serialize;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+/// Upper bound for any spread we accept, a larger one is likely a typo.
+pub const MAX_SPREAD: Decimal = dec!(0.1);
+
+/// Net exposure is priced per this many contracts.
+const EXPOSURE_UNIT: Decimal = dec!(1000);
+
+/// Determines the spread we apply to the quote, depending on the order and the market.
+///
+/// All spreads are fractions of the price, e.g. `0.015` is a spread of 1.5%.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SpreadModel {
+    /// Spread applied to every order.
+    #[serde(with = "rust_decimal::serde::float")]
+    pub base: Decimal,
+    /// Additional spread per unit of leverage above 1.
+    #[serde(with = "rust_decimal::serde::float")]
+    pub leverage: Decimal,
+    /// Additional spread per 1000 contracts of net exposure the order leaves us with.
+    ///
+    /// Only applies to the side of the book that increases our exposure.
+    #[serde(with = "rust_decimal::serde::float")]
+    pub exposure: Decimal,
+    /// Multiple of the price volatility added to the spread.
+    #[serde(with = "rust_decimal::serde::float")]
+    pub volatility: Decimal,
+    /// Upper bound for the spread on each side of the book.
+    #[serde(with = "rust_decimal::serde::float")]
+    pub max: Decimal,
+    /// Additional spread depending on the order size, sorted by quantity.
+    ///
+    /// We don't quote orders that are larger than the largest tier.
+    pub tiers: Vec<SizeTier>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeTier {
+    /// Largest order in this tier, in contracts.
+    pub max_quantity: u64,
+    #[serde(with = "rust_decimal::serde::float")]
+    pub spread: Decimal,
+}
+
+/// The spread on each side of the book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Spread {
+    pub bid: Decimal,
+    pub ask: Decimal,
+}
+
+impl SpreadModel {
+    /// Fails if the model has no size tiers or they are not sorted by quantity, or if a spread is
+    /// negative or would exceed [`MAX_SPREAD`].
+    pub fn validate(&self) -> Result<()> {
+        for (name, value) in [
+            ("base", self.base),
+            ("leverage", self.leverage),
+            ("exposure", self.exposure),
+            ("volatility", self.volatility),
+        ] {
+            if value < Decimal::ZERO {
+                bail!("Spread {name} must not be negative, got {value}");
+            }
+        }
+        if self.max <= Decimal::ZERO || self.max > MAX_SPREAD {
+            bail!(
+                "Maximum spread must be above 0 and at most {MAX_SPREAD}, got {}",
+                self.max
+            );
+        }
+        if self.base > self.max {
+            bail!(
+                "Base spread {} must not exceed the maximum spread {}",
+                self.base,
+                self.max
+            );
+        }
+        if let Some(tier) = self
+            .tiers
+            .iter()
+            .find(|tier| tier.spread < Decimal::ZERO || tier.spread > self.max)
+        {
+            bail!(
+                "Spread {} of the tier up to {} contracts must be between 0 and the maximum {}",
+                tier.spread,
+                tier.max_quantity,
+                self.max
+            );
+        }
+
+        if self.tiers.is_empty() {
+            bail!("Spread model needs at least one size tier");
+        }
+
+        if !self
+            .tiers
+            .windows(2)
+            .all(|tiers| tiers[0].max_quantity < tiers[1].max_quantity)
+        {
+            bail!("Size tiers must be sorted by quantity");
+        }
+
+        Ok(())
+    }
+
+    /// Calculates the spread for an order.
+    ///
+    /// `exposure` is our net exposure in contracts before the order, positive if we are long.
+    /// `volatility` is the standard deviation of recent price returns.
+    pub fn spread(
+        &self,
+        quantity: u64,
+        leverage: Decimal,
+        exposure: Decimal,
+        volatility: Decimal,
+    ) -> Result<Spread> {
+        let tier = self
+            .tiers
+            .iter()
+            .find(|tier| quantity <= tier.max_quantity)
+            .with_context(|| format!("We don't quote orders of {quantity} contracts"))?;
+
+        let leverage = (leverage - Decimal::ONE).max(Decimal::ZERO);
+        let spread =
+            self.base + tier.spread + self.leverage * leverage + self.volatility * volatility;
+
+        // A taker going long makes us go short and vice versa
+        let quantity = Decimal::from(quantity);
+        let short_exposure = (quantity - exposure).max(Decimal::ZERO);
+        let long_exposure = (exposure + quantity).max(Decimal::ZERO);
+
+        Ok(Spread {
+            bid: (spread + self.exposure * long_exposure / EXPOSURE_UNIT).min(self.max),
+            ask: (spread + self.exposure * short_exposure / EXPOSURE_UNIT).min(self.max),
+        })
+    }
+}
+
+impl Default for SpreadModel {
+    fn default() -> Self {
+        Self {
+            base: dec!(0.015),
+            leverage: dec!(0.001),
+            exposure: dec!(0.001),
+            volatility: Decimal::ONE,
+            max: dec!(0.05),
+            tiers: vec![
+                SizeTier {
+                    max_quantity: 1_000,
+                    spread: Decimal::ZERO,
+                },
+                SizeTier {
+                    max_quantity: 10_000,
+                    spread: dec!(0.002),
+                },
+                SizeTier {
+                    max_quantity: 100_000,
+                    spread: dec!(0.005),
+                },
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model() -> SpreadModel {
+        SpreadModel {
+            base: dec!(0.01),
+            leverage: dec!(0.001),
+            exposure: dec!(0.001),
+            volatility: Decimal::ONE,
+            max: dec!(0.05),
+            tiers: vec![
+                SizeTier {
+                    max_quantity: 100,
+                    spread: Decimal::ZERO,
+                },
+                SizeTier {
+                    max_quantity: 1000,
+                    spread: dec!(0.002),
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn small_unleveraged_order_gets_base_spread_plus_exposure() {
+        let spread = model()
+            .spread(100, Decimal::ONE, Decimal::ZERO, Decimal::ZERO)
+            .unwrap();
+
+        assert_eq!(spread.bid, dec!(0.0101));
+        assert_eq!(spread.ask, dec!(0.0101));
+    }
+
+    #[test]
+    fn larger_orders_and_leverage_widen_the_spread() {
+        let spread = model()
+            .spread(1000, dec!(3), Decimal::ZERO, Decimal::ZERO)
+            .unwrap();
+
+        assert_eq!(spread.bid, dec!(0.015));
+        assert_eq!(spread.ask, dec!(0.015));
+    }
+
+    #[test]
+    fn exposure_only_widens_the_side_increasing_it() {
+        // We are long 1000 contracts, a taker going long reduces our exposure
+        let spread = model()
+            .spread(100, Decimal::ONE, dec!(1000), Decimal::ZERO)
+            .unwrap();
+
+        assert_eq!(spread.ask, dec!(0.01));
+        assert_eq!(spread.bid, dec!(0.0111));
+    }
+
+    #[test]
+    fn spread_is_capped() {
+        let spread = model()
+            .spread(100, Decimal::ONE, Decimal::ZERO, Decimal::ONE)
+            .unwrap();
+
+        assert_eq!(spread.bid, dec!(0.05));
+        assert_eq!(spread.ask, dec!(0.05));
+    }
+
+    #[test]
+    fn does_not_quote_orders_beyond_largest_tier() {
+        let spread = model().spread(1001, Decimal::ONE, Decimal::ZERO, Decimal::ZERO);
+
+        assert!(spread.is_err());
+    }
+
+    #[test]
+    fn default_and_test_models_are_valid() {
+        SpreadModel::default().validate().unwrap();
+        model().validate().unwrap();
+    }
+
+    #[test]
+    fn rejects_spreads_out_of_range() {
+        let negative_base = SpreadModel {
+            base: dec!(-0.01),
+            ..model()
+        };
+        let base_above_max = SpreadModel {
+            base: dec!(0.06),
+            ..model()
+        };
+        let max_too_large = SpreadModel {
+            max: dec!(0.5),
+            ..model()
+        };
+        let negative_leverage = SpreadModel {
+            leverage: dec!(-0.001),
+            ..model()
+        };
+        let mut negative_tier = model();
+        negative_tier.tiers[1].spread = dec!(-0.002);
+
+        for model in [
+            negative_base,
+            base_above_max,
+            max_too_large,
+            negative_leverage,
+            negative_tier,
+        ] {
+            assert!(model.validate().is_err(), "{model:?} should be invalid");
+        }
+    }
+
+    #[test]
+    fn can_read_model_from_toml() {
+        let model: SpreadModel = toml::from_str(
+            r#"
+            base = 0.01
+            leverage = 0.001
+            exposure = 0.001
+            volatility = 1.0
+            max = 0.05
+
+            [[tiers]]
+            max_quantity = 100
+            spread = 0.0
+
+            [[tiers]]
+            max_quantity = 1000
+            spread = 0.002
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(model, self::model());
+    }
+}
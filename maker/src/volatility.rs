@@ -0,0 +1,142 @@
+use crate::bitmex::Quote;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use std::collections::VecDeque;
+use time::OffsetDateTime;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+/// We sample the index price at most once per interval.
+const SAMPLE_INTERVAL: time::Duration = time::Duration::minutes(1);
+
+/// How many samples we use to estimate the volatility, i.e. one hour's worth.
+const WINDOW: usize = 60;
+
+/// Tracks the volatility of the index price.
+///
+/// The volatility is the standard deviation of the returns between samples.
+pub fn track(
+    mut quote_receiver: watch::Receiver<Option<Quote>>,
+) -> (JoinHandle<()>, watch::Receiver<Decimal>) {
+    let (volatility_sender, volatility_receiver) = watch::channel(Decimal::ZERO);
+
+    let handle = tokio::spawn(async move {
+        let mut prices = Prices::default();
+
+        while quote_receiver.changed().await.is_ok() {
+            let quote = *quote_receiver.borrow();
+            if let Some(quote) = quote {
+                if prices.sample(quote.timestamp, quote.index) {
+                    let _ = volatility_sender.send(prices.volatility());
+                }
+            }
+        }
+
+        tracing::error!("Stopped tracking volatility");
+    });
+
+    (handle, volatility_receiver)
+}
+
+#[derive(Default)]
+struct Prices {
+    samples: VecDeque<(OffsetDateTime, Decimal)>,
+}
+
+impl Prices {
+    /// Records the price if the last sample is old enough, returns whether we recorded it.
+    fn sample(&mut self, timestamp: OffsetDateTime, price: Decimal) -> bool {
+        if price.is_zero() {
+            return false;
+        }
+
+        if let Some((last, _)) = self.samples.back() {
+            if timestamp - *last < SAMPLE_INTERVAL {
+                return false;
+            }
+        }
+
+        self.samples.push_back((timestamp, price));
+        if self.samples.len() > WINDOW {
+            self.samples.pop_front();
+        }
+
+        true
+    }
+
+    fn volatility(&self) -> Decimal {
+        let returns = self
+            .samples
+            .iter()
+            .zip(self.samples.iter().skip(1))
+            .filter_map(|((_, previous), (_, current))| (current / previous).to_f64())
+            .map(|ratio| ratio - 1.0)
+            .collect::<Vec<_>>();
+
+        if returns.len() < 2 {
+            return Decimal::ZERO;
+        }
+
+        let n = returns.len() as f64;
+        let mean = returns.iter().sum::<f64>() / n;
+        let variance = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (n - 1.0);
+
+        Decimal::try_from(variance.sqrt())
+            .unwrap_or_default()
+            .round_dp(6)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use time::ext::NumericalDuration;
+
+    #[test]
+    fn constant_price_has_no_volatility() {
+        let mut prices = Prices::default();
+        let start = OffsetDateTime::now_utc();
+
+        for i in 0..10i64 {
+            prices.sample(start + i.minutes(), dec!(16000));
+        }
+
+        assert_eq!(prices.volatility(), Decimal::ZERO);
+    }
+
+    #[test]
+    fn ignores_samples_within_interval() {
+        let mut prices = Prices::default();
+        let start = OffsetDateTime::now_utc();
+
+        assert!(prices.sample(start, dec!(16000)));
+        assert!(!prices.sample(start + 30.seconds(), dec!(16100)));
+        assert!(prices.sample(start + 1.minutes(), dec!(16100)));
+    }
+
+    #[test]
+    fn alternating_price_is_volatile() {
+        let mut prices = Prices::default();
+        let start = OffsetDateTime::now_utc();
+
+        for i in 0..10i64 {
+            let price = if i % 2 == 0 { dec!(100) } else { dec!(110) };
+            prices.sample(start + i.minutes(), price);
+        }
+
+        assert!(prices.volatility() > dec!(0.09));
+    }
+
+    #[test]
+    fn keeps_a_limited_window() {
+        let mut prices = Prices::default();
+        let start = OffsetDateTime::now_utc();
+
+        for i in 0..(WINDOW as i64 * 2) {
+            prices.sample(start + i.minutes(), dec!(16000));
+        }
+
+        assert_eq!(prices.samples.len(), WINDOW);
+    }
+}
@@ -0,0 +1,14 @@
+-- The maker's side of the CFDs it entered with takers
+CREATE TABLE IF NOT EXISTS maker_cfd (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    custom_output_id TEXT UNIQUE NOT NULL,
+    taker TEXT NOT NULL,
+    position TEXT NOT NULL,
+    leverage INTEGER NOT NULL,
+    quantity INTEGER NOT NULL,
+    open_price REAL NOT NULL,
+    state_id INTEGER NOT NULL,
+    created INTEGER NOT NULL,
+    updated INTEGER NOT NULL,
+    FOREIGN KEY(state_id) REFERENCES cfd_state(id)
+);
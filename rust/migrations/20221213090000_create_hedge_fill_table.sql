@@ -0,0 +1,11 @@
+-- Orders the maker filled on an exchange to hedge its exposure from CFDs
+CREATE TABLE IF NOT EXISTS hedge_fill (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    exchange TEXT NOT NULL,
+    order_id TEXT NOT NULL,
+    side TEXT NOT NULL,
+    quantity INTEGER NOT NULL,
+    price REAL NOT NULL,
+    created INTEGER NOT NULL,
+    UNIQUE(exchange, order_id)
+);
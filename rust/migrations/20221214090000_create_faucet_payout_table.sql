@@ -0,0 +1,11 @@
+-- Payouts of the maker's testnet faucet, used for rate limiting
+CREATE TABLE IF NOT EXISTS faucet_payout (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    address TEXT NOT NULL,
+    ip TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    -- NULL while we are sending the payout
+    txid TEXT,
+    created INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS faucet_payout_created ON faucet_payout(created);
@@ -0,0 +1,12 @@
+-- Channel opens the maker funded, keyed by the txid of the maker's funding transaction
+CREATE TABLE IF NOT EXISTS channel_open (
+    funding_txid TEXT PRIMARY KEY NOT NULL,
+    taker TEXT NOT NULL,
+    address TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    state TEXT NOT NULL,
+    channel_id TEXT,
+    created INTEGER NOT NULL,
+    updated INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS channel_open_taker ON channel_open(taker);
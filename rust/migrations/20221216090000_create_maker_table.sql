@@ -0,0 +1,10 @@
+-- Makers the taker knows about besides the ones we deploy, per network
+CREATE TABLE IF NOT EXISTS maker (
+    network TEXT NOT NULL,
+    pubkey TEXT NOT NULL,
+    p2p_address TEXT NOT NULL,
+    http_endpoint TEXT NOT NULL,
+    -- The taker trades with the selected maker, or the one we deploy if none is selected
+    selected INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (network, pubkey)
+);
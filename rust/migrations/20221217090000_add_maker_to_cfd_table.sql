@@ -0,0 +1,5 @@
+-- The maker the CFD lives with, unknown for CFDs opened before we traded with several makers
+ALTER TABLE
+    cfd
+ADD
+    COLUMN maker TEXT;
@@ -7,27 +7,34 @@ use crate::config;
 use crate::connection;
 use crate::db;
 use crate::faucet;
+use crate::hex_utils;
 use crate::logger;
+use crate::maker_registry;
+use crate::maker_registry::Maker;
 use crate::offer;
 use crate::offer::Offer;
 use crate::wallet;
 use crate::wallet::Balance;
 use crate::wallet::LightningTransaction;
-use anyhow::anyhow;
 use anyhow::Context;
 use anyhow::Result;
 use flutter_rust_bridge::StreamSink;
 use flutter_rust_bridge::SyncReturn;
+use lightning::ln::channelmanager::ChannelDetails;
 use lightning_invoice::Invoice;
 use lightning_invoice::InvoiceDescription;
+use maker_api::error::ErrorCode;
+use maker_api::error::Problem;
 use rust_decimal::prelude::ToPrimitive;
 use rust_decimal::Decimal;
+use state::Storage;
 use std::ops::Add;
 use std::path::Path;
 use std::str::FromStr;
 use std::time::SystemTime;
 use time::Duration;
 pub use time::OffsetDateTime;
+use tokio::sync::watch;
 use tokio::try_join;
 
 pub struct Address {
@@ -54,7 +61,8 @@ impl Address {
     }
 }
 
-#[derive(Clone)]
+/// Ordered from worst to best, so the state across several channels is the best of them.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum ChannelState {
     Unavailable,
     Establishing,
@@ -62,25 +70,67 @@ pub enum ChannelState {
     Available,
 }
 
-fn get_channel_state() -> ChannelState {
-    match wallet::get_first_channel_details() {
-        Some(channel_details) => {
-            if channel_details.is_usable {
-                ChannelState::Available
-            } else if channel_details.is_channel_ready {
-                // an unusable, but ready channel indicates that the maker might be
-                // disconnected.
-                ChannelState::Disconnected
-            } else {
-                // if the channel is not usable and not ready - we are currently establishing a
-                // channel with the maker.
-                ChannelState::Establishing
-            }
+impl From<&ChannelDetails> for ChannelState {
+    fn from(channel_details: &ChannelDetails) -> Self {
+        if channel_details.is_usable {
+            ChannelState::Available
+        } else if channel_details.is_channel_ready {
+            // an unusable, but ready channel indicates that the maker might be
+            // disconnected.
+            ChannelState::Disconnected
+        } else {
+            // if the channel is not usable and not ready - we are currently establishing a
+            // channel with the maker.
+            ChannelState::Establishing
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ChannelInfo {
+    /// Hex encoded
+    pub channel_id: String,
+    pub counterparty: String,
+    pub capacity_sats: u64,
+    pub local_balance_sats: u64,
+    /// The part of the capacity that is not ours
+    pub remote_balance_sats: u64,
+    pub state: ChannelState,
+}
+
+impl From<&ChannelDetails> for ChannelInfo {
+    fn from(channel_details: &ChannelDetails) -> Self {
+        let local_balance_sats = channel_details.balance_msat / 1000;
+
+        ChannelInfo {
+            channel_id: hex_utils::hex_str(&channel_details.channel_id),
+            counterparty: channel_details.counterparty.node_id.to_string(),
+            capacity_sats: channel_details.channel_value_satoshis,
+            local_balance_sats,
+            remote_balance_sats: channel_details
+                .channel_value_satoshis
+                .saturating_sub(local_balance_sats),
+            state: ChannelState::from(channel_details),
         }
-        None => ChannelState::Unavailable,
     }
 }
 
+fn get_channels() -> Vec<ChannelInfo> {
+    wallet::list_channels()
+        .iter()
+        .map(ChannelInfo::from)
+        .collect()
+}
+
+/// We can trade as soon as one of our channels is available.
+fn get_channel_state(channels: &[ChannelInfo]) -> ChannelState {
+    channels
+        .iter()
+        .map(|channel| channel.state)
+        .max()
+        .unwrap_or(ChannelState::Unavailable)
+}
+
 pub struct LightningInvoice {
     pub description: String,
     pub amount_sats: f64,
@@ -122,7 +172,9 @@ pub enum Event {
     Ready,
     Offer(Option<Offer>),
     WalletInfo(Option<WalletInfo>),
+    /// The state across all channels.
     ChannelState(ChannelState),
+    Channels(Vec<ChannelInfo>),
 }
 
 #[derive(Clone)]
@@ -175,11 +227,68 @@ pub async fn refresh_wallet_info() -> Result<WalletInfo> {
     WalletInfo::build_wallet_info().await
 }
 
+/// Restores the wallet from its 12 or 24 word seed phrase, e.g. after losing the phone.
+///
+/// Has to be called before [`run`] on a device without a wallet, which then looks for the
+/// wallet's on-chain funds before it starts. Funds in channels can't be restored.
+///
+/// The passphrase has to be the one the wallet was created with, a wrong one leads to a
+/// different, empty wallet and [`run`] fails. Correct it with [`set_seed_passphrase`], or open
+/// the wallet anyway with [`accept_empty_restored_wallet`], before calling [`run`] again.
+pub fn restore_from_mnemonic(
+    app_dir: String,
+    words: Vec<String>,
+    passphrase: Option<String>,
+) -> Result<()> {
+    anyhow::ensure!(!app_dir.is_empty(), "app_dir must not be empty");
+    wallet::restore_from_mnemonic(Path::new(app_dir.as_str()), &words)?;
+    if let Some(passphrase) = passphrase {
+        config::set_seed_passphrase(passphrase);
+    }
+
+    Ok(())
+}
+
+/// Derives the wallet from the seed with the BIP39 passphrase, the "25th word".
+///
+/// The passphrase is not stored, it has to be set before [`run`] on every start.
+pub fn set_seed_passphrase(passphrase: String) {
+    config::set_seed_passphrase(passphrase)
+}
+
+/// Opens the restored wallet even though we found no on-chain funds for it with the passphrase,
+/// e.g. because it was never used or its funds are in channels.
+///
+/// Has to be called before [`run`].
+pub fn accept_empty_restored_wallet() {
+    config::accept_empty_restored_wallet()
+}
+
+/// Routes all outbound connections through the SOCKS5 proxy, e.g. Tor at `127.0.0.1:9050`.
+///
+/// Has to be called before [`run`].
+pub fn set_socks5_proxy(address: String) -> Result<()> {
+    let proxy = address
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid proxy address {address}, expected <ip>:<port>"))?;
+    config::set_socks5_proxy(proxy)
+}
+
+/// Tells [`run`] to return once we have shut down.
+static SHUTDOWN: Storage<watch::Sender<bool>> = Storage::new();
+
+/// Runs the wallet and the Lightning node until [`shutdown`] is called.
 #[tokio::main(flavor = "current_thread")]
 pub async fn run(stream: StreamSink<Event>, app_dir: String) -> Result<()> {
     let network = config::network();
     anyhow::ensure!(!app_dir.is_empty(), "app_dir must not be empty");
     stream.add(Event::Init(format!("Initialising {network} wallet")));
+    if wallet::is_restored(Path::new(app_dir.as_str())) {
+        stream.add(Event::Init(
+            "Looking for the funds of your restored wallet".to_string(),
+        ));
+    }
     wallet::init_wallet(Path::new(app_dir.as_str()))?;
 
     stream.add(Event::Init("Initialising database".to_string()));
@@ -189,9 +298,13 @@ pub async fn run(stream: StreamSink<Event>, app_dir: String) -> Result<()> {
             .join("taker.sqlite"),
     )
     .await?;
+    maker_registry::load().await?;
 
     stream.add(Event::Init("Starting full ldk node".to_string()));
-    let background_processor = wallet::run_ldk().await?;
+    wallet::run_ldk().await?;
+
+    let (shutdown_sender, mut shutdown_receiver) = watch::channel(false);
+    SHUTDOWN.set(shutdown_sender);
 
     stream.add(Event::Init("Fetching an offer".to_string()));
     stream.add(Event::Offer(offer::get_offer().await.ok()));
@@ -201,7 +314,9 @@ pub async fn run(stream: StreamSink<Event>, app_dir: String) -> Result<()> {
         WalletInfo::build_wallet_info().await.ok(),
     ));
     stream.add(Event::Init("Checking channel state".to_string()));
-    stream.add(Event::ChannelState(get_channel_state()));
+    let channels = get_channels();
+    stream.add(Event::ChannelState(get_channel_state(&channels)));
+    stream.add(Event::Channels(channels));
 
     stream.add(Event::Init("Ready".to_string()));
     stream.add(Event::Ready);
@@ -210,8 +325,8 @@ pub async fn run(stream: StreamSink<Event>, app_dir: String) -> Result<()> {
     let peer_manager = wallet::get_peer_manager()?;
     let connection_handle = connection::spawn(peer_manager);
 
-    // sync offers every 5 seconds
-    let offer_handle = offer::spawn(stream.clone());
+    // forward the offers the maker pushes to us
+    let offer_handle = offer::spawn(stream.clone(), wallet::subscribe_to_offers());
 
     // sync wallet every 60 seconds
     let wallet_sync_handle = tokio::spawn(async {
@@ -239,20 +354,49 @@ pub async fn run(stream: StreamSink<Event>, app_dir: String) -> Result<()> {
     let channel_state_stream = stream.clone();
     let channel_state_handle = tokio::spawn(async move {
         loop {
-            channel_state_stream.add(Event::ChannelState(get_channel_state()));
+            let channels = get_channels();
+            channel_state_stream.add(Event::ChannelState(get_channel_state(&channels)));
+            channel_state_stream.add(Event::Channels(channels));
             tokio::time::sleep(std::time::Duration::from_secs(5)).await;
         }
     });
 
-    try_join!(
-        connection_handle,
-        offer_handle,
-        wallet_sync_handle,
-        wallet_info_sync_handle,
-        channel_state_handle,
-    )?;
+    tokio::select! {
+        result = async {
+            try_join!(
+                connection_handle,
+                offer_handle,
+                wallet_sync_handle,
+                wallet_info_sync_handle,
+                channel_state_handle,
+            )
+        } => {
+            result?;
+        }
+        _ = shutdown_receiver.changed() => {
+            tracing::info!("Shut down");
+        }
+    }
+
+    Ok(())
+}
 
-    background_processor.join().map_err(|e| anyhow!(e))
+/// Writes the Lightning node's state to disk, e.g. before the app goes into the background.
+pub fn persist() -> Result<()> {
+    wallet::persist()
+}
+
+/// Disconnects from our peers, stops the Lightning node, persists its state and closes the
+/// database, after which [`run`] returns.
+#[tokio::main(flavor = "current_thread")]
+pub async fn shutdown() -> Result<()> {
+    wallet::shutdown().await?;
+
+    if let Some(sender) = SHUTDOWN.try_get() {
+        let _ = sender.send(true);
+    }
+
+    Ok(())
 }
 
 pub fn get_balance() -> Result<Balance> {
@@ -267,8 +411,67 @@ pub fn get_address() -> Result<Address> {
     Ok(Address::new(wallet::get_address()?.to_string()))
 }
 
-pub fn maker_peer_info() -> String {
-    config::maker_peer_info().to_string()
+pub fn maker_peer_info() -> Result<String> {
+    Ok(config::maker_peer_info()?.to_string())
+}
+
+pub struct MakerInfo {
+    pub pubkey: String,
+    pub p2p_address: String,
+    pub http_endpoint: String,
+    /// Whether we trade with this maker.
+    pub selected: bool,
+}
+
+impl From<(Maker, bool)> for MakerInfo {
+    fn from((maker, selected): (Maker, bool)) -> Self {
+        Self {
+            pubkey: maker.pubkey.to_string(),
+            p2p_address: maker.p2p_address.to_string(),
+            http_endpoint: maker.http_endpoint,
+            selected,
+        }
+    }
+}
+
+pub fn list_makers() -> Vec<MakerInfo> {
+    maker_registry::list()
+        .into_iter()
+        .map(MakerInfo::from)
+        .collect()
+}
+
+/// Adds a maker to trade with, or updates its details if it is known already.
+#[tokio::main(flavor = "current_thread")]
+pub async fn add_maker(pubkey: String, p2p_address: String, http_endpoint: String) -> Result<()> {
+    let maker = Maker::parse(&pubkey, &p2p_address, &http_endpoint)?;
+    maker_registry::add(&maker).await
+}
+
+/// Removes a maker we don't have a channel with.
+#[tokio::main(flavor = "current_thread")]
+pub async fn remove_maker(pubkey: String) -> Result<()> {
+    let pubkey = pubkey.parse().context("Invalid maker public key")?;
+
+    let has_channel = wallet::get_channel_manager()
+        .list_channels()
+        .iter()
+        .any(|channel| channel.counterparty.node_id == pubkey);
+    anyhow::ensure!(!has_channel, "Close the channel with maker {pubkey} first");
+
+    maker_registry::remove(pubkey).await?;
+    wallet::get_peer_manager()?.disconnect_by_node_id(pubkey, false);
+
+    Ok(())
+}
+
+/// Opens channels with the maker and uses its HTTP API from now on.
+///
+/// We stay connected to all makers and route orders to the best of them either way.
+#[tokio::main(flavor = "current_thread")]
+pub async fn select_maker(pubkey: String) -> Result<()> {
+    let pubkey = pubkey.parse().context("Invalid maker public key")?;
+    maker_registry::select(pubkey).await
 }
 
 pub fn node_id() -> String {
@@ -281,13 +484,13 @@ pub fn network() -> SyncReturn<String> {
 
 #[tokio::main(flavor = "current_thread")]
 pub async fn open_channel(taker_amount: u64) -> Result<()> {
-    let peer_info = config::maker_peer_info();
+    let peer_info = config::maker_peer_info()?;
     wallet::open_channel(peer_info, taker_amount).await
 }
 
 #[tokio::main(flavor = "current_thread")]
 pub async fn close_channel() -> Result<()> {
-    let peer_info = config::maker_peer_info();
+    let peer_info = config::maker_peer_info()?;
     wallet::close_channel(peer_info.pubkey, false).await
 }
 
@@ -312,13 +515,71 @@ pub async fn open_cfd(order: Order) -> Result<()> {
     cfd::open(&order).await
 }
 
+/// Why the maker refused a request, mirrors [`ErrorCode`] as frb can't use it directly.
+#[derive(Clone, Copy, Debug)]
+pub enum MakerError {
+    InvalidAddress,
+    InvalidNodeId,
+    InsufficientFunds,
+    QuoteUnavailable,
+    QuoteStale,
+    ChannelNotFound,
+    ChannelFundingRefused,
+    TakerNotConnected,
+    FaucetUnavailable,
+    FaucetLimitExceeded,
+    PaymentFailed,
+    Unauthorized,
+    Forbidden,
+    Internal,
+    Unknown,
+}
+
+fn maker_error(code: ErrorCode) -> MakerError {
+    match code {
+        ErrorCode::InvalidAddress => MakerError::InvalidAddress,
+        ErrorCode::InvalidNodeId => MakerError::InvalidNodeId,
+        ErrorCode::InsufficientFunds => MakerError::InsufficientFunds,
+        ErrorCode::QuoteUnavailable => MakerError::QuoteUnavailable,
+        ErrorCode::QuoteStale => MakerError::QuoteStale,
+        ErrorCode::ChannelNotFound => MakerError::ChannelNotFound,
+        ErrorCode::ChannelFundingRefused => MakerError::ChannelFundingRefused,
+        ErrorCode::TakerNotConnected => MakerError::TakerNotConnected,
+        ErrorCode::FaucetUnavailable => MakerError::FaucetUnavailable,
+        ErrorCode::FaucetLimitExceeded => MakerError::FaucetLimitExceeded,
+        ErrorCode::PaymentFailed => MakerError::PaymentFailed,
+        ErrorCode::Unauthorized => MakerError::Unauthorized,
+        ErrorCode::Forbidden => MakerError::Forbidden,
+        ErrorCode::Internal => MakerError::Internal,
+        // Only the admin API rejects spreads, the app never gets to see it
+        ErrorCode::InvalidSpread | ErrorCode::Unknown => MakerError::Unknown,
+    }
+}
+
+pub enum FaucetResult {
+    /// The faucet paid out in the transaction with this ID.
+    Paid(String),
+    /// The maker refused to pay out, with a message for the user.
+    Refused(MakerError, String),
+}
+
 #[tokio::main(flavor = "current_thread")]
-pub async fn call_faucet(address: String) -> Result<String> {
+pub async fn call_faucet(address: String) -> Result<FaucetResult> {
     anyhow::ensure!(
         !address.is_empty(),
         "Cannot call faucet because of empty address"
     );
-    faucet::call_faucet(address).await
+
+    match faucet::call_faucet(address).await {
+        Ok(txid) => Ok(FaucetResult::Paid(txid)),
+        Err(e) => match e.downcast_ref::<Problem>() {
+            Some(problem) => Ok(FaucetResult::Refused(
+                maker_error(problem.code),
+                problem.to_string(),
+            )),
+            None => Err(e),
+        },
+    }
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -2,8 +2,10 @@ use crate::cfd::models::Order;
 use crate::db::SqliteConnection;
 use anyhow::bail;
 use anyhow::Result;
+use bdk::bitcoin::secp256k1::PublicKey;
 
 pub async fn insert_cfd(
+    maker: PublicKey,
     margin_taker: i64,
     custom_output_id: String,
     liquidation_price: f64,
@@ -13,10 +15,11 @@ pub async fn insert_cfd(
 ) -> Result<()> {
     let created = time::OffsetDateTime::now_utc().unix_timestamp();
     let updated = time::OffsetDateTime::now_utc().unix_timestamp();
+    let maker = maker.to_string();
     let query_result = sqlx::query!(
         r#"
-        INSERT INTO cfd (custom_output_id, contract_symbol, position, leverage, created, updated, state_id, quantity, expiry, open_price, liquidation_price, margin)
-        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+        INSERT INTO cfd (custom_output_id, contract_symbol, position, leverage, created, updated, state_id, quantity, expiry, open_price, liquidation_price, margin, maker)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
         "#,
         custom_output_id,
         order.contract_symbol,
@@ -29,7 +32,8 @@ pub async fn insert_cfd(
         expiry,
         order.open_price,
         liquidation_price,
-        margin_taker
+        margin_taker,
+        maker
     ).execute(connection).await?;
 
     if query_result.rows_affected() != 1 {
@@ -20,7 +20,8 @@ pub async fn load_cfds(conn: &mut SqliteConnection) -> Result<Vec<Cfd>> {
                 open_price,
                 close_price,
                 liquidation_price,
-                margin
+                margin,
+                maker
             from
                 cfd
             inner join cfd_state on cfd.state_id = cfd_state.id
@@ -46,6 +47,7 @@ pub async fn load_cfds(conn: &mut SqliteConnection) -> Result<Vec<Cfd>> {
             liquidation_price: row.liquidation_price,
             margin: row.margin,
             close_price: row.close_price,
+            maker: row.maker,
         };
 
         cfds.push(cfd);
@@ -3,9 +3,37 @@ mod open;
 mod settle;
 
 pub use dal::load_cfds;
+pub use open::margins;
 pub use open::open;
 pub use settle::settle;
 
+/// How much worse than the price the user agreed to we trade at, relative to that price.
+const MAX_SLIPPAGE: f64 = 0.005;
+
+/// Whether the price is at most [`MAX_SLIPPAGE`] worse than the agreed one.
+///
+/// Buying at a higher price is worse, selling at a lower price is worse.
+fn within_slippage(buying: bool, agreed: f64, price: f64) -> bool {
+    let tolerance = agreed * MAX_SLIPPAGE;
+
+    if buying {
+        price <= agreed + tolerance
+    } else {
+        price >= agreed - tolerance
+    }
+}
+
+/// The worst price within [`MAX_SLIPPAGE`] of the agreed one, which we send the maker as our limit.
+fn limit_price(buying: bool, agreed: f64) -> f64 {
+    let tolerance = agreed * MAX_SLIPPAGE;
+
+    if buying {
+        agreed + tolerance
+    } else {
+        agreed - tolerance
+    }
+}
+
 mod dal {
     mod insert_cfd;
     mod load_cfds;
@@ -1,18 +1,20 @@
 use flutter_rust_bridge::frb;
+use serde::Deserialize;
+use serde::Serialize;
 
-#[derive(Debug, Clone, Copy, sqlx::Type)]
+#[derive(Debug, Clone, Copy, sqlx::Type, Serialize, Deserialize)]
 pub enum ContractSymbol {
     BtcUsd,
 }
 
-#[derive(Debug, Clone, Copy, sqlx::Type)]
+#[derive(Debug, Clone, Copy, sqlx::Type, Serialize, Deserialize)]
 pub enum Position {
     Long,
     Short,
 }
 
 #[frb]
-#[derive(Debug, Clone, Copy, sqlx::Type)]
+#[derive(Debug, Clone, Copy, sqlx::Type, Serialize, Deserialize)]
 pub struct Order {
     #[frb(non_final)]
     pub leverage: i64,
@@ -26,7 +28,7 @@ pub struct Order {
     pub open_price: f64,
 }
 
-#[derive(Debug, Clone, Copy, sqlx::Type)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, Serialize, Deserialize)]
 pub enum CfdState {
     Open,
     Closed,
@@ -48,6 +50,10 @@ pub struct Cfd {
     pub close_price: Option<f64>,
     pub liquidation_price: f64,
     pub margin: f64,
+    /// Public key of the maker the CFD lives with.
+    ///
+    /// Unknown for CFDs opened before we traded with several makers.
+    pub maker: Option<String>,
 }
 
 impl Cfd {
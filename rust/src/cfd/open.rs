@@ -1,43 +1,103 @@
 use crate::cfd::dal;
+use crate::cfd::limit_price;
 use crate::cfd::models::Order;
-use crate::config::maker_pk;
+use crate::cfd::models::Position;
+use crate::cfd::within_slippage;
 use crate::db;
+use crate::maker_registry;
+use crate::message::Message;
+use crate::offer::Offers;
 use crate::wallet;
 use anyhow::anyhow;
+use anyhow::bail;
 use anyhow::Context;
 use anyhow::Result;
+use bdk::bitcoin::secp256k1::PublicKey;
+use std::cmp::Ordering;
+
+/// A maker we could open the CFD with, over our channel with it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Candidate {
+    maker: PublicKey,
+    short_channel_id: u64,
+    /// The maker's current price for the order, if it sends us offers.
+    price: Option<f64>,
+    /// How much we can lock up in the channel, in msats.
+    outbound_capacity_msat: u64,
+    /// How much the maker can lock up in the channel, in msats.
+    inbound_capacity_msat: u64,
+}
 
 pub async fn open(order: &Order) -> Result<()> {
+    let channel_manager = wallet::get_channel_manager();
+    let channels = channel_manager.list_usable_channels();
+
+    tracing::info!("Channels: {channels:?}");
+
+    let offers = wallet::get_offers();
+    let candidates = channels
+        .iter()
+        .filter(|channel| maker_registry::is_maker(&channel.counterparty.node_id))
+        .filter_map(|channel| {
+            Some(Candidate {
+                maker: channel.counterparty.node_id,
+                short_channel_id: channel.short_channel_id?,
+                price: price(&offers, channel.counterparty.node_id, order.position),
+                outbound_capacity_msat: channel.outbound_capacity_msat,
+                inbound_capacity_msat: channel.inbound_capacity_msat,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let agreed = order.open_price;
+    let (candidate, order) = route(order, candidates).context(
+        "No maker with a confirmed channel that can carry the order at the price you agreed to",
+    )?;
+    let Candidate {
+        maker: maker_pk,
+        short_channel_id,
+        ..
+    } = candidate;
+
+    // The maker fills at its own price, which may include a spread for leverage and size, as long
+    // as it is no worse than our limit
+    let buying = matches!(order.position, Position::Long);
+    let limit = Order {
+        open_price: limit_price(buying, agreed),
+        ..order
+    };
+
+    tracing::info!("Requesting maker to accept order");
+    let response = wallet::request(maker_pk, |id| Message::OrderRequest(Some(id), limit)).await?;
+    let price = match response {
+        Message::OrderAccepted(_, price) => price.unwrap_or(limit.open_price),
+        Message::Reject(_, problem) => {
+            return Err(anyhow::Error::new(problem).context("Maker rejected order"))
+        }
+        message => bail!("Unexpected response from maker: {message:?}"),
+    };
+    if !within_slippage(buying, agreed, price) {
+        bail!("Maker filled the order at {price}, too far from {agreed}");
+    }
+    let order = Order {
+        open_price: price,
+        ..order
+    };
+    tracing::info!(price, "Maker accepted order");
+
     let liquidation_price: f64 = order.calculate_liquidation_price().0;
     let expiry = order.calculate_expiry().0;
-
-    let margin_taker_as_btc = order.margin_taker().0;
-    // Convert to msats
-    let margin_taker = (margin_taker_as_btc * 100_000_000.0 * 1000.0) as u64;
-    let margin_maker = margin_taker * order.leverage as u64;
+    let (margin_taker, margin_maker) = margins(&order);
 
     tracing::info!(
+        %maker_pk,
         quantity = order.quantity,
+        price = order.open_price,
         margin_taker,
         margin_maker,
         "Opening CFD",
     );
 
-    let channel_manager = wallet::get_channel_manager();
-    let channels = channel_manager.list_channels();
-
-    tracing::info!("Channels: {channels:?}");
-
-    let channel_details = channels
-        .iter()
-        .find(|ch| ch.counterparty.node_id == maker_pk())
-        .context("no open channel with maker found")?;
-
-    let maker_pk = channel_details.counterparty.node_id;
-    let short_channel_id = channel_details
-        .short_channel_id
-        .context("Cannot create custom output if funding transaction has not yet been confirmed")?;
-
     // hardcoded because we are not dealing with force-close scenarios yet
     let dummy_script = "0020e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
         .parse()
@@ -62,14 +122,184 @@ pub async fn open(order: &Order) -> Result<()> {
     let mut conn = db::acquire().await?;
 
     dal::insert_cfd(
+        maker_pk,
         margin_taker as i64,
         custom_output_id,
         liquidation_price,
         expiry,
-        order,
+        &order,
         &mut conn,
     )
     .await?;
 
     Ok(())
 }
+
+/// The price the maker currently offers for the position, if it sends us offers.
+fn price(offers: &Offers, maker: PublicKey, position: Position) -> Option<f64> {
+    let (_, offer) = offers.current().find(|(offering, _)| *offering == maker)?;
+
+    // The taker buys at the maker's ask and sells at its bid
+    match position {
+        Position::Long => Some(offer.ask),
+        Position::Short => Some(offer.bid),
+    }
+}
+
+/// The margins of taker and maker in msats.
+///
+/// The maker checks the custom output we add against these.
+pub fn margins(order: &Order) -> (u64, u64) {
+    let margin_taker_as_btc = order.margin_taker().0;
+    // Convert to msats
+    let margin_taker = (margin_taker_as_btc * 100_000_000.0 * 1000.0) as u64;
+    let margin_maker = margin_taker * order.leverage as u64;
+
+    (margin_taker, margin_maker)
+}
+
+/// Picks the maker with the best price among those whose channel can carry the margins, and
+/// prices the order at that maker's price.
+///
+/// Makers whose price is worse than the one the user agreed to by more than the slippage tolerance
+/// are skipped. Makers that don't send us offers are only considered if no other maker can take
+/// the order, at the price the user saw.
+fn route(order: &Order, candidates: Vec<Candidate>) -> Option<(Candidate, Order)> {
+    let buying = matches!(order.position, Position::Long);
+
+    candidates
+        .into_iter()
+        .filter(|candidate| match candidate.price {
+            Some(price) => within_slippage(buying, order.open_price, price),
+            None => true,
+        })
+        .filter_map(|candidate| {
+            let order = Order {
+                open_price: candidate.price.unwrap_or(order.open_price),
+                ..*order
+            };
+            let (margin_taker, margin_maker) = margins(&order);
+
+            let fits = candidate.outbound_capacity_msat >= margin_taker
+                && candidate.inbound_capacity_msat >= margin_maker;
+            fits.then_some((candidate, order))
+        })
+        .min_by(|(a, _), (b, _)| compare_prices(order.position, a.price, b.price))
+}
+
+/// Orders prices from best to worst for the position, unknown prices last.
+fn compare_prices(position: Position, a: Option<f64>, b: Option<f64>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => match position {
+            Position::Long => a.total_cmp(&b),
+            Position::Short => b.total_cmp(&a),
+        },
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfd::models::ContractSymbol;
+    use bdk::bitcoin::secp256k1::Secp256k1;
+    use bdk::bitcoin::secp256k1::SecretKey;
+
+    const PLENTY_MSAT: u64 = 1_000_000_000;
+
+    fn maker(byte: u8) -> PublicKey {
+        let secret = SecretKey::from_slice(&[byte; 32]).unwrap();
+        PublicKey::from_secret_key(&Secp256k1::new(), &secret)
+    }
+
+    fn candidate(byte: u8, price: Option<f64>, inbound_capacity_msat: u64) -> Candidate {
+        Candidate {
+            maker: maker(byte),
+            short_channel_id: byte as u64,
+            price,
+            outbound_capacity_msat: PLENTY_MSAT,
+            inbound_capacity_msat,
+        }
+    }
+
+    fn order(position: Position) -> Order {
+        Order {
+            leverage: 2,
+            quantity: 100,
+            contract_symbol: ContractSymbol::BtcUsd,
+            position,
+            open_price: 16_000.0,
+        }
+    }
+
+    #[test]
+    fn routes_to_best_price_for_position() {
+        let candidates = vec![
+            candidate(1, Some(16_070.0), PLENTY_MSAT),
+            candidate(2, Some(16_050.0), PLENTY_MSAT),
+        ];
+
+        let (long, routed) = route(&order(Position::Long), candidates.clone()).unwrap();
+        assert_eq!(long.maker, maker(2));
+        assert_eq!(routed.open_price, 16_050.0);
+
+        let (short, _) = route(&order(Position::Short), candidates).unwrap();
+        assert_eq!(short.maker, maker(1));
+    }
+
+    #[test]
+    fn skips_makers_without_capacity() {
+        let candidates = vec![
+            candidate(1, Some(16_050.0), 0),
+            candidate(2, Some(16_070.0), PLENTY_MSAT),
+        ];
+
+        let (candidate, _) = route(&order(Position::Long), candidates).unwrap();
+
+        assert_eq!(candidate.maker, maker(2));
+    }
+
+    #[test]
+    fn prefers_makers_with_offers() {
+        let candidates = vec![
+            candidate(1, None, PLENTY_MSAT),
+            candidate(2, Some(16_070.0), PLENTY_MSAT),
+        ];
+
+        let (candidate, _) = route(&order(Position::Long), candidates.clone()).unwrap();
+        assert_eq!(candidate.maker, maker(2));
+
+        let (candidate, routed) = route(&order(Position::Long), vec![candidates[0]]).unwrap();
+        assert_eq!(candidate.maker, maker(1));
+        assert_eq!(routed.open_price, 16_000.0);
+    }
+
+    #[test]
+    fn rejects_prices_beyond_slippage() {
+        // The user agreed to 16_000, the tolerance is 80
+        let long = vec![candidate(1, Some(16_081.0), PLENTY_MSAT)];
+        let short = vec![candidate(1, Some(15_919.0), PLENTY_MSAT)];
+
+        assert!(route(&order(Position::Long), long).is_none());
+        assert!(route(&order(Position::Short), short).is_none());
+
+        let (_, routed) = route(
+            &order(Position::Long),
+            vec![candidate(1, Some(16_080.0), PLENTY_MSAT)],
+        )
+        .unwrap();
+        assert_eq!(routed.open_price, 16_080.0);
+        assert!(route(
+            &order(Position::Short),
+            vec![candidate(1, Some(16_500.0), PLENTY_MSAT)]
+        )
+        .is_some());
+    }
+
+    #[test]
+    fn no_route_without_channels() {
+        assert!(route(&order(Position::Long), Vec::new()).is_none());
+    }
+}
@@ -1,21 +1,43 @@
 use crate::cfd::dal;
 use crate::cfd::models::Cfd;
 use crate::cfd::models::Position;
+use crate::cfd::within_slippage;
+use crate::config::maker_pk;
 use crate::db;
+use crate::message::Message;
 use crate::offer::Offer;
 use crate::wallet;
 use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
 use anyhow::Result;
 use lightning::ln::channelmanager::CustomOutputId;
 use rust_decimal::prelude::ToPrimitive;
 use rust_decimal::Decimal;
 
 pub async fn settle(cfd: &Cfd, offer: &Offer) -> Result<()> {
-    let closing_price = match cfd.position {
-        Position::Long => offer.bid,
-        Position::Short => offer.ask,
+    let maker = match &cfd.maker {
+        Some(maker) => maker.parse().context("Invalid maker public key")?,
+        // Back then we only traded with the selected maker
+        None => maker_pk()?,
     };
 
+    // The offer shown to the user has the best prices across makers, but we settle with the
+    // maker the CFD lives with, at its price
+    let maker_offer = wallet::get_offers()
+        .current()
+        .find_map(|(offering_maker, offer)| (offering_maker == maker).then(|| offer.clone()))
+        .with_context(|| format!("Maker {maker} has no current offer to settle the CFD with"))?;
+
+    // Closing a long position sells at the bid, closing a short one buys at the ask
+    let (buying, agreed_price, closing_price) = match cfd.position {
+        Position::Long => (false, offer.bid, maker_offer.bid),
+        Position::Short => (true, offer.ask, maker_offer.ask),
+    };
+    if !within_slippage(buying, agreed_price, closing_price) {
+        bail!("The maker's price of {closing_price} moved too far from {agreed_price}");
+    }
+
     let taker_payout_sats = taker_payout_sats(cfd, closing_price)?;
 
     tracing::info!(
@@ -49,6 +71,9 @@ pub async fn settle(cfd: &Cfd, offer: &Offer) -> Result<()> {
     let mut connection = db::acquire().await?;
     dal::update_cfd(&cfd.custom_output_id, closing_price, &mut connection).await?;
 
+    // Makers of older versions only release the exposure the CFD carried once we tell them
+    wallet::send_custom_message(maker, Message::CfdSettled(cfd.custom_output_id.clone()));
+
     tracing::info!("CFD settled");
 
     Ok(())
@@ -91,6 +116,7 @@ mod tests {
             close_price: None,
             liquidation_price: 10_000.0,
             margin: 0.00319536,
+            maker: None,
         };
 
         let closing_price = 16_078.615;
@@ -0,0 +1,535 @@
+//! Where the wallet gets block and transaction data and fee estimates from, and broadcasts
+//! transactions through.
+//!
+//! The app uses public Electrum and Esplora servers and fails over to the next one when a server
+//! is down, whereas the maker can run against its own bitcoind. Electrum and Esplora servers are
+//! reached through the SOCKS5 proxy, if configured.
+
+use crate::config;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use bdk::bitcoin::BlockHash;
+use bdk::bitcoin::Network;
+use bdk::bitcoin::Transaction;
+use bdk::bitcoin::Txid;
+use bdk::bitcoincore_rpc;
+use bdk::bitcoincore_rpc::RpcApi;
+use bdk::blockchain::esplora::EsploraBlockchain;
+use bdk::blockchain::esplora::EsploraBlockchainConfig;
+use bdk::blockchain::rpc::Auth;
+use bdk::blockchain::rpc::RpcBlockchain;
+use bdk::blockchain::rpc::RpcConfig;
+use bdk::blockchain::AnyBlockchain;
+use bdk::blockchain::Blockchain;
+use bdk::blockchain::Capability;
+use bdk::blockchain::ConfigurableBlockchain;
+use bdk::blockchain::ElectrumBlockchain;
+use bdk::blockchain::GetBlockHash;
+use bdk::blockchain::GetHeight;
+use bdk::blockchain::GetTx;
+use bdk::blockchain::NoopProgress;
+use bdk::blockchain::Progress;
+use bdk::blockchain::WalletSync;
+use bdk::database::BatchDatabase;
+use bdk::electrum_client::Client;
+use bdk::electrum_client::Config as ElectrumConfig;
+use bdk::electrum_client::ConfigBuilder;
+use bdk::electrum_client::ElectrumApi;
+use bdk::electrum_client::Socks5Config;
+use bdk::FeeRate;
+use serde::Deserialize;
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+/// How many unused addresses in a row we look at before we stop looking for transactions.
+const STOP_GAP: usize = 20;
+
+/// How long we don't use a backend that failed, unless all others fail as well.
+const RETRY_FAILED_AFTER: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Backend {
+    Electrum {
+        url: String,
+    },
+    /// The HTTP API of an Esplora instance, e.g. `https://blockstream.info/api`.
+    Esplora {
+        url: String,
+    },
+    Bitcoind(Bitcoind),
+}
+
+/// The RPC interface of a Bitcoin Core node.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Bitcoind {
+    /// E.g. `http://127.0.0.1:18443` on regtest.
+    pub url: String,
+
+    /// Authenticates with `rpc_password`, takes precedence over the cookie file.
+    pub rpc_user: Option<String>,
+    pub rpc_password: Option<String>,
+
+    /// The `.cookie` file in bitcoind's data directory.
+    pub cookie_file: Option<PathBuf>,
+}
+
+impl Bitcoind {
+    fn auth(&self) -> Result<Auth> {
+        match (&self.rpc_user, &self.rpc_password, &self.cookie_file) {
+            (Some(username), Some(password), _) => Ok(Auth::UserPass {
+                username: username.clone(),
+                password: password.clone(),
+            }),
+            (None, None, Some(file)) => Ok(Auth::Cookie { file: file.clone() }),
+            _ => bail!("Configure `rpc_user` and `rpc_password` or `cookie_file` for bitcoind"),
+        }
+    }
+
+    fn client(&self) -> Result<bitcoincore_rpc::Client> {
+        let auth = match self.auth()? {
+            Auth::UserPass { username, password } => {
+                bitcoincore_rpc::Auth::UserPass(username, password)
+            }
+            Auth::Cookie { file } => bitcoincore_rpc::Auth::CookieFile(file),
+            Auth::None => bitcoincore_rpc::Auth::None,
+        };
+
+        bitcoincore_rpc::Client::new(&self.url, auth)
+            .with_context(|| format!("Failed to create RPC client for bitcoind at {}", self.url))
+    }
+}
+
+impl Backend {
+    /// Connects to the backend, loading the wallet into bitcoind if we use it.
+    pub fn blockchain(&self, network: Network, wallet_name: &str) -> Result<AnyBlockchain> {
+        let blockchain = match self {
+            Backend::Electrum { url } => {
+                let electrum_config = ConfigBuilder::new().socks5(socks5_config())?.build();
+                let client = Client::from_config(url, electrum_config)
+                    .with_context(|| format!("Failed to connect to Electrum server {url}"))?;
+                ElectrumBlockchain::from(client).into()
+            }
+            Backend::Esplora { url } => EsploraBlockchain::from_config(&esplora_config(url))
+                .with_context(|| format!("Failed to create client for Esplora server {url}"))?
+                .into(),
+            Backend::Bitcoind(bitcoind) => {
+                let config = RpcConfig {
+                    url: bitcoind.url.clone(),
+                    auth: bitcoind.auth()?,
+                    network,
+                    wallet_name: wallet_name.to_owned(),
+                    sync_params: None,
+                };
+                RpcBlockchain::from_config(&config)
+                    .with_context(|| format!("Failed to connect to bitcoind at {}", bitcoind.url))?
+                    .into()
+            }
+        };
+
+        Ok(blockchain)
+    }
+
+    /// Checks that the backend responds.
+    ///
+    /// Blocks for up to [`config::TCP_TIMEOUT`] with Electrum and Esplora.
+    pub fn ping(&self) -> Result<()> {
+        match self {
+            Backend::Electrum { url } => match socks5_config() {
+                // The Electrum client doesn't support timeouts through a proxy, so we stop waiting
+                // for it instead
+                Some(socks5) => {
+                    let electrum_config = ConfigBuilder::new().socks5(Some(socks5))?.build();
+                    let url = url.clone();
+                    let (sender, receiver) = mpsc::channel();
+                    std::thread::spawn(move || {
+                        // The receiver is gone if we gave up waiting
+                        let _ = sender.send(ping_electrum(&url, electrum_config));
+                    });
+
+                    receiver
+                        .recv_timeout(config::TCP_TIMEOUT)
+                        .context("Electrum server did not respond in time")??;
+                }
+                None => {
+                    let timeout = config::TCP_TIMEOUT.as_secs() as u8;
+                    let electrum_config = ConfigBuilder::new().timeout(Some(timeout))?.build();
+                    ping_electrum(url, electrum_config)?;
+                }
+            },
+            Backend::Esplora { url } => {
+                EsploraBlockchain::from_config(&esplora_config(url))?
+                    .get_height()
+                    .context("Esplora server did not respond")?;
+            }
+            Backend::Bitcoind(bitcoind) => {
+                bitcoind
+                    .client()?
+                    .get_block_count()
+                    .context("bitcoind did not respond")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Short name of the kind of backend, e.g. for health checks.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Backend::Electrum { .. } => "electrum",
+            Backend::Esplora { .. } => "esplora",
+            Backend::Bitcoind(_) => "bitcoind",
+        }
+    }
+
+    fn capabilities(&self) -> HashSet<Capability> {
+        match self {
+            Backend::Electrum { .. } | Backend::Esplora { .. } => [
+                Capability::FullHistory,
+                Capability::GetAnyTx,
+                Capability::AccurateFees,
+            ]
+            .into(),
+            // Without `txindex`, bitcoind only knows the transactions of our wallet
+            Backend::Bitcoind(_) => [Capability::FullHistory, Capability::AccurateFees].into(),
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Backend::Electrum { url } => write!(f, "Electrum server {url}"),
+            Backend::Esplora { url } => write!(f, "Esplora server {url}"),
+            Backend::Bitcoind(bitcoind) => write!(f, "bitcoind at {}", bitcoind.url),
+        }
+    }
+}
+
+fn ping_electrum(url: &str, electrum_config: ElectrumConfig) -> Result<()> {
+    let client = Client::from_config(url, electrum_config)
+        .context("Failed to connect to Electrum server")?;
+    client.ping().context("Electrum server did not respond")?;
+
+    Ok(())
+}
+
+fn esplora_config(url: &str) -> EsploraBlockchainConfig {
+    let mut esplora_config = EsploraBlockchainConfig::new(url.to_owned(), STOP_GAP);
+    esplora_config.timeout = Some(config::TCP_TIMEOUT.as_secs());
+    // `socks5h` lets the proxy resolve host names, so they don't leak and onion services work
+    esplora_config.proxy = config::socks5_proxy().map(|proxy| format!("socks5h://{proxy}"));
+
+    esplora_config
+}
+
+fn socks5_config() -> Option<Socks5Config> {
+    config::socks5_proxy().map(Socks5Config::new)
+}
+
+/// How a backend fared recently.
+#[derive(Debug, Clone, Default)]
+pub struct Health {
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+    last_failure: Option<Instant>,
+}
+
+impl Health {
+    fn is_available(&self, now: Instant) -> bool {
+        match self.last_failure {
+            Some(last_failure) if self.consecutive_failures > 0 => {
+                now.duration_since(last_failure) >= RETRY_FAILED_AFTER
+            }
+            _ => true,
+        }
+    }
+}
+
+struct Slot {
+    backend: Backend,
+    /// Connected on first use and dropped on failure, so we reconnect.
+    blockchain: Mutex<Option<Arc<AnyBlockchain>>>,
+    health: Mutex<Health>,
+}
+
+/// Uses the first of its backends that works, in order.
+///
+/// Backends that failed are skipped for [`RETRY_FAILED_AFTER`], unless no other backend works.
+/// Any error counts as a failure of the backend, we'd rather ask another one once too often than
+/// stall the wallet.
+pub struct Failover {
+    network: Network,
+    wallet_name: String,
+    slots: Vec<Slot>,
+}
+
+impl Failover {
+    pub fn new(backends: Vec<Backend>, network: Network, wallet_name: &str) -> Result<Self> {
+        if backends.is_empty() {
+            bail!("No chain backend configured");
+        }
+
+        let slots = backends
+            .into_iter()
+            .map(|backend| Slot {
+                backend,
+                blockchain: Mutex::new(None),
+                health: Mutex::new(Health::default()),
+            })
+            .collect();
+
+        Ok(Self {
+            network,
+            wallet_name: wallet_name.to_owned(),
+            slots,
+        })
+    }
+
+    /// The backends in order and how they fared.
+    pub fn health(&self) -> Vec<(Backend, Health)> {
+        self.slots
+            .iter()
+            .map(|slot| {
+                let health = slot
+                    .health
+                    .lock()
+                    .expect("mutex not to be poisoned")
+                    .clone();
+                (slot.backend.clone(), health)
+            })
+            .collect()
+    }
+
+    fn call<T>(
+        &self,
+        mut f: impl FnMut(&AnyBlockchain) -> Result<T, bdk::Error>,
+    ) -> Result<T, bdk::Error> {
+        let healths = self
+            .slots
+            .iter()
+            .map(|slot| {
+                slot.health
+                    .lock()
+                    .expect("mutex not to be poisoned")
+                    .clone()
+            })
+            .collect::<Vec<_>>();
+
+        let mut last_error = None;
+        for index in attempt_order(&healths, Instant::now()) {
+            let slot = &self.slots[index];
+
+            match self.call_slot(slot, &mut f) {
+                Ok(value) => {
+                    let mut health = slot.health.lock().expect("mutex not to be poisoned");
+                    if health.consecutive_failures > 0 {
+                        tracing::info!(backend = %slot.backend, "Chain backend works again");
+                    }
+                    *health = Health::default();
+
+                    return Ok(value);
+                }
+                Err(e) => {
+                    tracing::warn!(backend = %slot.backend, "Chain backend failed: {e:#}");
+
+                    let mut health = slot.health.lock().expect("mutex not to be poisoned");
+                    health.consecutive_failures += 1;
+                    health.last_error = Some(format!("{e:#}"));
+                    health.last_failure = Some(Instant::now());
+
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error.expect("at least one backend"))
+    }
+
+    fn call_slot<T>(
+        &self,
+        slot: &Slot,
+        f: &mut impl FnMut(&AnyBlockchain) -> Result<T, bdk::Error>,
+    ) -> Result<T, bdk::Error> {
+        let blockchain = {
+            let mut connection = slot.blockchain.lock().expect("mutex not to be poisoned");
+            match connection.as_ref() {
+                Some(blockchain) => blockchain.clone(),
+                None => {
+                    let connected = slot
+                        .backend
+                        .blockchain(self.network, &self.wallet_name)
+                        .map_err(|e| bdk::Error::Generic(format!("{e:#}")))?;
+                    let connected = Arc::new(connected);
+                    *connection = Some(connected.clone());
+                    connected
+                }
+            }
+        };
+
+        // Without holding the lock, so a long sync doesn't hold up fee estimates or broadcasts
+        let result = f(&blockchain);
+        if result.is_err() {
+            let mut connection = slot.blockchain.lock().expect("mutex not to be poisoned");
+            // Another call may have reconnected in the meantime
+            if matches!(connection.as_ref(), Some(current) if Arc::ptr_eq(current, &blockchain)) {
+                *connection = None;
+            }
+        }
+
+        result
+    }
+}
+
+/// Available backends in their configured order, followed by those we are waiting to retry.
+fn attempt_order(healths: &[Health], now: Instant) -> Vec<usize> {
+    let (available, waiting): (Vec<_>, Vec<_>) =
+        (0..healths.len()).partition(|index| healths[*index].is_available(now));
+
+    available.into_iter().chain(waiting).collect()
+}
+
+impl Blockchain for Failover {
+    fn get_capabilities(&self) -> HashSet<Capability> {
+        self.slots
+            .iter()
+            .map(|slot| slot.backend.capabilities())
+            .reduce(|all, capabilities| &all & &capabilities)
+            .unwrap_or_default()
+    }
+
+    fn broadcast(&self, tx: &Transaction) -> Result<(), bdk::Error> {
+        self.call(|blockchain| blockchain.broadcast(tx))
+    }
+
+    fn estimate_fee(&self, target: usize) -> Result<FeeRate, bdk::Error> {
+        self.call(|blockchain| blockchain.estimate_fee(target))
+    }
+}
+
+impl GetHeight for Failover {
+    fn get_height(&self) -> Result<u32, bdk::Error> {
+        self.call(|blockchain| blockchain.get_height())
+    }
+}
+
+impl GetTx for Failover {
+    fn get_tx(&self, txid: &Txid) -> Result<Option<Transaction>, bdk::Error> {
+        self.call(|blockchain| blockchain.get_tx(txid))
+    }
+}
+
+impl GetBlockHash for Failover {
+    fn get_block_hash(&self, height: u64) -> Result<BlockHash, bdk::Error> {
+        self.call(|blockchain| blockchain.get_block_hash(height))
+    }
+}
+
+impl WalletSync for Failover {
+    fn wallet_setup<D: BatchDatabase>(
+        &self,
+        database: &RefCell<D>,
+        progress_update: Box<dyn Progress>,
+    ) -> Result<(), bdk::Error> {
+        // Only the first attempt reports progress, the progress can't be reused
+        let mut progress_update = Some(progress_update);
+        self.call(|blockchain| {
+            let progress: Box<dyn Progress> = match progress_update.take() {
+                Some(progress_update) => progress_update,
+                None => Box::new(NoopProgress),
+            };
+            blockchain.wallet_setup(database, progress)
+        })
+    }
+
+    fn wallet_sync<D: BatchDatabase>(
+        &self,
+        database: &RefCell<D>,
+        progress_update: Box<dyn Progress>,
+    ) -> Result<(), bdk::Error> {
+        let mut progress_update = Some(progress_update);
+        self.call(|blockchain| {
+            let progress: Box<dyn Progress> = match progress_update.take() {
+                Some(progress_update) => progress_update,
+                None => Box::new(NoopProgress),
+            };
+            blockchain.wallet_sync(database, progress)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bitcoind() -> Bitcoind {
+        Bitcoind {
+            url: "http://127.0.0.1:18443".to_owned(),
+            rpc_user: None,
+            rpc_password: None,
+            cookie_file: None,
+        }
+    }
+
+    fn failed(at: Instant) -> Health {
+        Health {
+            consecutive_failures: 1,
+            last_error: Some("connection refused".to_owned()),
+            last_failure: Some(at),
+        }
+    }
+
+    #[test]
+    fn user_and_password_take_precedence_over_cookie_file() {
+        let bitcoind = Bitcoind {
+            rpc_user: Some("admin1".to_owned()),
+            rpc_password: Some("123".to_owned()),
+            cookie_file: Some(PathBuf::from("/data/regtest/.cookie")),
+            ..bitcoind()
+        };
+
+        assert!(matches!(
+            bitcoind.auth().unwrap(),
+            Auth::UserPass { username, .. } if username == "admin1"
+        ));
+    }
+
+    #[test]
+    fn requires_credentials() {
+        assert!(bitcoind().auth().is_err());
+
+        let without_password = Bitcoind {
+            rpc_user: Some("admin1".to_owned()),
+            ..bitcoind()
+        };
+        assert!(without_password.auth().is_err());
+    }
+
+    #[test]
+    fn tries_failed_backends_last() {
+        let now = Instant::now();
+        let healths = [failed(now), Health::default(), Health::default()];
+
+        assert_eq!(attempt_order(&healths, now), vec![1, 2, 0]);
+    }
+
+    #[test]
+    fn retries_failed_backends_in_order_after_a_while() {
+        let now = Instant::now();
+        let healths = [failed(now), Health::default()];
+
+        assert_eq!(
+            attempt_order(&healths, now + RETRY_FAILED_AFTER),
+            vec![0, 1]
+        );
+    }
+}
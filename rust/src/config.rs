@@ -1,14 +1,32 @@
+use crate::chain;
 use crate::lightning::PeerInfo;
+use crate::maker_registry;
+use crate::maker_registry::Maker;
 use anyhow::bail;
 use anyhow::Result;
 use bdk::bitcoin::secp256k1::PublicKey;
 use bdk::bitcoin::Network;
+use state::Storage;
+use std::net::SocketAddr;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::RwLock;
 use std::time::Duration;
 
 const MAINNET_ELECTRUM: &str = "ssl://blockstream.info:700";
 const TESTNET_ELECTRUM: &str = "ssl://blockstream.info:993";
+const SIGNET_ELECTRUM: &str = "ssl://mempool.space:60602";
 const REGTEST_ELECTRUM: &str = "tcp://localhost:50000";
 
+/// Esplora servers we fail over to, in order, when the Electrum server is down.
+const MAINNET_ESPLORA: &[&str] = &["https://blockstream.info/api", "https://mempool.space/api"];
+const TESTNET_ESPLORA: &[&str] = &[
+    "https://blockstream.info/testnet/api",
+    "https://mempool.space/testnet/api",
+];
+const SIGNET_ESPLORA: &[&str] = &["https://mempool.space/signet/api"];
+const REGTEST_ESPLORA: &[&str] = &["http://localhost:3000"];
+
 const REGTEST_MAKER_IP: &str = "127.0.0.1";
 const REGTEST_MAKER_PORT_HTTP: u64 = 8000;
 // Maker PK is derived from our checked in regtest maker seed
@@ -47,11 +65,114 @@ const TESTNET_MAIN_MAKER_PK: &str =
 
 pub const TCP_TIMEOUT: Duration = Duration::from_secs(10);
 
+/// Takes precedence over the `NETWORK` environment variable, if set.
+static NETWORK: Storage<Network> = Storage::new();
+
+/// Takes precedence over the public servers of the network, if set.
+static CHAIN_BACKENDS: Storage<Vec<chain::Backend>> = Storage::new();
+
+/// Routes our outbound connections through this SOCKS5 proxy, e.g. Tor, if set.
+static SOCKS5_PROXY: Storage<SocketAddr> = Storage::new();
+
+/// Encrypts the seed on disk, if set.
+static SEED_PASSWORD: Storage<String> = Storage::new();
+
+/// The BIP39 passphrase the seed is derived with, if set.
+///
+/// Can be replaced, so the user can try again after entering a wrong passphrase.
+static SEED_PASSPHRASE: RwLock<Option<String>> = RwLock::new(None);
+
+/// Opens a restored wallet with a passphrase even if we found no funds for it, if set.
+static ACCEPT_EMPTY_RESTORED_WALLET: AtomicBool = AtomicBool::new(false);
+
 /// Network the app is running
 ///
 /// Defaults to testnet if nothing is specified
 pub fn network() -> Network {
-    read_network_from_env().unwrap_or(Network::Testnet)
+    match NETWORK.try_get() {
+        Some(network) => *network,
+        None => read_network_from_env().unwrap_or(Network::Testnet),
+    }
+}
+
+/// Overrides the network, e.g. from a config file.
+///
+/// Has to be called before anything reads the network, as it can only be set once.
+pub fn set_network(network: Network) -> Result<()> {
+    if !NETWORK.set(network) {
+        bail!("Network has already been set");
+    }
+
+    Ok(())
+}
+
+/// Overrides the chain backends, in the order we try them, e.g. from a config file.
+///
+/// Has to be called before the wallet is initialised, as it can only be set once.
+pub fn set_chain_backends(backends: Vec<chain::Backend>) -> Result<()> {
+    if backends.is_empty() {
+        bail!("At least one chain backend has to be configured");
+    }
+    if !CHAIN_BACKENDS.set(backends) {
+        bail!("Chain backends have already been set");
+    }
+
+    Ok(())
+}
+
+/// Connects to Electrum and Esplora servers, the maker's HTTP API and Lightning peers through
+/// the SOCKS5 proxy, e.g. Tor at `127.0.0.1:9050`.
+///
+/// Has to be called before the wallet is initialised, as it can only be set once.
+pub fn set_socks5_proxy(proxy: SocketAddr) -> Result<()> {
+    if !SOCKS5_PROXY.set(proxy) {
+        bail!("SOCKS5 proxy has already been set");
+    }
+
+    Ok(())
+}
+
+pub fn socks5_proxy() -> Option<SocketAddr> {
+    SOCKS5_PROXY.try_get().copied()
+}
+
+/// Sets the password the seed is encrypted with.
+///
+/// Has to be called before the wallet is initialised, as it can only be set once.
+pub fn set_seed_password(password: String) -> Result<()> {
+    if !SEED_PASSWORD.set(password) {
+        bail!("Seed password has already been set");
+    }
+
+    Ok(())
+}
+
+pub fn seed_password() -> Option<String> {
+    SEED_PASSWORD.try_get().cloned()
+}
+
+/// Sets the BIP39 passphrase the seed is derived with, replacing the previous one.
+///
+/// Has to be called before the wallet is initialised.
+pub fn set_seed_passphrase(passphrase: String) {
+    *SEED_PASSPHRASE.write().expect("lock not to be poisoned") = Some(passphrase);
+}
+
+pub fn seed_passphrase() -> Option<String> {
+    SEED_PASSPHRASE
+        .read()
+        .expect("lock not to be poisoned")
+        .clone()
+}
+
+/// Opens a restored wallet with a passphrase even if we found no funds for it, e.g. because it
+/// was never used or its funds are in channels.
+pub fn accept_empty_restored_wallet() {
+    ACCEPT_EMPTY_RESTORED_WALLET.store(true, Ordering::SeqCst);
+}
+
+pub fn empty_restored_wallet_accepted() -> bool {
+    ACCEPT_EMPTY_RESTORED_WALLET.load(Ordering::SeqCst)
 }
 
 /// The instance of the testnet 10101 maker the taker app should
@@ -63,81 +184,99 @@ fn testnet_maker_instance() -> TestnetMakerInstance {
     read_testnet_maker_instance_from_env().unwrap_or(TestnetMakerInstance::Stable)
 }
 
-pub fn electrum_url() -> String {
-    match network() {
-        Network::Bitcoin => MAINNET_ELECTRUM,
-        Network::Testnet => TESTNET_ELECTRUM,
-        Network::Signet => todo!(),
-        Network::Regtest => REGTEST_ELECTRUM,
+/// Where the wallet gets its chain data from, in the order we try them.
+///
+/// Defaults to the public servers of the network, unless configured otherwise.
+pub fn chain_backends() -> Vec<chain::Backend> {
+    match CHAIN_BACKENDS.try_get() {
+        Some(backends) => backends.clone(),
+        None => default_chain_backends(network()),
     }
-    .to_string()
 }
 
-pub fn maker_pk() -> PublicKey {
-    match network() {
-        Network::Testnet => {
-            let pk = match testnet_maker_instance() {
-                TestnetMakerInstance::Main => TESTNET_MAIN_MAKER_PK,
-                TestnetMakerInstance::Stable => TESTNET_STABLE_MAKER_PK,
-            };
-
-            pk.parse().expect("Hard-coded PK to be valid")
-        }
-        Network::Regtest => REGTEST_MAKER_PK.parse().expect("Hard-coded PK to be valid"),
-        Network::Signet => todo!(),
-        Network::Bitcoin => todo!(),
-    }
+/// The public servers of the network, the Electrum server first.
+pub fn default_chain_backends(network: Network) -> Vec<chain::Backend> {
+    chain_backends_with_electrum(network, default_electrum_url(network))
 }
 
-fn maker_ip() -> String {
-    match network() {
-        Network::Bitcoin => todo!(),
-        Network::Testnet => TESTNET_MAKER_IP.to_string(),
-        Network::Signet => todo!(),
-        Network::Regtest => REGTEST_MAKER_IP.to_string(),
-    }
+/// The Electrum server, followed by the public Esplora servers of the network we fail over to.
+pub fn chain_backends_with_electrum(network: Network, electrum_url: String) -> Vec<chain::Backend> {
+    let esplora_urls = match network {
+        Network::Bitcoin => MAINNET_ESPLORA,
+        Network::Testnet => TESTNET_ESPLORA,
+        Network::Signet => SIGNET_ESPLORA,
+        Network::Regtest => REGTEST_ESPLORA,
+    };
+
+    let electrum = chain::Backend::Electrum { url: electrum_url };
+    let esplora = esplora_urls.iter().map(|url| chain::Backend::Esplora {
+        url: url.to_string(),
+    });
+
+    std::iter::once(electrum).chain(esplora).collect()
 }
 
-fn maker_port_http() -> u64 {
-    match network() {
-        Network::Bitcoin => todo!(),
-        Network::Testnet => match testnet_maker_instance() {
-            TestnetMakerInstance::Main => TESTNET_MAIN_MAKER_PORT_HTTP,
-            TestnetMakerInstance::Stable => TESTNET_STABLE_MAKER_PORT_HTTP,
-        },
-        Network::Signet => todo!(),
-        Network::Regtest => REGTEST_MAKER_PORT_HTTP,
+/// The Electrum server we use on the network unless configured otherwise.
+pub fn default_electrum_url(network: Network) -> String {
+    match network {
+        Network::Bitcoin => MAINNET_ELECTRUM,
+        Network::Testnet => TESTNET_ELECTRUM,
+        Network::Signet => SIGNET_ELECTRUM,
+        Network::Regtest => REGTEST_ELECTRUM,
     }
+    .to_string()
 }
 
-fn maker_port_lightning() -> u64 {
-    match network() {
-        Network::Bitcoin => todo!(),
+/// The maker we deploy for the network, used unless the user selected another maker.
+pub fn default_maker(network: Network) -> Option<Maker> {
+    let (pk, ip, port_http, port_lightning) = match network {
         Network::Testnet => match testnet_maker_instance() {
-            TestnetMakerInstance::Main => TESTNET_MAIN_MAKER_PORT_LIGHTNING,
-            TestnetMakerInstance::Stable => MAKER_PORT_LIGHTNING,
+            TestnetMakerInstance::Main => (
+                TESTNET_MAIN_MAKER_PK,
+                TESTNET_MAKER_IP,
+                TESTNET_MAIN_MAKER_PORT_HTTP,
+                TESTNET_MAIN_MAKER_PORT_LIGHTNING,
+            ),
+            TestnetMakerInstance::Stable => (
+                TESTNET_STABLE_MAKER_PK,
+                TESTNET_MAKER_IP,
+                TESTNET_STABLE_MAKER_PORT_HTTP,
+                MAKER_PORT_LIGHTNING,
+            ),
         },
-        Network::Signet => todo!(),
-        Network::Regtest => MAKER_PORT_LIGHTNING,
-    }
+        Network::Regtest => (
+            REGTEST_MAKER_PK,
+            REGTEST_MAKER_IP,
+            REGTEST_MAKER_PORT_HTTP,
+            MAKER_PORT_LIGHTNING,
+        ),
+        // We don't deploy a maker on signet, but tests can run against their own
+        Network::Signet => return signet_maker(),
+        // We don't deploy a maker on mainnet, users add their own to the maker registry
+        Network::Bitcoin => return None,
+    };
+
+    let maker = Maker {
+        pubkey: pk.parse().expect("Hard-coded PK to be valid"),
+        p2p_address: format!("{ip}:{port_lightning}")
+            .parse()
+            .expect("Hard-coded IP and port to be valid"),
+        http_endpoint: format!("http://{ip}:{port_http}"),
+    };
+
+    Some(maker)
 }
 
-pub fn maker_endpoint() -> String {
-    let ip = maker_ip();
-    let http = maker_port_http();
-    format!("http://{ip}:{http}")
+pub fn maker_pk() -> Result<PublicKey> {
+    Ok(maker_registry::selected()?.pubkey)
 }
 
-pub fn maker_peer_info() -> PeerInfo {
-    let ip = maker_ip();
-    let port_lightning = maker_port_lightning();
+pub fn maker_endpoint() -> Result<String> {
+    Ok(maker_registry::selected()?.http_endpoint)
+}
 
-    PeerInfo {
-        pubkey: maker_pk(),
-        peer_addr: format!("{ip}:{port_lightning}")
-            .parse()
-            .expect("Hard-coded IP and port to be valid"),
-    }
+pub fn maker_peer_info() -> Result<PeerInfo> {
+    Ok(maker_registry::selected()?.peer_info())
 }
 
 /// Parse bitcoin network from command line, e.g. NETWORK=testnet
@@ -161,6 +300,41 @@ fn from_network_str(s: &str) -> Result<Network> {
     }
 }
 
+/// The signet maker given by the environment, e.g. for end-to-end tests on signet.
+///
+/// Invalid settings are ignored, as if there was no signet maker.
+pub(crate) fn signet_maker() -> Option<Maker> {
+    match read_signet_maker_from_env() {
+        Ok(maker) => maker,
+        Err(e) => {
+            tracing::warn!("Ignoring signet maker from environment: {e:#}");
+            None
+        }
+    }
+}
+
+/// Parse the signet maker from the `SIGNET_MAKER_PK`, `SIGNET_MAKER_P2P_ADDRESS` and
+/// `SIGNET_MAKER_HTTP_ENDPOINT` environment variables, `None` if none of them is set.
+fn read_signet_maker_from_env() -> Result<Option<Maker>> {
+    let var = |name: &str| std::env::var(name).ok();
+
+    let maker = match (
+        var("SIGNET_MAKER_PK"),
+        var("SIGNET_MAKER_P2P_ADDRESS"),
+        var("SIGNET_MAKER_HTTP_ENDPOINT"),
+    ) {
+        (Some(pk), Some(p2p_address), Some(http_endpoint)) => {
+            Maker::parse(&pk, &p2p_address, &http_endpoint)?
+        }
+        (None, None, None) => return Ok(None),
+        _ => bail!(
+            "Set all of SIGNET_MAKER_PK, SIGNET_MAKER_P2P_ADDRESS and SIGNET_MAKER_HTTP_ENDPOINT"
+        ),
+    };
+
+    Ok(Some(maker))
+}
+
 /// Parse from the environment the instance of the 10101 testnet maker
 /// that the taker app should connect to.
 fn read_testnet_maker_instance_from_env() -> Result<TestnetMakerInstance> {
@@ -1,44 +1,111 @@
 use crate::config;
+use crate::lightning::PeerAddr;
+use crate::lightning::PeerInfo;
 use crate::lightning::PeerManager;
+use crate::maker_registry;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
 use bdk::bitcoin::secp256k1::PublicKey;
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
+use tokio::net::TcpStream;
 use tokio::task::JoinHandle;
+use tokio_socks::tcp::Socks5Stream;
+
+/// How often we check whether we keep a connection with every maker.
+const SUPERVISE_INTERVAL: Duration = Duration::from_secs(5);
 
 pub fn spawn(peer_manager: Arc<PeerManager>) -> JoinHandle<()> {
-    // keep connection with maker alive!
+    // keep connections with all makers alive!
     tokio::spawn(async move {
-        let peer_info = config::maker_peer_info();
+        let mut connections = HashMap::<PublicKey, JoinHandle<()>>::new();
         loop {
-            tracing::info!("Connecting to {peer_info}");
-            match lightning_net_tokio::connect_outbound(
-                Arc::clone(&peer_manager),
-                peer_info.pubkey,
-                peer_info.peer_addr,
-            )
-            .await
-            {
-                Some(connection_closed_future) => {
-                    let mut connection_closed_future = Box::pin(connection_closed_future);
-                    while !is_connected(&peer_manager, peer_info.pubkey) {
-                        if futures::poll!(&mut connection_closed_future).is_ready() {
-                            tracing::warn!("Peer disconnected before we finished the handshake! Retrying in 5 seconds.");
-                            tokio::time::sleep(Duration::from_secs(5)).await;
-                            return;
-                        }
+            connections.retain(|_, connection| !connection.is_finished());
+
+            // The user might have added makers in the meantime
+            for (maker, _) in maker_registry::list() {
+                connections.entry(maker.pubkey).or_insert_with(|| {
+                    tokio::spawn(keep_connected(Arc::clone(&peer_manager), maker.pubkey))
+                });
+            }
+
+            tokio::time::sleep(SUPERVISE_INTERVAL).await;
+        }
+    })
+}
+
+/// Keeps the connection with the maker alive until it is removed from the registry.
+async fn keep_connected(peer_manager: Arc<PeerManager>, pubkey: PublicKey) {
+    loop {
+        // Read on every attempt, the user might have changed the maker's address
+        let peer_info = match maker_registry::list()
+            .into_iter()
+            .find(|(maker, _)| maker.pubkey == pubkey)
+        {
+            Some((maker, _)) => maker.peer_info(),
+            None => {
+                tracing::info!(%pubkey, "Maker was removed, not connecting anymore");
+                return;
+            }
+        };
+        tracing::info!("Connecting to {peer_info}");
+        match connect(&peer_info).await {
+            Ok(stream) => {
+                let connection_closed_future = lightning_net_tokio::setup_outbound(
+                    Arc::clone(&peer_manager),
+                    peer_info.pubkey,
+                    stream,
+                );
+                let mut connection_closed_future = Box::pin(connection_closed_future);
+                while !is_connected(&peer_manager, peer_info.pubkey) {
+                    if futures::poll!(&mut connection_closed_future).is_ready() {
+                        tracing::warn!("Peer disconnected before we finished the handshake! Retrying in 5 seconds.");
                         tokio::time::sleep(Duration::from_secs(5)).await;
+                        return;
                     }
-                    tracing::info!("Successfully connected to {peer_info}");
-                    connection_closed_future.await;
-                    tracing::warn!("Lost connection to maker, retrying immediately.")
-                }
-                None => {
-                    tracing::warn!("Failed to connect to maker! Retrying in 5 seconds.");
                     tokio::time::sleep(Duration::from_secs(5)).await;
                 }
+                tracing::info!("Successfully connected to {peer_info}");
+                connection_closed_future.await;
+                tracing::warn!("Lost connection to {peer_info}, retrying immediately.")
+            }
+            Err(e) => {
+                tracing::warn!("Failed to connect to {peer_info}: {e:#}! Retrying in 5 seconds.");
+                tokio::time::sleep(Duration::from_secs(5)).await;
             }
         }
+    }
+}
+
+/// Opens a TCP connection to the peer, through the SOCKS5 proxy if configured.
+async fn connect(peer_info: &PeerInfo) -> Result<std::net::TcpStream> {
+    let stream = tokio::time::timeout(config::TCP_TIMEOUT, async {
+        let stream = match (config::socks5_proxy(), &peer_info.peer_addr) {
+            (Some(proxy), PeerAddr::Ip(addr)) => Socks5Stream::connect(proxy, *addr)
+                .await
+                .context("Failed to connect through SOCKS5 proxy")?
+                .into_inner(),
+            (Some(proxy), PeerAddr::Onion { host, port }) => {
+                Socks5Stream::connect(proxy, (host.as_str(), *port))
+                    .await
+                    .context("Failed to connect through SOCKS5 proxy")?
+                    .into_inner()
+            }
+            (None, PeerAddr::Ip(addr)) => TcpStream::connect(addr).await?,
+            (None, PeerAddr::Onion { .. }) => {
+                bail!("Connecting to onion addresses requires a SOCKS5 proxy, e.g. Tor")
+            }
+        };
+
+        anyhow::Ok(stream)
     })
+    .await
+    .context("Timed out connecting")??;
+
+    // LDK drives the connection itself
+    Ok(stream.into_std()?)
 }
 
 fn is_connected(peer_manager: &Arc<PeerManager>, pubkey: PublicKey) -> bool {
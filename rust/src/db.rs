@@ -53,6 +53,27 @@ pub async fn init_db(sqlite_path: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Waits for all connections to be returned and closes them, if the database was initialised.
+pub async fn close() {
+    if let Ok(pool) = get_db() {
+        pool.close().await;
+        tracing::debug!("Closed database");
+    }
+}
+
+/// Takes the write lock of the database and releases it again, fails if we could not write.
+pub async fn check_writable() -> Result<()> {
+    let mut connection = acquire().await?;
+
+    sqlx::query("BEGIN IMMEDIATE")
+        .execute(&mut connection)
+        .await
+        .context("Failed to lock database for writing")?;
+    sqlx::query("ROLLBACK").execute(&mut connection).await?;
+
+    Ok(())
+}
+
 pub async fn acquire() -> Result<SqliteConnection> {
     let pool = get_db()?;
 
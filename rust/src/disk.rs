@@ -1,6 +1,7 @@
 // taken from ldk-bdk-sample
 use crate::hex_utils;
 use crate::lightning::NetGraph;
+use crate::lightning::PeerAddr;
 use crate::lightning::PeerInfo;
 use anyhow::bail;
 use anyhow::Result;
@@ -121,11 +122,17 @@ pub fn parse_peer_info(peer_pubkey_and_ip_addr: String) -> Result<PeerInfo> {
         bail!("ERROR: incorrectly formatted peer info. Should be formatted as: `pubkey@host:port`");
     }
 
-    let peer_addr = peer_addr_str
-        .unwrap()
-        .to_socket_addrs()
-        .map(|mut r| r.next());
-    if peer_addr.is_err() || peer_addr.as_ref().unwrap().is_none() {
+    // Onion addresses are resolved by the Tor proxy, host names we resolve ourselves
+    let peer_addr_str = peer_addr_str.unwrap();
+    let peer_addr = match peer_addr_str.parse::<PeerAddr>() {
+        Ok(peer_addr) => Some(peer_addr),
+        Err(_) => peer_addr_str
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addrs| addrs.next())
+            .map(PeerAddr::from),
+    };
+    if peer_addr.is_none() {
         bail!("ERROR: couldn't parse pubkey@host:port into a socket address");
     }
 
@@ -136,6 +143,6 @@ pub fn parse_peer_info(peer_pubkey_and_ip_addr: String) -> Result<PeerInfo> {
 
     Ok(PeerInfo {
         pubkey: pubkey.unwrap(),
-        peer_addr: peer_addr.unwrap().unwrap(),
+        peer_addr: peer_addr.unwrap(),
     })
 }
@@ -1,30 +1,19 @@
 use crate::config::maker_endpoint;
-use anyhow::anyhow;
-use anyhow::bail;
+use crate::maker_client::MakerClient;
 use anyhow::Context;
 use anyhow::Result;
-use reqwest::StatusCode;
+use bdk::bitcoin::Address;
 
 pub async fn call_faucet(address: String) -> Result<String> {
-    let client = reqwest::Client::builder()
-        .timeout(crate::config::TCP_TIMEOUT)
-        .build()?;
-    let response = client
-        .get(maker_endpoint() + "/api/faucet/" + address.as_str())
-        .send()
-        .await
-        .context("Could not call faucet")?;
-
-    if response.status() == StatusCode::NOT_FOUND
-        || response.status() == StatusCode::INTERNAL_SERVER_ERROR
-    {
-        let response = response.text().await?;
-        bail!("Failed to call faucet: {response}")
-    }
+    let address = address
+        .parse::<Address>()
+        .with_context(|| format!("Invalid address {address}"))?;
 
-    let result = response
-        .json::<String>()
+    // The maker rate limits its faucet
+    let txid = MakerClient::new(maker_endpoint()?)
+        .faucet(&address)
         .await
-        .map_err(|e| anyhow!("Failed to call faucet {e:?}"))?;
-    Ok(result)
+        .context("Failed to call faucet")?;
+
+    Ok(txid.to_string())
 }
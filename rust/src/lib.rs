@@ -1,7 +1,8 @@
 mod api;
 mod bridge_generated;
 mod calc;
-mod cfd;
+pub mod cfd;
+pub mod chain;
 pub mod config;
 mod connection;
 pub mod db;
@@ -10,6 +11,9 @@ mod faucet;
 mod hex_utils;
 pub mod lightning;
 pub mod logger;
-mod offer;
+pub mod maker_client;
+pub mod maker_registry;
+pub mod message;
+pub mod offer;
 pub mod seed;
 pub mod wallet;
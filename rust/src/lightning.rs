@@ -1,3 +1,4 @@
+use crate::chain;
 use crate::db::insert_payment;
 use crate::db::load_payment;
 use crate::db::update_payment;
@@ -5,6 +6,7 @@ use crate::db::PaymentNewInfo;
 use crate::disk;
 use crate::disk::FilesystemLogger;
 use crate::hex_utils;
+use crate::message::TenTenOneMessageHandler;
 use anyhow::anyhow;
 use anyhow::bail;
 use anyhow::Context;
@@ -17,7 +19,6 @@ use bdk::bitcoin::secp256k1::Secp256k1;
 use bdk::bitcoin::Amount;
 use bdk::bitcoin::BlockHash;
 use bdk::bitcoin::Network;
-use bdk::blockchain::ElectrumBlockchain;
 use bdk::wallet::time::get_timestamp;
 use bitcoin_bech32::WitnessProgram;
 use lightning::chain;
@@ -41,7 +42,6 @@ use lightning::ln::channelmanager::ChannelManagerReadArgs;
 use lightning::ln::channelmanager::SimpleArcChannelManager;
 use lightning::ln::peer_handler::IgnoringMessageHandler;
 use lightning::ln::peer_handler::MessageHandler;
-use lightning::ln::peer_handler::SimpleArcPeerManager;
 use lightning::ln::PaymentHash;
 use lightning::ln::PaymentPreimage;
 use lightning::ln::PaymentSecret;
@@ -56,7 +56,9 @@ use lightning::util::config::UserConfig;
 use lightning::util::events::Event;
 use lightning::util::events::EventHandler;
 use lightning::util::events::PaymentPurpose;
+use lightning::util::persist::KVStorePersister;
 use lightning::util::ser::ReadableArgs;
+use lightning::util::ser::Writeable;
 use lightning_background_processor::BackgroundProcessor;
 use lightning_background_processor::GossipSync;
 use lightning_invoice::payment;
@@ -66,22 +68,21 @@ use lightning_invoice::Currency;
 use lightning_invoice::Invoice;
 use lightning_net_tokio::SocketDescriptor;
 use lightning_persister::FilesystemPersister;
+pub use maker_api::PeerAddr;
+pub use maker_api::PeerInfo;
 use rand::thread_rng;
 use rand::Rng;
 use rand::RngCore;
 use serde::Serialize;
 use state::Storage;
 use std::fmt;
-use std::fmt::Display;
-use std::fmt::Formatter;
 use std::iter;
 use std::net::SocketAddr;
 use std::path::Path;
 use std::path::PathBuf;
-use std::sync::atomic::AtomicBool;
-use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::sync::Weak;
 use std::time::Duration;
 use std::time::SystemTime;
 use tokio::runtime;
@@ -90,6 +91,65 @@ use tokio::task::JoinHandle;
 /// has to be managed by Rust as generics are not support by frb
 static INVOICE_PAYER: Storage<Arc<BdkLdkInvoicePayer>> = Storage::new();
 
+/// Taken on shutdown, as stopping it consumes it.
+static BACKGROUND_PROCESSOR: Mutex<Option<RunningProcessor>> = Mutex::new(None);
+
+struct RunningProcessor {
+    processor: BackgroundProcessor,
+    /// Gone once the processor's thread ended.
+    persister: Weak<BackgroundPersister>,
+}
+
+/// Persists for the background processor.
+///
+/// Only the processor's thread holds it, so it is dropped when the thread ends, whether it was
+/// stopped, failed to persist or panicked.
+struct BackgroundPersister(Arc<FilesystemPersister>);
+
+impl KVStorePersister for BackgroundPersister {
+    fn persist<W: Writeable>(&self, key: &str, object: &W) -> std::io::Result<()> {
+        self.0.persist(key, object)
+    }
+}
+
+/// Accepts inbound connections if we run as a server, aborted on shutdown.
+static LISTENER: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+
+/// Consulted before we continue adding a custom output proposed by our peer, if set.
+static CUSTOM_OUTPUT_FILTER: Storage<Arc<dyn CustomOutputFilter>> = Storage::new();
+
+/// Decides whether we go along with the custom outputs our peer wants to add to our channel, and
+/// learns when they leave it again.
+pub trait CustomOutputFilter: Send + Sync {
+    /// Returns an error if we must not add the custom output.
+    fn accept(&self, custom_output: &ProposedCustomOutput) -> Result<()>;
+
+    /// Called once a custom output we accepted is no longer part of the channel with our peer,
+    /// e.g. because the peer settled it or adding it failed.
+    fn removed(&self, counterparty: PublicKey, custom_output_id: &str);
+}
+
+/// A custom output our peer proposes to add to our channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProposedCustomOutput {
+    /// Base64-encoded, as in our CFD records.
+    pub id: String,
+    pub counterparty: PublicKey,
+    /// What we lock up in the custom output, in msats.
+    pub local_amount_msat: u64,
+    /// What our peer locks up in the custom output, in msats.
+    pub remote_amount_msat: u64,
+}
+
+/// Sets the filter for custom outputs proposed by our peers.
+///
+/// Without a filter we accept every custom output.
+pub fn set_custom_output_filter(filter: Arc<dyn CustomOutputFilter>) {
+    if !CUSTOM_OUTPUT_FILTER.set(filter) {
+        tracing::warn!("Custom output filter was already set");
+    }
+}
+
 #[derive(Serialize)]
 pub struct NodeInfo {
     pub node_id: PublicKey,
@@ -111,22 +171,11 @@ pub struct LightningSystem {
     pub keys_manager: Arc<KeysManager>,
     pub persister: Arc<FilesystemPersister>,
     pub gossip_sync: Arc<LdkGossipSync>,
+    pub custom_message_handler: Arc<TenTenOneMessageHandler>,
     pub data_dir: PathBuf,
     pub network: Network,
 }
 
-#[derive(Serialize)]
-pub struct PeerInfo {
-    pub pubkey: PublicKey,
-    pub peer_addr: SocketAddr,
-}
-
-impl Display for PeerInfo {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        format!("{}@{}", self.pubkey, self.peer_addr).fmt(f)
-    }
-}
-
 impl LightningSystem {
     pub fn confirmables(&self) -> Vec<&dyn Confirm> {
         vec![
@@ -268,7 +317,7 @@ impl PaymentInfo {
     }
 }
 
-pub(crate) type BdkLdkWallet = bdk_ldk::LightningWallet<ElectrumBlockchain, bdk::sled::Tree>;
+pub(crate) type BdkLdkWallet = bdk_ldk::LightningWallet<Arc<chain::Failover>, bdk::sled::Tree>;
 
 type LdkGossipSync =
     P2PGossipSync<Arc<NetGraph>, Arc<dyn chain::Access + Send + Sync>, Arc<FilesystemLogger>>;
@@ -282,13 +331,13 @@ type ChainMonitor = chainmonitor::ChainMonitor<
     Arc<FilesystemPersister>,
 >;
 
-pub(crate) type PeerManager = SimpleArcPeerManager<
+pub(crate) type PeerManager = lightning::ln::peer_handler::PeerManager<
     SocketDescriptor,
-    ChainMonitor,
-    BdkLdkWallet,
-    BdkLdkWallet,
-    dyn chain::Access + Send + Sync,
-    FilesystemLogger,
+    Arc<ChannelManager>,
+    Arc<LdkGossipSync>,
+    Arc<OnionMessenger>,
+    Arc<FilesystemLogger>,
+    Arc<TenTenOneMessageHandler>,
 >;
 
 pub(crate) type ChannelManager =
@@ -499,6 +548,7 @@ pub fn setup(
         route_handler: gossip_sync.clone(),
         onion_message_handler: onion_messenger,
     };
+    let custom_message_handler = Arc::new(TenTenOneMessageHandler::new());
     let peer_manager: Arc<PeerManager> = Arc::new(PeerManager::new(
         lightning_msg_handler,
         keys_manager
@@ -507,7 +557,7 @@ pub fn setup(
         current_time.try_into()?,
         &ephemeral_bytes,
         logger.clone(),
-        IgnoringMessageHandler {},
+        custom_message_handler.clone(),
     ));
 
     let system = LightningSystem {
@@ -520,6 +570,7 @@ pub fn setup(
         keys_manager,
         persister,
         gossip_sync,
+        custom_message_handler,
         data_dir: Path::new(&ldk_data_dir).to_path_buf(),
         network,
     };
@@ -545,7 +596,9 @@ fn default_user_config() -> UserConfig {
     }
 }
 
-pub async fn run_ldk(system: &LightningSystem) -> Result<BackgroundProcessor> {
+/// Starts processing events and persisting the channel manager in the background until
+/// [`shutdown`].
+pub async fn run_ldk(system: &LightningSystem) -> Result<()> {
     let ldk_data_dir = system.data_dir.to_string_lossy().to_string();
 
     let runtime_handle = tokio::runtime::Handle::current();
@@ -584,8 +637,10 @@ pub async fn run_ldk(system: &LightningSystem) -> Result<BackgroundProcessor> {
     INVOICE_PAYER.set(invoice_payer.clone());
 
     // Step 19: Background Processing
+    let persister = Arc::new(BackgroundPersister(system.persister.clone()));
+    let running_persister = Arc::downgrade(&persister);
     let background_processor = BackgroundProcessor::start(
-        system.persister.clone(),
+        persister,
         invoice_payer.clone(),
         system.chain_monitor.clone(),
         system.channel_manager.clone(),
@@ -595,19 +650,22 @@ pub async fn run_ldk(system: &LightningSystem) -> Result<BackgroundProcessor> {
         Some(scorer),
     );
 
+    *BACKGROUND_PROCESSOR
+        .lock()
+        .expect("mutex not to be poisoned") = Some(RunningProcessor {
+        processor: background_processor,
+        persister: running_persister,
+    });
+
     let node_id = system.channel_manager.get_our_node_id();
 
     tracing::info!("Lightning node started with node ID {node_id}");
-    Ok(background_processor)
+    Ok(())
 }
 
-pub async fn run_ldk_server(
-    system: &LightningSystem,
-    address: SocketAddr,
-) -> Result<(JoinHandle<()>, BackgroundProcessor)> {
+/// Like [`run_ldk`], but also accepts inbound connections on the address.
+pub async fn run_ldk_server(system: &LightningSystem, address: SocketAddr) -> Result<()> {
     let peer_manager_connection_handler = system.peer_manager.clone();
-    let stop_listen_connect = Arc::new(AtomicBool::new(false));
-    let stop_listen = Arc::clone(&stop_listen_connect);
     let tcp_handle = tokio::spawn(async move {
         let listener = tokio::net::TcpListener::bind(address)
             .await
@@ -615,9 +673,6 @@ pub async fn run_ldk_server(
         loop {
             let peer_mgr = peer_manager_connection_handler.clone();
             let tcp_stream = listener.accept().await.unwrap().0;
-            if stop_listen.load(Ordering::Acquire) {
-                return;
-            }
             tokio::spawn(async move {
                 lightning_net_tokio::setup_inbound(
                     peer_mgr.clone(),
@@ -628,10 +683,64 @@ pub async fn run_ldk_server(
         }
     });
 
+    *LISTENER.lock().expect("mutex not to be poisoned") = Some(tcp_handle);
+
     tracing::info!("Listening on {address}");
 
-    let background_processor = run_ldk(system).await?;
-    Ok((tcp_handle, background_processor))
+    run_ldk(system).await
+}
+
+/// Writes the channel manager and the network graph to disk, e.g. before the app goes into the
+/// background.
+pub fn persist(system: &LightningSystem) -> Result<()> {
+    // The same keys the background processor persists to
+    system
+        .persister
+        .persist("manager", &*system.channel_manager)
+        .context("Failed to persist channel manager")?;
+    system
+        .persister
+        .persist("network_graph", &*system.network_graph)
+        .context("Failed to persist network graph")?;
+
+    Ok(())
+}
+
+/// Whether the background processor runs, i.e. we handle events and persist the channel manager.
+///
+/// False once its thread ended, e.g. because it failed to persist the channel manager.
+pub fn is_running() -> bool {
+    BACKGROUND_PROCESSOR
+        .lock()
+        .expect("mutex not to be poisoned")
+        .as_ref()
+        .map_or(false, |running| running.persister.strong_count() > 0)
+}
+
+/// Stops the node: disconnects our peers, stops the background processor and persists our state
+/// one last time.
+pub fn shutdown(system: &LightningSystem) -> Result<()> {
+    if let Some(listener) = LISTENER.lock().expect("mutex not to be poisoned").take() {
+        listener.abort();
+    }
+
+    system.peer_manager.disconnect_all_peers();
+
+    let background_processor = BACKGROUND_PROCESSOR
+        .lock()
+        .expect("mutex not to be poisoned")
+        .take();
+    if let Some(running) = background_processor {
+        running
+            .processor
+            .stop()
+            .context("Failed to stop background processor")?;
+    }
+
+    persist(system)?;
+
+    tracing::info!("Lightning node stopped");
+    Ok(())
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -661,7 +770,7 @@ async fn handle_ldk_events(
                     Network::Bitcoin => bitcoin_bech32::constants::Network::Bitcoin,
                     Network::Testnet => bitcoin_bech32::constants::Network::Testnet,
                     Network::Regtest => bitcoin_bech32::constants::Network::Regtest,
-                    Network::Signet => panic!("Signet unsupported"),
+                    Network::Signet => bitcoin_bech32::constants::Network::Signet,
                 },
             )
             .expect("Lightning funding tx should always be to a SegWit output")
@@ -1022,13 +1131,51 @@ async fn handle_ldk_events(
             );
         }
         // Maker
-        Event::RemoteSentAddCustomOutputEvent { custom_output_id } => {
-            // TODO: Remove unwrap
-            let _details = channel_manager
-                .continue_remote_add_custom_output(*custom_output_id)
-                .unwrap();
+        Event::RemoteSentAddCustomOutputEvent {
+            custom_output_id,
+            counterparty_node_id,
+            amount_local_msat,
+            amount_remote_msat,
+            ..
+        } => {
+            let custom_output = ProposedCustomOutput {
+                id: base64::encode(custom_output_id.0),
+                counterparty: *counterparty_node_id,
+                local_amount_msat: *amount_local_msat,
+                remote_amount_msat: *amount_remote_msat,
+            };
 
-            // TODO: Persist CFD details
+            // Without continuing, the custom output never makes it into the channel and nothing
+            // of ours is locked up in it
+            if let Some(filter) = CUSTOM_OUTPUT_FILTER.try_get() {
+                if let Err(e) = filter.accept(&custom_output) {
+                    tracing::warn!(?custom_output, "Rejecting custom output: {e:#}");
+                    return;
+                }
+            }
+
+            match channel_manager.continue_remote_add_custom_output(*custom_output_id) {
+                Ok(details) => tracing::debug!(?details, "Added custom output"),
+                Err(e) => {
+                    tracing::error!(?custom_output, "Failed to add custom output: {e:?}");
+                    if let Some(filter) = CUSTOM_OUTPUT_FILTER.try_get() {
+                        filter.removed(custom_output.counterparty, &custom_output.id);
+                    }
+                }
+            }
+        }
+        // Maker
+        Event::RemoteSentRemoveCustomOutputEvent {
+            custom_output_id,
+            counterparty_node_id,
+            ..
+        } => {
+            let custom_output_id = base64::encode(custom_output_id.0);
+            tracing::info!(custom_output_id, "Peer removed custom output");
+
+            if let Some(filter) = CUSTOM_OUTPUT_FILTER.try_get() {
+                filter.removed(*counterparty_node_id, &custom_output_id);
+            }
         }
         // Maker
         Event::RemoteSentCustomOutputCommitmentSignature {
@@ -1131,7 +1278,7 @@ pub async fn create_invoice(
         Network::Bitcoin => Currency::Bitcoin,
         Network::Testnet => Currency::BitcoinTestnet,
         Network::Regtest => Currency::Regtest,
-        Network::Signet => bail!("Signet unsupported"),
+        Network::Signet => Currency::Signet,
     };
     let invoice = match lightning_invoice::utils::create_invoice_from_channelmanager(
         &channel_manager,
@@ -0,0 +1,158 @@
+//! Client for the maker's public HTTP API, sharing its request and response types with the maker.
+
+use crate::config;
+use crate::config::TCP_TIMEOUT;
+use anyhow::Context;
+use anyhow::Result;
+use bdk::bitcoin::Address;
+use bdk::bitcoin::Txid;
+use maker_api::error::ErrorCode;
+use maker_api::error::Problem;
+use maker_api::path;
+use maker_api::Offer;
+use maker_api::OpenChannelRequest;
+use maker_api::OpenChannelResponse;
+use maker_api::PeerInfo;
+use reqwest::Response;
+
+pub struct MakerClient {
+    client: reqwest::Client,
+    endpoint: String,
+}
+
+impl MakerClient {
+    /// Creates a client for the maker at the endpoint, e.g. `http://localhost:8000`.
+    pub fn new(endpoint: String) -> Self {
+        // Only connecting times out for all requests, as the offer stream is long-lived
+        let mut builder = reqwest::Client::builder().connect_timeout(TCP_TIMEOUT);
+        if let Some(proxy) = config::socks5_proxy() {
+            // `socks5h` lets the proxy resolve host names, which onion services require
+            let proxy = reqwest::Proxy::all(format!("socks5h://{proxy}"))
+                .expect("socket address to be a valid proxy URL");
+            builder = builder.proxy(proxy);
+        }
+        let client = builder
+            .build()
+            .expect("static client configuration to be valid");
+
+        Self { client, endpoint }
+    }
+
+    pub async fn offer(&self) -> Result<Offer> {
+        let response = self
+            .client
+            .get(self.url(path::OFFER))
+            .timeout(TCP_TIMEOUT)
+            .send()
+            .await
+            .context("Failed to fetch offer")?;
+
+        let offer = check_status(response).await?.json().await?;
+        Ok(offer)
+    }
+
+    /// Subscribes to the offer stream, the response body is a stream of server-sent events.
+    pub async fn offer_stream(&self) -> Result<Response> {
+        let response = self
+            .client
+            .get(self.url(path::OFFER_STREAM))
+            .send()
+            .await
+            .context("Failed to subscribe to offer stream")?;
+
+        check_status(response).await
+    }
+
+    pub async fn faucet(&self, address: &Address) -> Result<Txid> {
+        let path = path::FAUCET.replace("{address}", &address.to_string());
+        let response = self
+            .client
+            .get(self.url(&path))
+            .timeout(TCP_TIMEOUT)
+            .send()
+            .await
+            .context("Could not call faucet")?;
+
+        let txid = check_status(response).await?.json().await?;
+        Ok(txid)
+    }
+
+    pub async fn open_channel(&self, request: &OpenChannelRequest) -> Result<OpenChannelResponse> {
+        let response = self
+            .client
+            .post(self.url(path::OPEN_CHANNEL))
+            .json(request)
+            .timeout(TCP_TIMEOUT)
+            .send()
+            .await
+            .context("Failed to ask maker to open channel")?;
+
+        let response = check_status(response).await?.json().await?;
+        Ok(response)
+    }
+
+    pub async fn alive(&self) -> Result<PeerInfo> {
+        let response = self
+            .client
+            .get(self.url(path::ALIVE))
+            .timeout(TCP_TIMEOUT)
+            .send()
+            .await
+            .context("Failed to reach maker")?;
+
+        let peer_info = check_status(response).await?.json().await?;
+        Ok(peer_info)
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.endpoint)
+    }
+}
+
+/// Why the maker refused the request, if it did.
+pub fn error_code(e: &anyhow::Error) -> Option<ErrorCode> {
+    e.downcast_ref::<Problem>().map(|problem| problem.code)
+}
+
+/// Fails with the maker's [`Problem`] if the request was not successful.
+///
+/// Callers can tell why the request failed with [`error_code`].
+async fn check_status(response: Response) -> Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let body = response.text().await.unwrap_or_default();
+    tracing::debug!(%status, "Request to maker failed: {body}");
+
+    // Not every failure comes from our routes, e.g. Rocket's default catchers respond with HTML
+    let problem = serde_json::from_str::<Problem>(&body).unwrap_or(Problem {
+        code: ErrorCode::Unknown,
+        status: Some(status.as_u16()),
+        title: status.canonical_reason().map(str::to_owned),
+        detail: None,
+    });
+
+    Err(problem.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs against the maker given by the `SIGNET_MAKER_*` environment variables, with
+    /// `cargo test -- --ignored signet`.
+    #[tokio::test]
+    #[ignore = "needs a maker running on signet"]
+    async fn signet_maker_serves_the_taker() {
+        let maker = config::signet_maker().expect("SIGNET_MAKER_* environment variables to be set");
+        let client = MakerClient::new(maker.http_endpoint.clone());
+
+        let peer_info = client.alive().await.unwrap();
+        let offer = client.offer().await.unwrap();
+
+        assert_eq!(peer_info.pubkey, maker.pubkey);
+        assert!(offer.bid <= offer.ask);
+    }
+}
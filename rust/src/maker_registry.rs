@@ -0,0 +1,252 @@
+//! The makers the taker can trade with, persisted per network.
+//!
+//! Besides the maker we deploy for the network, users can add their own makers, e.g. to run
+//! against a local regtest maker. We stay connected to all of them and route orders to the one
+//! with the best price. The selected maker, or the deployed one if none is selected, is the one we
+//! open channels with and whose HTTP API we use.
+
+use crate::config;
+use crate::db;
+use crate::lightning::PeerAddr;
+use crate::lightning::PeerInfo;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use bdk::bitcoin::secp256k1::PublicKey;
+use bdk::bitcoin::Network;
+use futures::TryStreamExt;
+use reqwest::Url;
+use sqlx::Row;
+use std::sync::Mutex;
+
+/// The makers of the network and whether they are selected, cached so they can be read without
+/// going to the database.
+static MAKERS: Mutex<Option<Vec<(Maker, bool)>>> = Mutex::new(None);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Maker {
+    pub pubkey: PublicKey,
+    pub p2p_address: PeerAddr,
+    /// Without a trailing slash, e.g. `http://127.0.0.1:8000`.
+    pub http_endpoint: String,
+}
+
+impl Maker {
+    /// Parses the maker's details as a user enters them.
+    pub fn parse(pubkey: &str, p2p_address: &str, http_endpoint: &str) -> Result<Self> {
+        let pubkey: PublicKey = pubkey
+            .trim()
+            .parse()
+            .with_context(|| format!("Invalid maker public key {pubkey}"))?;
+        let p2p_address: PeerAddr = p2p_address.trim().parse()?;
+
+        let url = Url::parse(http_endpoint.trim())
+            .with_context(|| format!("Invalid maker endpoint {http_endpoint}"))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            bail!("Maker endpoint {http_endpoint} is not an HTTP URL");
+        }
+
+        Ok(Self {
+            pubkey,
+            p2p_address,
+            http_endpoint: url.as_str().trim_end_matches('/').to_owned(),
+        })
+    }
+
+    pub fn peer_info(&self) -> PeerInfo {
+        PeerInfo {
+            pubkey: self.pubkey,
+            peer_addr: self.p2p_address.clone(),
+        }
+    }
+}
+
+/// Reads the makers of the network from the database.
+///
+/// Has to be called once the database is initialised.
+pub async fn load() -> Result<()> {
+    let network = config::network();
+    let mut makers = load_makers(network).await?;
+
+    if let Some(default) = config::default_maker(network) {
+        if !makers
+            .iter()
+            .any(|(maker, _)| maker.pubkey == default.pubkey)
+        {
+            makers.insert(0, (default, false));
+        }
+    }
+
+    // Without a selection, we use the deployed maker or the first one the user added
+    if !makers.iter().any(|(_, selected)| *selected) {
+        if let Some((_, selected)) = makers.first_mut() {
+            *selected = true;
+        }
+    }
+
+    tracing::info!(makers = makers.len(), "Loaded makers");
+    *MAKERS.lock().expect("mutex not to be poisoned") = Some(makers);
+
+    Ok(())
+}
+
+/// The maker we open channels with and whose HTTP API we use.
+pub fn selected() -> Result<Maker> {
+    list()
+        .into_iter()
+        .find_map(|(maker, selected)| selected.then_some(maker))
+        .with_context(|| format!("No maker configured for {}", config::network()))
+}
+
+/// All makers of the network, the deployed one first, and whether they are selected.
+pub fn list() -> Vec<(Maker, bool)> {
+    match &*MAKERS.lock().expect("mutex not to be poisoned") {
+        Some(makers) => makers.clone(),
+        // Before loading, e.g. in the maker which has no registry, only the deployed maker
+        None => config::default_maker(config::network())
+            .map(|maker| vec![(maker, true)])
+            .unwrap_or_default(),
+    }
+}
+
+/// Whether the node is one of our makers, e.g. to only accept offers from makers.
+pub fn is_maker(pubkey: &PublicKey) -> bool {
+    list().iter().any(|(maker, _)| maker.pubkey == *pubkey)
+}
+
+/// Adds the maker, or updates its details if we know it already.
+pub async fn add(maker: &Maker) -> Result<()> {
+    let mut connection = db::acquire().await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO maker (network, pubkey, p2p_address, http_endpoint)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (network, pubkey) DO UPDATE SET
+            p2p_address = excluded.p2p_address,
+            http_endpoint = excluded.http_endpoint
+        "#,
+    )
+    .bind(config::network().to_string())
+    .bind(maker.pubkey.to_string())
+    .bind(maker.p2p_address.to_string())
+    .bind(&maker.http_endpoint)
+    .execute(&mut connection)
+    .await
+    .context("Failed to add maker")?;
+
+    load().await
+}
+
+/// Removes the maker, we fall back to the deployed maker if it was selected.
+///
+/// The caller has to disconnect from the maker.
+pub async fn remove(pubkey: PublicKey) -> Result<()> {
+    let mut connection = db::acquire().await?;
+
+    let result = sqlx::query("DELETE FROM maker WHERE network = $1 AND pubkey = $2")
+        .bind(config::network().to_string())
+        .bind(pubkey.to_string())
+        .execute(&mut connection)
+        .await
+        .context("Failed to remove maker")?;
+    if result.rows_affected() == 0 {
+        bail!("Unknown maker {pubkey}");
+    }
+
+    load().await
+}
+
+/// Selects the maker we open channels with and whose HTTP API we use.
+pub async fn select(pubkey: PublicKey) -> Result<()> {
+    let network = config::network().to_string();
+    let is_default = config::default_maker(config::network())
+        .map(|maker| maker.pubkey == pubkey)
+        .unwrap_or(false);
+
+    let mut connection = db::acquire().await?;
+    let known = sqlx::query("SELECT 1 FROM maker WHERE network = $1 AND pubkey = $2")
+        .bind(&network)
+        .bind(pubkey.to_string())
+        .fetch_optional(&mut connection)
+        .await?
+        .is_some();
+    if !known && !is_default {
+        bail!("Unknown maker {pubkey}, add it first");
+    }
+
+    // Selecting the deployed maker clears the selection, unless the user added it explicitly
+    sqlx::query("UPDATE maker SET selected = (pubkey = $2) WHERE network = $1")
+        .bind(&network)
+        .bind(pubkey.to_string())
+        .execute(&mut connection)
+        .await
+        .context("Failed to select maker")?;
+
+    load().await
+}
+
+async fn load_makers(network: Network) -> Result<Vec<(Maker, bool)>> {
+    let mut connection = db::acquire().await?;
+
+    let mut rows = sqlx::query(
+        r#"
+        SELECT pubkey, p2p_address, http_endpoint, selected
+        FROM maker
+        WHERE network = $1
+        ORDER BY pubkey
+        "#,
+    )
+    .bind(network.to_string())
+    .fetch(&mut connection);
+
+    let mut makers = Vec::new();
+    while let Some(row) = rows.try_next().await? {
+        let maker = Maker {
+            pubkey: row.try_get::<String, _>("pubkey")?.parse()?,
+            p2p_address: row.try_get::<String, _>("p2p_address")?.parse()?,
+            http_endpoint: row.try_get("http_endpoint")?,
+        };
+        makers.push((maker, row.try_get("selected")?));
+    }
+
+    Ok(makers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PUBKEY: &str = "02cb6517193c466de0688b8b0386dbfb39d96c3844525c1315d44bd8e108c08bc1";
+
+    #[test]
+    fn parses_maker() {
+        let maker = Maker::parse(PUBKEY, "127.0.0.1:9045", "http://127.0.0.1:8000/").unwrap();
+
+        assert_eq!(maker.pubkey.to_string(), PUBKEY);
+        assert_eq!(maker.p2p_address.port(), 9045);
+        assert_eq!(maker.http_endpoint, "http://127.0.0.1:8000");
+    }
+
+    #[test]
+    fn parses_onion_maker() {
+        let onion = "vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd.onion";
+        let maker = Maker::parse(
+            PUBKEY,
+            &format!("{onion}:9045"),
+            &format!("http://{onion}:8000"),
+        )
+        .unwrap();
+
+        assert!(matches!(maker.p2p_address, PeerAddr::Onion { .. }));
+        assert_eq!(maker.http_endpoint, format!("http://{onion}:8000"));
+    }
+
+    #[test]
+    fn rejects_invalid_maker() {
+        assert!(Maker::parse("02cb", "127.0.0.1:9045", "http://127.0.0.1:8000").is_err());
+        assert!(Maker::parse(PUBKEY, "127.0.0.1", "http://127.0.0.1:8000").is_err());
+        assert!(Maker::parse(PUBKEY, "127.0.0.1:9045", "127.0.0.1:8000").is_err());
+        assert!(Maker::parse(PUBKEY, "127.0.0.1:9045", "ftp://127.0.0.1").is_err());
+    }
+}
@@ -0,0 +1,387 @@
+//! Custom Lightning messages exchanged between taker and maker over their peer connection.
+
+use crate::cfd::models::Order;
+use crate::maker_registry;
+use crate::offer::Offer;
+use crate::offer::Offers;
+use bdk::bitcoin::secp256k1::PublicKey;
+use lightning::io;
+use lightning::ln::msgs::DecodeError;
+use lightning::ln::msgs::LightningError;
+use lightning::ln::peer_handler::CustomMessageHandler;
+use lightning::ln::wire::CustomMessageReader;
+use lightning::ln::wire::Type;
+use lightning::util::ser::Writeable;
+use lightning::util::ser::Writer;
+use maker_api::error::ErrorCode;
+use maker_api::error::Problem;
+use maker_api::OpenChannelRequest;
+use maker_api::OpenChannelResponse;
+use serde::Deserialize;
+use serde::Serialize;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+use tokio::sync::watch;
+
+// We use odd message types, so that peers that don't know about them just ignore them.
+const OFFER_TYPE: u16 = 48001;
+const ORDER_REQUEST_TYPE: u16 = 48003;
+const ORDER_ACCEPTED_TYPE: u16 = 48005;
+const OPEN_CHANNEL_REQUEST_TYPE: u16 = 48007;
+const OPEN_CHANNEL_RESPONSE_TYPE: u16 = 48009;
+const REJECT_TYPE: u16 = 48011;
+const CFD_SETTLED_TYPE: u16 = 48013;
+
+// The requests and responses above, tagged with a request ID. Peers running an older version only
+// know the untagged types, so we answer their requests with those.
+const ORDER_REQUEST_WITH_ID_TYPE: u16 = 48015;
+const ORDER_ACCEPTED_WITH_ID_TYPE: u16 = 48017;
+const OPEN_CHANNEL_REQUEST_WITH_ID_TYPE: u16 = 48019;
+const OPEN_CHANNEL_RESPONSE_WITH_ID_TYPE: u16 = 48021;
+const REJECT_WITH_ID_TYPE: u16 = 48023;
+
+/// How many inbound requests and responses we buffer for slow subscribers.
+const INBOUND_CAPACITY: usize = 100;
+
+/// Chosen by the requester and repeated in the response, so a response can't be mistaken for the
+/// response to another request.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestId(u64);
+
+impl RequestId {
+    pub fn random() -> Self {
+        Self(rand::random())
+    }
+}
+
+/// Requests and responses carry the [`RequestId`], unless they come from or go to a peer running
+/// an older version.
+#[derive(Debug, Clone)]
+pub enum Message {
+    /// The maker's current offer, broadcast to all connected takers.
+    Offer(Offer),
+    /// The taker asks the maker whether it is willing to take the other side of an order.
+    OrderRequest(Option<RequestId>, Order),
+    /// The maker agrees to the order at this price; the taker can go ahead and add the custom
+    /// output.
+    ///
+    /// Makers fill requests with an ID at their price, within the price the taker sent as its
+    /// limit, and requests without one at the taker's price.
+    OrderAccepted(Option<RequestId>, Option<f64>),
+    /// The taker asks the maker to fund its side of a new channel.
+    OpenChannelRequest(Option<RequestId>, OpenChannelRequest),
+    /// The maker has funded the taker's address.
+    OpenChannelResponse(Option<RequestId>, OpenChannelResponse),
+    /// The maker refuses a request.
+    ///
+    /// Requests without an ID only get the reason, without an error code.
+    Reject(Option<RequestId>, Problem),
+    /// The taker has settled the CFD with this custom output ID.
+    ///
+    /// Only informational, the maker learns about it from its channel.
+    CfdSettled(String),
+}
+
+impl Message {
+    /// The request this message responds to, if it is a response with a request ID.
+    pub fn response_to(&self) -> Option<RequestId> {
+        match self {
+            Message::OrderAccepted(id, _)
+            | Message::OpenChannelResponse(id, _)
+            | Message::Reject(id, _) => *id,
+            Message::Offer(_)
+            | Message::OrderRequest(..)
+            | Message::OpenChannelRequest(..)
+            | Message::CfdSettled(_) => None,
+        }
+    }
+
+    fn encode(&self) -> serde_json::Result<Vec<u8>> {
+        match self {
+            Message::Offer(offer) => serde_json::to_vec(offer),
+            Message::OrderRequest(id, order) => encode_with_id(*id, order),
+            Message::OrderAccepted(Some(id), price) => serde_json::to_vec(&(id, price)),
+            Message::OrderAccepted(None, _) => Ok(Vec::new()),
+            Message::OpenChannelRequest(id, request) => encode_with_id(*id, request),
+            Message::OpenChannelResponse(id, response) => encode_with_id(*id, response),
+            Message::Reject(Some(id), problem) => serde_json::to_vec(&(id, problem)),
+            Message::Reject(None, problem) => serde_json::to_vec(&problem.to_string()),
+            Message::CfdSettled(custom_output_id) => serde_json::to_vec(custom_output_id),
+        }
+    }
+
+    /// Decodes a message of the given type, returns `None` if the type is not ours.
+    fn decode(message_type: u16, bytes: &[u8]) -> serde_json::Result<Option<Self>> {
+        let message = match message_type {
+            OFFER_TYPE => Message::Offer(serde_json::from_slice(bytes)?),
+            ORDER_REQUEST_TYPE => Message::OrderRequest(None, serde_json::from_slice(bytes)?),
+            ORDER_ACCEPTED_TYPE => Message::OrderAccepted(None, None),
+            OPEN_CHANNEL_REQUEST_TYPE => {
+                Message::OpenChannelRequest(None, serde_json::from_slice(bytes)?)
+            }
+            OPEN_CHANNEL_RESPONSE_TYPE => {
+                Message::OpenChannelResponse(None, serde_json::from_slice(bytes)?)
+            }
+            REJECT_TYPE => Message::Reject(None, reason(serde_json::from_slice(bytes)?)),
+            CFD_SETTLED_TYPE => Message::CfdSettled(serde_json::from_slice(bytes)?),
+            ORDER_REQUEST_WITH_ID_TYPE => {
+                let (id, order) = serde_json::from_slice(bytes)?;
+                Message::OrderRequest(Some(id), order)
+            }
+            ORDER_ACCEPTED_WITH_ID_TYPE => {
+                let (id, price) = serde_json::from_slice(bytes)?;
+                Message::OrderAccepted(Some(id), price)
+            }
+            OPEN_CHANNEL_REQUEST_WITH_ID_TYPE => {
+                let (id, request) = serde_json::from_slice(bytes)?;
+                Message::OpenChannelRequest(Some(id), request)
+            }
+            OPEN_CHANNEL_RESPONSE_WITH_ID_TYPE => {
+                let (id, response) = serde_json::from_slice(bytes)?;
+                Message::OpenChannelResponse(Some(id), response)
+            }
+            REJECT_WITH_ID_TYPE => {
+                let (id, problem) = serde_json::from_slice(bytes)?;
+                Message::Reject(Some(id), problem)
+            }
+            _ => return Ok(None),
+        };
+
+        Ok(Some(message))
+    }
+}
+
+/// A rejection that only comes with a reason.
+fn reason(reason: String) -> Problem {
+    Problem {
+        code: ErrorCode::Unknown,
+        status: None,
+        title: None,
+        detail: Some(reason),
+    }
+}
+
+/// Encodes the payload after the request ID, if any.
+fn encode_with_id<T: Serialize>(id: Option<RequestId>, payload: &T) -> serde_json::Result<Vec<u8>> {
+    match id {
+        Some(id) => serde_json::to_vec(&(id, payload)),
+        None => serde_json::to_vec(payload),
+    }
+}
+
+impl Type for Message {
+    fn type_id(&self) -> u16 {
+        match self {
+            Message::Offer(_) => OFFER_TYPE,
+            Message::OrderRequest(None, _) => ORDER_REQUEST_TYPE,
+            Message::OrderRequest(Some(_), _) => ORDER_REQUEST_WITH_ID_TYPE,
+            Message::OrderAccepted(None, _) => ORDER_ACCEPTED_TYPE,
+            Message::OrderAccepted(Some(_), _) => ORDER_ACCEPTED_WITH_ID_TYPE,
+            Message::OpenChannelRequest(None, _) => OPEN_CHANNEL_REQUEST_TYPE,
+            Message::OpenChannelRequest(Some(_), _) => OPEN_CHANNEL_REQUEST_WITH_ID_TYPE,
+            Message::OpenChannelResponse(None, _) => OPEN_CHANNEL_RESPONSE_TYPE,
+            Message::OpenChannelResponse(Some(_), _) => OPEN_CHANNEL_RESPONSE_WITH_ID_TYPE,
+            Message::Reject(None, _) => REJECT_TYPE,
+            Message::Reject(Some(_), _) => REJECT_WITH_ID_TYPE,
+            Message::CfdSettled(_) => CFD_SETTLED_TYPE,
+        }
+    }
+}
+
+impl Writeable for Message {
+    fn write<W: Writer>(&self, writer: &mut W) -> Result<(), io::Error> {
+        let bytes = self
+            .encode()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        writer.write_all(&bytes)
+    }
+}
+
+/// Handles our custom messages on the Lightning peer connection.
+///
+/// The latest offer of each maker is published through a [`watch`] channel, every other message
+/// is broadcast to all subscribers, e.g. the maker's request handler or a taker waiting for a
+/// response.
+pub struct TenTenOneMessageHandler {
+    pending: Mutex<Vec<(PublicKey, Message)>>,
+    offers: watch::Sender<Offers>,
+    inbound: broadcast::Sender<(PublicKey, Message)>,
+}
+
+impl TenTenOneMessageHandler {
+    pub fn new() -> Self {
+        let (offers, _) = watch::channel(Offers::default());
+        let (inbound, _) = broadcast::channel(INBOUND_CAPACITY);
+
+        Self {
+            pending: Mutex::new(Vec::new()),
+            offers,
+            inbound,
+        }
+    }
+
+    /// Queues a message to be sent to the given peer.
+    ///
+    /// The message is only sent once the peer manager processes its events.
+    pub fn send(&self, node_id: PublicKey, message: Message) {
+        tracing::trace!(%node_id, ?message, "Queueing custom message");
+        self.pending
+            .lock()
+            .expect("mutex not to be poisoned")
+            .push((node_id, message));
+    }
+
+    pub fn subscribe_to_offers(&self) -> watch::Receiver<Offers> {
+        self.offers.subscribe()
+    }
+
+    pub fn offers(&self) -> Offers {
+        self.offers.borrow().clone()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<(PublicKey, Message)> {
+        self.inbound.subscribe()
+    }
+}
+
+impl Default for TenTenOneMessageHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CustomMessageReader for TenTenOneMessageHandler {
+    type CustomMessage = Message;
+
+    fn read<R: io::Read>(
+        &self,
+        message_type: u16,
+        buffer: &mut R,
+    ) -> Result<Option<Message>, DecodeError> {
+        let mut bytes = Vec::new();
+        buffer
+            .read_to_end(&mut bytes)
+            .map_err(|e| DecodeError::Io(e.kind()))?;
+
+        Message::decode(message_type, &bytes).map_err(|e| {
+            tracing::warn!(message_type, "Failed to decode custom message: {e:#}");
+            DecodeError::InvalidValue
+        })
+    }
+}
+
+impl CustomMessageHandler for TenTenOneMessageHandler {
+    fn handle_custom_message(
+        &self,
+        message: Message,
+        sender_node_id: &PublicKey,
+    ) -> Result<(), LightningError> {
+        tracing::debug!(%sender_node_id, ?message, "Received custom message");
+
+        match message {
+            Message::Offer(offer) => {
+                if !maker_registry::is_maker(sender_node_id) {
+                    tracing::warn!(%sender_node_id, "Ignoring offer from unknown peer");
+                    return Ok(());
+                }
+
+                self.offers
+                    .send_modify(|offers| offers.insert(*sender_node_id, offer));
+            }
+            message => {
+                // Nobody might be listening, which is fine
+                let _ = self.inbound.send((*sender_node_id, message));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn get_and_clear_pending_msg(&self) -> Vec<(PublicKey, Message)> {
+        std::mem::take(&mut *self.pending.lock().expect("mutex not to be poisoned"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offer_roundtrip() {
+        let message = Message::Offer(Offer {
+            bid: 16000.0,
+            ask: 16100.0,
+            index: 16050.0,
+        });
+
+        let bytes = message.encode().unwrap();
+        let decoded = Message::decode(message.type_id(), &bytes).unwrap();
+
+        assert!(matches!(
+            decoded,
+            Some(Message::Offer(Offer {
+                bid,
+                ask,
+                index
+            })) if bid == 16000.0 && ask == 16100.0 && index == 16050.0
+        ));
+    }
+
+    #[test]
+    fn reject_keeps_request_id() {
+        let id = RequestId::random();
+        let problem = Problem {
+            code: ErrorCode::QuoteStale,
+            ..reason("Price moved".to_owned())
+        };
+        let message = Message::Reject(Some(id), problem.clone());
+
+        let bytes = message.encode().unwrap();
+        let decoded = Message::decode(message.type_id(), &bytes).unwrap().unwrap();
+
+        assert_eq!(decoded.response_to(), Some(id));
+        assert!(matches!(decoded, Message::Reject(_, decoded) if decoded == problem));
+    }
+
+    #[test]
+    fn order_accepted_keeps_fill_price() {
+        let id = RequestId::random();
+        let message = Message::OrderAccepted(Some(id), Some(16_100.0));
+
+        let bytes = message.encode().unwrap();
+        let decoded = Message::decode(message.type_id(), &bytes).unwrap().unwrap();
+
+        assert_eq!(decoded.response_to(), Some(id));
+        assert!(matches!(decoded, Message::OrderAccepted(_, Some(price)) if price == 16_100.0));
+    }
+
+    #[test]
+    fn untagged_messages_keep_their_type() {
+        let message = Message::Reject(None, reason("Price moved".to_owned()));
+
+        let bytes = message.encode().unwrap();
+        let decoded = Message::decode(REJECT_TYPE, &bytes).unwrap().unwrap();
+
+        assert_eq!(message.type_id(), REJECT_TYPE);
+        assert_eq!(bytes, serde_json::to_vec("Price moved").unwrap());
+        assert_eq!(decoded.response_to(), None);
+        match decoded {
+            Message::Reject(None, problem) => assert_eq!(problem.to_string(), "Price moved"),
+            message => panic!("Unexpected message {message:?}"),
+        }
+    }
+
+    #[test]
+    fn requests_are_not_responses() {
+        let message = Message::CfdSettled("custom-output".to_owned());
+
+        assert_eq!(message.response_to(), None);
+    }
+
+    #[test]
+    fn unknown_type_is_ignored() {
+        let decoded = Message::decode(48999, b"").unwrap();
+
+        assert!(decoded.is_none());
+    }
+}
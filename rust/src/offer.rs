@@ -1,14 +1,37 @@
 use crate::api::Event;
 use crate::config::maker_endpoint;
-use anyhow::anyhow;
-use anyhow::bail;
+use crate::maker_client::error_code;
+use crate::maker_client::MakerClient;
+use anyhow::Context;
 use anyhow::Result;
+use bdk::bitcoin::secp256k1::PublicKey;
 use flutter_rust_bridge::StreamSink;
-use reqwest::StatusCode;
+use futures::TryStreamExt;
+use maker_api::error::ErrorCode;
+use rust_decimal::prelude::ToPrimitive;
 use serde::Deserialize;
 use serde::Serialize;
+use std::collections::HashMap;
+use std::time::Duration;
+use std::time::Instant;
+use tokio::sync::watch;
 use tokio::task::JoinHandle;
 
+/// If the maker does not send us an offer over the Lightning connection for this long, we fall
+/// back to its HTTP API until it does.
+const P2P_OFFER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often we poll for an offer if the maker's offer stream is unavailable.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How many times we poll before trying to subscribe to the offer stream again.
+const POLLS_BEFORE_RESUBSCRIBE: u32 = 12;
+
+/// If we don't receive anything on the offer stream for this long, we consider it dead.
+///
+/// The maker sends a heartbeat every 30 seconds, even if there are no new offers.
+const STREAM_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Offer {
     pub bid: f64,
@@ -16,29 +39,239 @@ pub struct Offer {
     pub index: f64,
 }
 
-pub fn spawn(stream: StreamSink<Event>) -> JoinHandle<()> {
+/// The latest offer of each maker, as received over the Lightning connections.
+#[derive(Debug, Clone, Default)]
+pub struct Offers(HashMap<PublicKey, (Offer, Instant)>);
+
+impl Offers {
+    pub fn insert(&mut self, maker: PublicKey, offer: Offer) {
+        self.0.insert(maker, (offer, Instant::now()));
+    }
+
+    /// The offers of the makers that still send us offers, older ones are likely outdated.
+    pub fn current(&self) -> impl Iterator<Item = (PublicKey, &Offer)> {
+        self.0
+            .iter()
+            .filter(|(_, (_, received))| received.elapsed() < P2P_OFFER_TIMEOUT)
+            .map(|(maker, (offer, _))| (*maker, offer))
+    }
+
+    /// The best prices across makers, i.e. the highest bid and the lowest ask.
+    pub fn best(&self) -> Option<Offer> {
+        let offers = self.current().map(|(_, offer)| offer).collect::<Vec<_>>();
+        if offers.is_empty() {
+            return None;
+        }
+
+        Some(Offer {
+            bid: offers
+                .iter()
+                .map(|offer| offer.bid)
+                .fold(f64::MIN, f64::max),
+            ask: offers
+                .iter()
+                .map(|offer| offer.ask)
+                .fold(f64::MAX, f64::min),
+            index: offers.iter().map(|offer| offer.index).sum::<f64>() / offers.len() as f64,
+        })
+    }
+}
+
+impl From<maker_api::Offer> for Offer {
+    fn from(offer: maker_api::Offer) -> Self {
+        Self {
+            bid: offer.bid.to_f64().expect("price to fit into f64"),
+            ask: offer.ask.to_f64().expect("price to fit into f64"),
+            index: offer.index.to_f64().expect("price to fit into f64"),
+        }
+    }
+}
+
+/// Forwards the best prices of the offers the makers send over the Lightning connections.
+///
+/// Falls back to the selected maker's HTTP API whenever no maker sends offers over the Lightning
+/// connection, e.g. because we are not connected.
+pub fn spawn(stream: StreamSink<Event>, mut p2p_offers: watch::Receiver<Offers>) -> JoinHandle<()> {
     tokio::spawn(async move {
         loop {
-            let offer = get_offer().await.ok();
+            tokio::select! {
+                changed = p2p_offers.changed() => {
+                    if changed.is_err() {
+                        tracing::error!("Stopped receiving offers over Lightning");
+                        return;
+                    }
+                }
+                _ = tokio::time::sleep(P2P_OFFER_TIMEOUT) => {
+                    tracing::debug!("No offer over Lightning, falling back to the maker's HTTP API");
+
+                    tokio::select! {
+                        changed = p2p_offers.changed() => {
+                            if changed.is_err() {
+                                tracing::error!("Stopped receiving offers over Lightning");
+                                return;
+                            }
+                            tracing::debug!("Receiving offers over Lightning again");
+                        }
+                        _ = stream_over_http(&stream) => {}
+                    }
+                }
+            }
+
+            let offer = p2p_offers.borrow_and_update().best();
             stream.add(Event::Offer(offer));
-            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
         }
     })
 }
 
+/// Subscribes to the maker's HTTP offer stream, falling back to polling when the stream is
+/// unavailable.
+///
+/// Never returns, drop the future to stop.
+async fn stream_over_http(stream: &StreamSink<Event>) {
+    loop {
+        // Read on every attempt, the user might have selected another maker
+        let client = match maker_endpoint() {
+            Ok(endpoint) => MakerClient::new(endpoint),
+            Err(e) => {
+                tracing::warn!("Cannot fetch offers: {e:#}");
+                tokio::time::sleep(POLL_INTERVAL).await;
+                continue;
+            }
+        };
+
+        if let Err(e) = subscribe(&client, stream).await {
+            tracing::debug!("Offer stream unavailable, falling back to polling: {e:#}");
+        }
+
+        for _ in 0..POLLS_BEFORE_RESUBSCRIBE {
+            let offer = match client.offer().await {
+                Ok(offer) => Some(offer.into()),
+                Err(e) => {
+                    match error_code(&e) {
+                        Some(ErrorCode::QuoteUnavailable | ErrorCode::QuoteStale) => {
+                            tracing::debug!("Maker has no offer: {e:#}")
+                        }
+                        _ => tracing::warn!("Failed to fetch offer: {e:#}"),
+                    }
+                    None
+                }
+            };
+            stream.add(Event::Offer(offer));
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+}
+
+/// Forwards every offer pushed by the maker until the stream ends.
+async fn subscribe(client: &MakerClient, stream: &StreamSink<Event>) -> Result<()> {
+    let response = client.offer_stream().await?;
+
+    tracing::debug!("Subscribed to offer stream");
+
+    let mut bytes = response.bytes_stream();
+    let mut buffer = Vec::new();
+
+    loop {
+        let chunk = tokio::time::timeout(STREAM_IDLE_TIMEOUT, bytes.try_next())
+            .await
+            .context("Offer stream timed out")??
+            .context("Offer stream closed by maker")?;
+        buffer.extend_from_slice(&chunk);
+
+        while let Some(end) = buffer.windows(2).position(|window| window == b"\n\n") {
+            let event = buffer.drain(..end + 2).collect::<Vec<_>>();
+            let event = std::str::from_utf8(&event)?;
+
+            if let Some(offer) = parse_event(event)? {
+                stream.add(Event::Offer(Some(offer)));
+            }
+        }
+    }
+}
+
+/// Parses a server-sent event into an offer.
+///
+/// Returns `None` for events without data, e.g. heartbeats.
+fn parse_event(event: &str) -> Result<Option<Offer>> {
+    let data = event
+        .lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .map(|data| data.strip_prefix(' ').unwrap_or(data))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    if data.is_empty() {
+        return Ok(None);
+    }
+
+    let offer = serde_json::from_str::<maker_api::Offer>(&data)
+        .context("Failed to parse offer from stream")?;
+    Ok(Some(offer.into()))
+}
+
 pub async fn get_offer() -> Result<Offer> {
-    let client = reqwest::Client::builder()
-        .timeout(crate::config::TCP_TIMEOUT)
-        .build()?;
-    let response = client.get(maker_endpoint() + "/api/offer").send().await?;
+    let offer = MakerClient::new(maker_endpoint()?).offer().await?;
+    Ok(offer.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bdk::bitcoin::secp256k1::Secp256k1;
+    use bdk::bitcoin::secp256k1::SecretKey;
 
-    if response.status() == StatusCode::NOT_FOUND
-        || response.status() == StatusCode::INTERNAL_SERVER_ERROR
-    {
-        let response = response.text().await?;
-        tracing::debug!("Failed to fetch offer: {response}");
-        bail!("Failed to fetch offer: {response}");
+    fn maker(byte: u8) -> PublicKey {
+        let secret = SecretKey::from_slice(&[byte; 32]).unwrap();
+        PublicKey::from_secret_key(&Secp256k1::new(), &secret)
     }
 
-    response.json::<Offer>().await.map_err(|e| anyhow!(e))
+    #[test]
+    fn parses_offer_event() {
+        let offer = parse_event("data:{\"bid\":16000.5,\"ask\":16100.0,\"index\":16050.0}\n\n")
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(offer.bid, 16000.5);
+        assert_eq!(offer.ask, 16100.0);
+        assert_eq!(offer.index, 16050.0);
+    }
+
+    #[test]
+    fn best_offer_combines_best_prices_of_makers() {
+        let mut offers = Offers::default();
+        offers.insert(
+            maker(1),
+            Offer {
+                bid: 16000.0,
+                ask: 16200.0,
+                index: 16100.0,
+            },
+        );
+        offers.insert(
+            maker(2),
+            Offer {
+                bid: 15900.0,
+                ask: 16100.0,
+                index: 16000.0,
+            },
+        );
+
+        let best = offers.best().unwrap();
+
+        assert_eq!(best.bid, 16000.0);
+        assert_eq!(best.ask, 16100.0);
+        assert_eq!(best.index, 16050.0);
+    }
+
+    #[test]
+    fn no_best_offer_without_offers() {
+        assert!(Offers::default().best().is_none());
+    }
+
+    #[test]
+    fn heartbeat_is_not_an_offer() {
+        let offer = parse_event(":\n\n").unwrap();
+
+        assert!(offer.is_none());
+    }
 }
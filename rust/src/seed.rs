@@ -1,45 +1,113 @@
 use std::path::Path;
 
+use aes_gcm::aead::Aead;
+use aes_gcm::Aes256Gcm;
+use aes_gcm::KeyInit;
+use aes_gcm::Nonce;
+use anyhow::anyhow;
 use anyhow::bail;
+use anyhow::Context;
 use anyhow::Result;
+use argon2::Argon2;
 use bdk::bitcoin;
 use bdk::bitcoin::util::bip32::ExtendedPrivKey;
 use bip39::Language;
 use bip39::Mnemonic;
 use bitcoin::Network;
 use hkdf::Hkdf;
+use rand::Rng;
 use sha2::Sha256;
 
+/// Marks seed files holding the entropy encrypted with a password.
+const ENCRYPTED_MAGIC: &[u8] = b"10101enc";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
 #[derive(Clone)]
 pub struct Bip39Seed {
     mnemonic: Mnemonic,
+    /// The optional BIP39 passphrase, the "25th word", empty without one.
+    ///
+    /// It is never stored, a different passphrase leads to a different wallet.
+    passphrase: String,
 }
 
 impl Bip39Seed {
     pub fn new() -> Result<Self> {
         let mut rng = rand::thread_rng();
         let mnemonic = Mnemonic::generate_in_with(&mut rng, Language::English, 12)?;
-        Ok(Self { mnemonic })
+        Ok(Self {
+            mnemonic,
+            passphrase: String::new(),
+        })
     }
 
     /// Initialise a [`Seed`] from a path.
     /// Generates new seed if there was no seed found in the given path
-    pub fn initialize(seed_file: &Path) -> Result<Self> {
+    ///
+    /// With a password the seed is stored encrypted, an unencrypted seed gets encrypted the first
+    /// time a password is given.
+    pub fn initialize(seed_file: &Path, password: Option<&str>) -> Result<Self> {
         let seed = if !seed_file.exists() {
             tracing::info!("No seed found. Generating new seed");
             let seed = Self::new()?;
-            seed.write_to(seed_file)?;
+            seed.write_to(seed_file, password)?;
             seed
         } else {
-            Bip39Seed::read_from(seed_file)?
+            let (seed, encrypted) = Bip39Seed::read_from(seed_file, password)?;
+            if let (false, Some(password)) = (encrypted, password) {
+                tracing::info!("Encrypting seed");
+                seed.encrypt(seed_file, password)?;
+            }
+            seed
+        };
+        Ok(seed)
+    }
+
+    /// Writes the seed of the mnemonic to the path, to restore a wallet from its seed phrase.
+    ///
+    /// Refuses to replace an existing seed, as that would lose its funds.
+    pub fn restore(seed_file: &Path, words: &[String], password: Option<&str>) -> Result<Self> {
+        if !matches!(words.len(), 12 | 24) {
+            bail!("Expected 12 or 24 words, got {}", words.len());
+        }
+
+        let phrase = words
+            .iter()
+            .map(|word| word.trim().to_lowercase())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let mnemonic = Mnemonic::parse_in_normalized(Language::English, &phrase)
+            .context("Invalid seed phrase")?;
+
+        if seed_file.exists() {
+            bail!("A wallet exists already, refusing to replace its seed");
+        }
+
+        let seed = Self {
+            mnemonic,
+            passphrase: String::new(),
         };
+        seed.write_to(seed_file, password)?;
+
         Ok(seed)
     }
 
+    /// Derives the seed with the BIP39 passphrase, which is not stored with the seed.
+    pub fn with_passphrase(self, passphrase: &str) -> Self {
+        Self {
+            passphrase: passphrase.to_owned(),
+            ..self
+        }
+    }
+
+    pub fn has_passphrase(&self) -> bool {
+        !self.passphrase.is_empty()
+    }
+
     pub fn seed(&self) -> [u8; 64] {
-        // passing an empty string here is the expected argument if the seed should not be
-        // additionally password protected (according to https://github.com/bitcoin/bips/blob/master/bip-0039.mediawiki#from-mnemonic-to-seed)
-        self.mnemonic.to_seed_normalized("")
+        // without a passphrase, the empty string is the expected argument (according to https://github.com/bitcoin/bips/blob/master/bip-0039.mediawiki#from-mnemonic-to-seed)
+        self.mnemonic.to_seed_normalized(&self.passphrase)
     }
 
     pub fn derive_extended_priv_key(&self, network: Network) -> Result<ExtendedPrivKey> {
@@ -57,32 +125,97 @@ impl Bip39Seed {
         self.mnemonic.word_iter().map(|word| word.into()).collect()
     }
 
-    // Read the entropy used to generate Mnemonic from disk
-    fn read_from(path: &Path) -> Result<Self> {
+    // Read the entropy used to generate Mnemonic from disk, and whether it was encrypted
+    fn read_from(path: &Path, password: Option<&str>) -> Result<(Self, bool)> {
         let bytes = std::fs::read(path)?;
 
+        let (bytes, encrypted) = match bytes.strip_prefix(ENCRYPTED_MAGIC) {
+            Some(encrypted) => {
+                let password = password.context("The seed is encrypted, a password is required")?;
+                (decrypt(encrypted, password)?, true)
+            }
+            None => (bytes, false),
+        };
+
         let seed: Bip39Seed = TryInto::try_into(bytes)
             .map_err(|_| anyhow::anyhow!("Cannot read the stored entropy"))?;
-        Ok(seed)
+        Ok((seed, encrypted))
     }
 
     // Store the entropy used to generate Mnemonic on disk
-    fn write_to(&self, path: &Path) -> Result<()> {
+    fn write_to(&self, path: &Path, password: Option<&str>) -> Result<()> {
         if path.exists() {
             let path = path.display();
             bail!("Refusing to overwrite file at {path}")
         }
-        std::fs::write(path, &self.mnemonic.to_entropy())?;
+        std::fs::write(path, self.file_contents(password)?)?;
+
+        Ok(())
+    }
+
+    // Replace the unencrypted seed on disk, without ever leaving a partially written file
+    fn encrypt(&self, path: &Path, password: &str) -> Result<()> {
+        let encrypted_path = path.with_extension("encrypted");
+        std::fs::write(&encrypted_path, self.file_contents(Some(password))?)?;
+        std::fs::rename(&encrypted_path, path)?;
 
         Ok(())
     }
+
+    fn file_contents(&self, password: Option<&str>) -> Result<Vec<u8>> {
+        let entropy = self.mnemonic.to_entropy();
+
+        match password {
+            Some(password) => Ok([ENCRYPTED_MAGIC, &encrypt(&entropy, password)?].concat()),
+            None => Ok(entropy),
+        }
+    }
+}
+
+fn encrypt(plaintext: &[u8], password: &str) -> Result<Vec<u8>> {
+    let mut rng = rand::thread_rng();
+    let mut salt = [0u8; SALT_LEN];
+    rng.fill(&mut salt);
+    let mut nonce = [0u8; NONCE_LEN];
+    rng.fill(&mut nonce);
+
+    let ciphertext = cipher(password, &salt)?
+        .encrypt(Nonce::from_slice(&nonce), plaintext)
+        .map_err(|_| anyhow!("Failed to encrypt seed"))?;
+
+    Ok([&salt[..], &nonce, &ciphertext].concat())
+}
+
+fn decrypt(bytes: &[u8], password: &str) -> Result<Vec<u8>> {
+    if bytes.len() < SALT_LEN + NONCE_LEN {
+        bail!("Encrypted seed is truncated");
+    }
+    let (salt, rest) = bytes.split_at(SALT_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+
+    cipher(password, salt)?
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow!("Failed to decrypt seed, is the password correct?"))
+}
+
+/// Derives the key from the password, Argon2 makes guessing the password expensive.
+fn cipher(password: &str, salt: &[u8]) -> Result<Aes256Gcm> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(password.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow!("Failed to derive key from password: {e}"))?;
+
+    Ok(Aes256Gcm::new_from_slice(&key).expect("key to have the right length"))
 }
 
 impl TryFrom<Vec<u8>> for Bip39Seed {
     type Error = anyhow::Error;
     fn try_from(bytes: Vec<u8>) -> Result<Self, Self::Error> {
         let mnemonic = Mnemonic::from_entropy(&bytes)?;
-        Ok(Bip39Seed { mnemonic })
+        Ok(Bip39Seed {
+            mnemonic,
+            passphrase: String::new(),
+        })
     }
 }
 
@@ -91,6 +224,8 @@ mod tests {
     use std::env::temp_dir;
 
     use crate::seed::Bip39Seed;
+    use crate::seed::Network;
+    use crate::seed::ENCRYPTED_MAGIC;
 
     #[test]
     fn create_bip39_seed() {
@@ -103,8 +238,8 @@ mod tests {
     fn reinitialised_seed_is_the_same() {
         let mut path = temp_dir();
         path.push("seed");
-        let seed_1 = Bip39Seed::initialize(&path).unwrap();
-        let seed_2 = Bip39Seed::initialize(&path).unwrap();
+        let seed_1 = Bip39Seed::initialize(&path, None).unwrap();
+        let seed_2 = Bip39Seed::initialize(&path, None).unwrap();
         assert_eq!(
             seed_1.mnemonic, seed_2.mnemonic,
             "Reinitialised wallet should contain the same mnemonic"
@@ -115,4 +250,79 @@ mod tests {
             "Seed derived from mnemonic should be the same"
         );
     }
+
+    #[test]
+    fn encrypted_seed_requires_password() {
+        let path = temp_dir().join("encrypted_seed");
+        let _ = std::fs::remove_file(&path);
+
+        let seed = Bip39Seed::initialize(&path, Some("s3cr3t")).unwrap();
+        let reinitialised = Bip39Seed::initialize(&path, Some("s3cr3t")).unwrap();
+
+        assert_eq!(reinitialised.mnemonic, seed.mnemonic);
+        assert!(std::fs::read(&path).unwrap().starts_with(ENCRYPTED_MAGIC));
+        assert!(Bip39Seed::initialize(&path, None).is_err());
+        assert!(Bip39Seed::initialize(&path, Some("wrong")).is_err());
+    }
+
+    #[test]
+    fn restored_seed_has_the_same_phrase() {
+        let path = temp_dir().join("restored_seed");
+        let _ = std::fs::remove_file(&path);
+        let phrase = Bip39Seed::new().unwrap().get_seed_phrase();
+
+        let restored = Bip39Seed::restore(&path, &phrase, None).unwrap();
+        let reinitialised = Bip39Seed::initialize(&path, None).unwrap();
+
+        assert_eq!(restored.get_seed_phrase(), phrase);
+        assert_eq!(reinitialised.mnemonic, restored.mnemonic);
+        assert!(
+            Bip39Seed::restore(&path, &phrase, None).is_err(),
+            "Restoring must not replace an existing seed"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_seed_phrase() {
+        let path = temp_dir().join("invalid_restored_seed");
+        let _ = std::fs::remove_file(&path);
+        let phrase = Bip39Seed::new().unwrap().get_seed_phrase();
+        let mut unknown_word = phrase.clone();
+        unknown_word[0] = "notaword".to_owned();
+        // Valid words, but the checksum doesn't match
+        let bad_checksum = vec!["abandon".to_owned(); 12];
+
+        assert!(Bip39Seed::restore(&path, &phrase[..11], None).is_err());
+        assert!(Bip39Seed::restore(&path, &unknown_word, None).is_err());
+        assert!(Bip39Seed::restore(&path, &bad_checksum, None).is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn passphrase_leads_to_a_different_wallet() {
+        let seed = Bip39Seed::new().unwrap();
+        let with_passphrase = seed.clone().with_passphrase("25th word");
+
+        assert_ne!(seed.seed(), with_passphrase.seed());
+        assert_ne!(
+            seed.derive_extended_priv_key(Network::Testnet).unwrap(),
+            with_passphrase
+                .derive_extended_priv_key(Network::Testnet)
+                .unwrap()
+        );
+        assert_eq!(seed.seed(), seed.clone().with_passphrase("").seed());
+        assert_eq!(seed.get_seed_phrase(), with_passphrase.get_seed_phrase());
+    }
+
+    #[test]
+    fn unencrypted_seed_gets_encrypted() {
+        let path = temp_dir().join("unencrypted_seed");
+        let _ = std::fs::remove_file(&path);
+
+        let seed = Bip39Seed::initialize(&path, None).unwrap();
+        let reinitialised = Bip39Seed::initialize(&path, Some("s3cr3t")).unwrap();
+
+        assert_eq!(reinitialised.mnemonic, seed.mnemonic);
+        assert!(std::fs::read(&path).unwrap().starts_with(ENCRYPTED_MAGIC));
+    }
 }
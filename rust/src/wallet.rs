@@ -1,18 +1,21 @@
+use crate::chain;
 use crate::config;
-use crate::config::maker_endpoint;
-use crate::config::TCP_TIMEOUT;
 use crate::db;
 use crate::db::clean_expired_payments;
 use crate::db::load_payments;
 use crate::db::update_ignore_txid;
 use crate::lightning;
 use crate::lightning::ChannelManager;
+use crate::lightning::CustomOutputFilter;
 use crate::lightning::Flow;
 use crate::lightning::HTLCStatus;
 use crate::lightning::LightningSystem;
 use crate::lightning::NodeInfo;
 use crate::lightning::PeerInfo;
 use crate::lightning::PeerManager;
+use crate::message::Message;
+use crate::message::RequestId;
+use crate::offer::Offers;
 use crate::seed::Bip39Seed;
 use ::lightning::chain::chaininterface::ConfirmationTarget;
 use ::lightning::ln::channelmanager::ChannelDetails;
@@ -27,43 +30,46 @@ use bdk::bitcoin::Address;
 use bdk::bitcoin::Amount;
 use bdk::bitcoin::Network;
 use bdk::bitcoin::Script;
+use bdk::bitcoin::Transaction;
 use bdk::bitcoin::Txid;
-use bdk::blockchain::ElectrumBlockchain;
-use bdk::electrum_client::Client;
 use bdk::wallet::wallet_name_from_descriptor;
 use bdk::FeeRate;
 use bdk::KeychainKind;
 use bdk::SignOptions;
+use bdk::SyncOptions;
 use bdk_ldk::ScriptStatus;
-use lightning_background_processor::BackgroundProcessor;
 use lightning_invoice::Invoice;
+use maker_api::OpenChannelRequest;
 use rust_decimal::prelude::FromPrimitive;
 use serde::Deserialize;
 use serde::Serialize;
 use state::Storage;
 use std::net::SocketAddr;
 use std::path::Path;
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::task::JoinHandle;
+use tokio::sync::broadcast;
+use tokio::sync::watch;
 
 /// Wallet has to be managed by Rust as generics are not support by frb
 static WALLET: Storage<Wallet> = Storage::new();
 
 pub struct Wallet {
     seed: Bip39Seed,
+    blockchain: Arc<chain::Failover>,
     pub lightning: LightningSystem,
     network: Network,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Balance {
     pub on_chain: OnChain,
     pub off_chain: OffChain,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OnChain {
     /// Unconfirmed UTXOs generated by a wallet tx
     pub trusted_pending: u64,
@@ -73,7 +79,7 @@ pub struct OnChain {
     pub confirmed: u64,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OffChain {
     pub available: u64,
     pub pending_close: u64,
@@ -82,20 +88,35 @@ pub struct OffChain {
 impl Wallet {
     pub fn new(data_dir: &Path) -> Result<Wallet> {
         let network = config::network();
-        let electrum_str = config::electrum_url();
-        tracing::info!(?network, electrum_str, "Creating the wallet");
-
-        let data_dir = data_dir.join(&network.to_string());
-        if !data_dir.exists() {
-            std::fs::create_dir(&data_dir)
-                .context(format!("Could not create data dir for {network}"))?;
-        }
-        let seed_path = data_dir.join("seed");
-        let seed = Bip39Seed::initialize(&seed_path)?;
+        let chain_backends = config::chain_backends();
+        tracing::info!(?network, ?chain_backends, "Creating the wallet");
+
+        let data_dir = network_data_dir(data_dir, network)?;
+        let seed_path = data_dir.join(SEED_FILE);
+        let restored = data_dir.join(RESCAN_FILE).exists();
+        // As opposed to a seed we create or restore now
+        let existing_seed = seed_path.exists() && !restored;
+        let seed = Bip39Seed::initialize(&seed_path, config::seed_password().as_deref())?
+            .with_passphrase(&config::seed_passphrase().unwrap_or_default());
         let ext_priv_key = seed.derive_extended_priv_key(network)?;
 
-        let client = Client::new(&electrum_str)?;
-        let blockchain = ElectrumBlockchain::from(client);
+        let fingerprint = ext_priv_key.fingerprint(&Secp256k1::new()).to_string();
+        let fingerprint_path = data_dir.join(FINGERPRINT_FILE);
+        let recorded = match fingerprint_path.exists() {
+            true => Some(std::fs::read_to_string(&fingerprint_path)?),
+            false => None,
+        };
+        check_fingerprint(
+            recorded.as_deref(),
+            &fingerprint,
+            existing_seed,
+            seed.has_passphrase(),
+        )?;
+        // Restored wallets only get their fingerprint once we found their funds
+        if recorded.is_none() && !restored {
+            std::fs::write(&fingerprint_path, &fingerprint)
+                .context("Failed to store the wallet's fingerprint")?;
+        }
 
         let wallet_name = wallet_name_from_descriptor(
             bdk::template::Bip84(ext_priv_key, KeychainKind::External),
@@ -104,6 +125,8 @@ impl Wallet {
             &Secp256k1::new(),
         )?;
 
+        let blockchain = Arc::new(chain::Failover::new(chain_backends, network, &wallet_name)?);
+
         // Create a database (using default sled type) to store wallet data
         let db = bdk::sled::open(data_dir.join("wallet"))?;
         let db = db.open_tree(wallet_name)?;
@@ -115,7 +138,32 @@ impl Wallet {
             db,
         )?;
 
-        let lightning_wallet = bdk_ldk::LightningWallet::new(Box::new(blockchain), bdk_wallet);
+        if restored {
+            // Scan before the Lightning node starts, so that it doesn't create state for a seed
+            // derived with the wrong passphrase
+            tracing::info!("Rescanning the chain for the funds of the restored wallet");
+            bdk_wallet
+                .sync(&*blockchain, SyncOptions::default())
+                .context("Failed to rescan the chain")?;
+
+            let empty = bdk_wallet.list_transactions(false)?.is_empty();
+            if seed.has_passphrase() && empty && !config::empty_restored_wallet_accepted() {
+                bail!(
+                    "No funds found for the restored wallet. A wrong passphrase leads to a \
+                     different, empty wallet: correct the passphrase, or confirm that the wallet \
+                     has no on-chain funds to open it anyway"
+                );
+            }
+
+            let balance = bdk_wallet.get_balance()?;
+            tracing::info!(%balance, "Rescanned the chain");
+            std::fs::write(&fingerprint_path, &fingerprint)
+                .context("Failed to store the wallet's fingerprint")?;
+            std::fs::remove_file(data_dir.join(RESCAN_FILE))?;
+        }
+
+        let lightning_wallet =
+            bdk_ldk::LightningWallet::new(Box::new(blockchain.clone()), bdk_wallet);
 
         // Lightning seed needs to be shorter
         let lightning_seed = &seed.seed()[0..32].try_into()?;
@@ -125,6 +173,7 @@ impl Wallet {
         Ok(Wallet {
             lightning,
             seed,
+            blockchain,
             network,
         })
     }
@@ -238,18 +287,23 @@ impl Wallet {
     }
 
     /// Run the lightning node
-    pub async fn run_ldk(&self) -> Result<BackgroundProcessor> {
+    pub async fn run_ldk(&self) -> Result<()> {
         lightning::run_ldk(&self.lightning).await
     }
 
     /// Run the lightning node
-    pub async fn run_ldk_server(
-        &self,
-        address: SocketAddr,
-    ) -> Result<(JoinHandle<()>, BackgroundProcessor)> {
+    pub async fn run_ldk_server(&self, address: SocketAddr) -> Result<()> {
         lightning::run_ldk_server(&self.lightning, address).await
     }
 
+    pub fn persist(&self) -> Result<()> {
+        lightning::persist(&self.lightning)
+    }
+
+    pub fn shutdown(&self) -> Result<()> {
+        lightning::shutdown(&self.lightning)
+    }
+
     pub async fn get_bitcoin_tx_history(&self) -> Result<Vec<bdk::TransactionDetails>> {
         let tx_history = self
             .lightning
@@ -350,6 +404,17 @@ impl Wallet {
     pub fn send_to_address(&self, send_to: Address, amount: u64) -> Result<Txid> {
         tracing::debug!(address = %send_to, sats = %amount, "Sending to address");
 
+        let tx = self.build_transaction(send_to.clone(), amount)?;
+        let txid = tx.txid();
+        self.lightning.wallet.broadcast(&tx)?;
+
+        tracing::debug!(address = %send_to, sats = %amount, %txid, "Sent to address");
+
+        Ok(txid)
+    }
+
+    /// Builds and signs a transaction sending the amount to the address, without broadcasting it.
+    pub fn build_transaction(&self, send_to: Address, amount: u64) -> Result<Transaction> {
         let wallet = self.lightning.wallet.get_wallet()?;
 
         let estimated_fee_rate = self
@@ -382,14 +447,7 @@ impl Wallet {
             bail!("Failed to sign psbt");
         }
 
-        let tx = psbt.extract_tx();
-        let txid = tx.txid();
-
-        self.lightning.wallet.broadcast(&tx)?;
-
-        tracing::debug!(address = %send_to, sats = %amount, %txid, "Sent to address");
-
-        Ok(txid)
+        Ok(psbt.extract_tx())
     }
 
     /// Fee recommendation in sats per vbyte.
@@ -412,22 +470,119 @@ fn get_wallet() -> &'static Wallet {
 
 /// Boilerplate wrappers for using Wallet with static functions in the library
 
+/// Holds the wallet's seed in the data directory of the network.
+const SEED_FILE: &str = "seed";
+
+/// Marks a restored wallet whose funds we haven't looked for yet.
+///
+/// The wallet stays marked until the scan succeeded, so an interrupted scan is repeated on the
+/// next start.
+const RESCAN_FILE: &str = "rescan";
+
+/// Holds the fingerprint of the wallet's key, to tell a wrong passphrase from a new wallet.
+const FINGERPRINT_FILE: &str = "fingerprint";
+
+/// Checks that the seed with the passphrase gives the wallet we recorded.
+///
+/// Existing seeds without a recorded fingerprint date from before we recorded fingerprints, when
+/// the wallet had no passphrase.
+fn check_fingerprint(
+    recorded: Option<&str>,
+    fingerprint: &str,
+    existing_seed: bool,
+    has_passphrase: bool,
+) -> Result<()> {
+    match recorded {
+        Some(recorded) if recorded.trim() != fingerprint => {
+            bail!("Wrong passphrase, the seed was used with a different one")
+        }
+        Some(_) => {}
+        None if existing_seed && has_passphrase => {
+            bail!(
+                "The seed was used without a passphrase, a passphrase would lead to another wallet"
+            )
+        }
+        None => {}
+    }
+
+    Ok(())
+}
+
+fn network_data_dir(data_dir: &Path, network: Network) -> Result<PathBuf> {
+    let data_dir = data_dir.join(network.to_string());
+    if !data_dir.exists() {
+        std::fs::create_dir_all(&data_dir)
+            .context(format!("Could not create data dir for {network}"))?;
+    }
+
+    Ok(data_dir)
+}
+
+/// Writes the seed of the seed phrase, so the wallet starts with it.
+///
+/// Has to be called before the wallet is initialised, which then looks for the wallet's funds on
+/// chain before the Lightning node starts. Syncing a new wallet scans the addresses until it finds
+/// as many unused addresses in a row as the gap limit.
+pub fn restore_from_mnemonic(data_dir: &Path, words: &[String]) -> Result<()> {
+    let data_dir = network_data_dir(data_dir, config::network())?;
+
+    Bip39Seed::restore(
+        &data_dir.join(SEED_FILE),
+        words,
+        config::seed_password().as_deref(),
+    )?;
+    std::fs::write(data_dir.join(RESCAN_FILE), [])
+        .context("Failed to mark the wallet for rescanning")?;
+
+    tracing::info!("Restored wallet from seed phrase");
+    Ok(())
+}
+
+/// Whether the wallet was restored and we haven't looked for its funds yet.
+///
+/// Only looks, [`init_wallet`] does the rescan when it creates the wallet.
+pub fn is_restored(data_dir: &Path) -> bool {
+    data_dir
+        .join(config::network().to_string())
+        .join(RESCAN_FILE)
+        .exists()
+}
+
 pub fn init_wallet(data_dir: &Path) -> Result<()> {
     tracing::debug!(?data_dir, "Wallet will be stored on disk");
     WALLET.set(Wallet::new(data_dir)?);
     Ok(())
 }
 
-pub async fn run_ldk() -> Result<BackgroundProcessor> {
+pub async fn run_ldk() -> Result<()> {
     let wallet = get_wallet();
     wallet.run_ldk().await
 }
 
-pub async fn run_ldk_server(address: SocketAddr) -> Result<(JoinHandle<()>, BackgroundProcessor)> {
+pub async fn run_ldk_server(address: SocketAddr) -> Result<()> {
     let wallet = get_wallet();
     wallet.run_ldk_server(address).await
 }
 
+/// Writes the Lightning node's state to disk.
+pub fn persist() -> Result<()> {
+    WALLET
+        .try_get()
+        .context("Wallet has not been initialised")?
+        .persist()
+}
+
+/// Stops the Lightning node and closes the database, nothing works afterwards.
+pub async fn shutdown() -> Result<()> {
+    WALLET
+        .try_get()
+        .context("Wallet has not been initialised")?
+        .shutdown()?;
+    db::close().await;
+
+    Ok(())
+}
+
 pub fn node_id() -> PublicKey {
     get_wallet().get_node_id()
 }
@@ -441,6 +596,31 @@ pub fn sync() -> Result<()> {
     get_wallet().sync()
 }
 
+/// Checks that one of our chain backends responds, trying them in order, and returns it.
+///
+/// Blocks for up to [`config::TCP_TIMEOUT`] per Electrum or Esplora server.
+pub fn ping_chain_backend() -> Result<chain::Backend> {
+    let mut errors = Vec::new();
+    for backend in config::chain_backends() {
+        match backend.ping() {
+            Ok(()) => return Ok(backend),
+            Err(e) => errors.push(format!("{backend}: {e:#}")),
+        }
+    }
+
+    bail!("No chain backend responded: {}", errors.join(", "))
+}
+
+/// Our chain backends in order and how they fared recently.
+pub fn chain_backend_health() -> Vec<(chain::Backend, chain::Health)> {
+    get_wallet().blockchain.health()
+}
+
+/// Whether the Lightning node is running.
+pub fn is_lightning_running() -> bool {
+    lightning::is_running()
+}
+
 pub fn network() -> bitcoin::Network {
     get_wallet().network()
 }
@@ -495,21 +675,8 @@ pub async fn send_lightning_payment(invoice: &str) -> Result<()> {
     Ok(())
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct OpenChannelRequest {
-    /// The taker address where the maker should send the funds to
-    pub address_to_fund: Address,
-
-    /// The amount that the taker expects for funding
-    ///
-    /// This represents the amount of the maker.
-    pub fund_amount: u64,
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-pub struct OpenChannelResponse {
-    pub funding_txid: Txid,
-}
+/// How long we wait for the maker to respond to a request over the Lightning connection.
+const MAKER_RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
 
 pub async fn open_channel(peer_info: PeerInfo, taker_amount: u64) -> Result<()> {
     let maker_amount = taker_amount * 2;
@@ -517,26 +684,25 @@ pub async fn open_channel(peer_info: PeerInfo, taker_amount: u64) -> Result<()>
 
     let address_to_fund = get_address()?;
 
-    let client = reqwest::Client::builder().timeout(TCP_TIMEOUT).build()?;
-
     let body = OpenChannelRequest {
         address_to_fund: address_to_fund.clone(),
         fund_amount: maker_amount,
+        taker_node_id: node_id(),
     };
 
-    let endpoint = maker_endpoint();
-    let maker_api = format!("{endpoint}/api/channel/open");
-
-    tracing::info!("Sending request to open channel to maker at: {maker_api}");
-
-    let response = client.post(maker_api).json(&body).send().await?;
+    tracing::info!("Sending request to open channel to maker {peer_info}");
 
-    if let Err(e) = response.error_for_status_ref() {
-        let text = response.text().await?;
-        bail!("maker was unable to open a channel due to {e}, {text}")
-    }
-
-    let response: OpenChannelResponse = response.json().await?;
+    let response = match request(peer_info.pubkey, |id| {
+        Message::OpenChannelRequest(Some(id), body)
+    })
+    .await?
+    {
+        Message::OpenChannelResponse(_, response) => response,
+        Message::Reject(_, problem) => {
+            return Err(anyhow::Error::new(problem).context("maker was unable to open a channel"))
+        }
+        message => bail!("Unexpected response from maker: {message:?}"),
+    };
 
     let maker_funding_txid = response.funding_txid;
 
@@ -574,20 +740,9 @@ pub async fn open_channel(peer_info: PeerInfo, taker_amount: u64) -> Result<()>
     lightning::open_channel(channel_manager, peer_info, channel_capacity, maker_amount).await
 }
 
-/// If the first channel is not usable, it might be because we've lost
-/// the connection with the 10101 maker, according to the
-/// `rust-lightning` logs.
-pub fn is_first_channel_usable() -> bool {
-    match get_first_channel_details() {
-        Some(channel_details) => channel_details.is_usable,
-        None => false,
-    }
-}
-
-pub fn get_first_channel_details() -> Option<ChannelDetails> {
-    let channel_manager = get_wallet().lightning.channel_manager.clone();
-
-    channel_manager.list_channels().first().cloned()
+/// All our channels, including those that are still being established.
+pub fn list_channels() -> Vec<ChannelDetails> {
+    get_wallet().lightning.channel_manager.list_channels()
 }
 
 pub async fn close_channel(remote_node_id: PublicKey, force: bool) -> Result<()> {
@@ -602,6 +757,86 @@ pub fn send_to_address(address: Address, amount: u64) -> Result<Txid> {
     get_wallet().send_to_address(address, amount)
 }
 
+/// Builds and signs a transaction sending the amount to the address, see [`broadcast`].
+pub fn build_transaction(address: Address, amount: u64) -> Result<Transaction> {
+    get_wallet().build_transaction(address, amount)
+}
+
+pub fn broadcast(tx: &Transaction) -> Result<()> {
+    get_wallet().lightning.wallet.broadcast(tx)?;
+    Ok(())
+}
+
+/// Sends a custom message to the given peer.
+pub fn send_custom_message(node_id: PublicKey, message: Message) {
+    let lightning = &get_wallet().lightning;
+    lightning.custom_message_handler.send(node_id, message);
+    lightning.peer_manager.process_events();
+}
+
+/// Sends a custom message to the given peer and waits for its response.
+///
+/// The message is built with a new request ID, only a response from the peer with the same ID
+/// counts.
+pub async fn request(
+    node_id: PublicKey,
+    message: impl FnOnce(RequestId) -> Message,
+) -> Result<Message> {
+    let peer_manager = get_peer_manager()?;
+    if !peer_manager.get_peer_node_ids().contains(&node_id) {
+        bail!("Not connected to {node_id}");
+    }
+
+    // Subscribe before sending, so we can't miss the response
+    let mut responses = subscribe_to_custom_messages();
+    let id = RequestId::random();
+    send_custom_message(node_id, message(id));
+
+    tokio::time::timeout(MAKER_RESPONSE_TIMEOUT, async {
+        loop {
+            match responses.recv().await {
+                Ok((sender, response))
+                    if sender == node_id && response.response_to() == Some(id) =>
+                {
+                    return Ok(response)
+                }
+                Ok(_) => continue,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    tracing::warn!(skipped, "Missed custom messages while waiting for response");
+                }
+                Err(broadcast::error::RecvError::Closed) => {
+                    bail!("Custom message handler went away")
+                }
+            }
+        }
+    })
+    .await
+    .with_context(|| format!("No response from {node_id} within {MAKER_RESPONSE_TIMEOUT:?}"))?
+}
+
+/// Lets the given filter decide whether we accept custom outputs proposed by our peers.
+pub fn set_custom_output_filter(filter: Arc<dyn CustomOutputFilter>) {
+    lightning::set_custom_output_filter(filter)
+}
+
+/// Subscribe to all custom messages from our peers, except for offers.
+pub fn subscribe_to_custom_messages() -> broadcast::Receiver<(PublicKey, Message)> {
+    get_wallet().lightning.custom_message_handler.subscribe()
+}
+
+/// Subscribe to the offers the makers broadcast over the Lightning connections.
+pub fn subscribe_to_offers() -> watch::Receiver<Offers> {
+    get_wallet()
+        .lightning
+        .custom_message_handler
+        .subscribe_to_offers()
+}
+
+/// The latest offer of each maker.
+pub fn get_offers() -> Offers {
+    get_wallet().lightning.custom_message_handler.offers()
+}
+
 pub fn get_node_info() -> NodeInfo {
     get_wallet().lightning.node_info()
 }
@@ -654,3 +889,30 @@ pub struct LightningTransaction {
     pub updated_timestamp: u64,
     pub expiry_timestamp: Option<u64>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_recorded_fingerprint() {
+        assert!(check_fingerprint(Some("abcd1234"), "abcd1234", false, true).is_ok());
+        assert!(check_fingerprint(Some("abcd1234\n"), "abcd1234", false, false).is_ok());
+    }
+
+    #[test]
+    fn rejects_other_fingerprint() {
+        assert!(check_fingerprint(Some("abcd1234"), "ffff0000", false, true).is_err());
+    }
+
+    #[test]
+    fn rejects_passphrase_for_seed_without_fingerprint() {
+        assert!(check_fingerprint(None, "abcd1234", true, true).is_err());
+        assert!(check_fingerprint(None, "abcd1234", true, false).is_ok());
+    }
+
+    #[test]
+    fn accepts_passphrase_for_new_seed() {
+        assert!(check_fingerprint(None, "abcd1234", false, true).is_ok());
+    }
+}